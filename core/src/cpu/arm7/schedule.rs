@@ -75,7 +75,7 @@ pub struct Schedule {
 }
 
 impl Schedule {
-    pub(super) fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Schedule {
             cur_time: Timestamp(0),
             target_time: Timestamp(0),
@@ -29,6 +29,7 @@ use crate::{
     emu::{Emu, Timestamp},
     utils::{mem_prelude::*, schedule::RawTimestamp, Bytes, Savestate},
 };
+use core::{mem, ops::Range};
 
 proc_bitfield::bitfield! {
     #[derive(Clone, Copy, PartialEq, Eq, Savestate)]
@@ -76,6 +77,53 @@ mod bounded {
 }
 pub use bounded::{RomOutputLen, RomOutputPos};
 
+// The only transfer lengths ROMCTRL can encode are 0, 4 and 0x200 << n bytes for n in 0..=5 (i.e.
+// 1 to 32 512-byte blocks).
+impl RomOutputLen {
+    /// Returns the transfer length selected by ROMCTRL's data block size field.
+    #[inline]
+    pub fn from_block_size_shift(shift: u8) -> Self {
+        RomOutputLen::new(match shift & 7 {
+            0 => 0,
+            7 => 4,
+            shift => 0x100 << shift,
+        })
+    }
+
+    /// Returns the transfer length for `count` 512-byte blocks, or `None` if ROMCTRL can't encode
+    /// it (`count` must be 0 or a power of two up to 32).
+    #[inline]
+    pub fn from_block_count(count: u16) -> Option<Self> {
+        if count == 0 || (count.is_power_of_two() && count <= 32) {
+            Some(RomOutputLen::new(count << 9))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the transfer length of `len` bytes, or `None` if ROMCTRL can't encode it.
+    #[inline]
+    pub fn from_bytes(len: u16) -> Option<Self> {
+        if len == 4 {
+            Some(RomOutputLen::new(4))
+        } else if len & 0x1FF == 0 {
+            Self::from_block_count(len >> 9)
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over the consecutive ranges of at most `chunk_len` bytes that make up
+    /// the first `self.get()` bytes of the ROM output buffer.
+    #[inline]
+    pub fn chunks(self, chunk_len: usize) -> impl Iterator<Item = Range<usize>> {
+        let len = self.get() as usize;
+        (0..len)
+            .step_by(chunk_len)
+            .map(move |start_i| start_i..(start_i + chunk_len).min(len))
+    }
+}
+
 #[derive(Savestate)]
 #[load(in_place_only)]
 pub struct DsSlot {
@@ -138,6 +186,38 @@ impl DsSlot {
         }
     }
 
+    /// Returns whether a cartridge is inserted, i.e. whether the ROM device isn't
+    /// [`rom::Empty`].
+    #[inline]
+    pub fn is_cart_inserted(&self) -> bool {
+        !matches!(self.rom, rom::Rom::Empty(_))
+    }
+
+    /// Ejects the inserted cartridge (if any), returning its ROM device; until another one is
+    /// inserted, commands will return an open bus and the chip ID will read as 0.
+    ///
+    /// NOTE: The card IRQ raised on removal isn't emulated, and a transfer in progress will still
+    /// return the data read before ejection.
+    pub fn eject_cart(&mut self, #[cfg(feature = "log")] logger: slog::Logger) -> rom::Rom {
+        mem::replace(
+            &mut self.rom,
+            rom::Rom::Empty(rom::Empty::new(
+                #[cfg(feature = "log")]
+                logger,
+            )),
+        )
+    }
+
+    /// Inserts a cartridge, replacing the current ROM device (which is returned) and setting it up
+    /// as the BIOS would find it on boot (i.e. still expecting the initial unencrypted commands).
+    ///
+    /// The result of setting up the new ROM is returned alongside the old one; if it failed, the
+    /// new ROM is left inserted anyway.
+    pub fn insert_cart(&mut self, mut rom: rom::Rom) -> (rom::Rom, Result<(), rom::SetupError>) {
+        let result = rom.setup(false);
+        (mem::replace(&mut self.rom, rom), result)
+    }
+
     #[inline]
     pub fn spi_control(&self) -> AuxSpiControl {
         self.spi_control
@@ -174,27 +254,28 @@ impl DsSlot {
         }
         self.rom_control.set_data_ready(false);
         self.rom_output_pos = RomOutputPos::new(0);
-        self.rom_output_len = RomOutputLen::new(match self.rom_control.data_block_size_shift() {
-            0 => 0,
-            7 => 4,
-            shift => 0x100 << shift,
-        });
+        self.rom_output_len =
+            RomOutputLen::from_block_size_shift(self.rom_control.data_block_size_shift());
+        let gaps = rom::normal::Key1Gaps {
+            gap1: self.rom_control.leading_gap_length(),
+            gap2: self.rom_control.first_block_byte_gap_length(),
+        };
+        self.rom.configure_key1_gaps(gaps);
         self.rom.handle_rom_command(
             self.rom_cmd.clone(),
             &mut self.rom_output_buffer,
             self.rom_output_len,
         );
-        // The command itself takes 8 CLK pulses to transfer, while every data byte takes 4 pulses
-        // (the DS game card slot can only transfer 8 bits on every CLK cycle)
-        let mut first_word_delay = 8 + (((self.rom_output_len.get() != 0) as u16) << 2);
-        if !self.rom_control.write_enabled() {
-            first_word_delay += self.rom_control.leading_gap_length();
-            if self.rom_output_len.get() != 0 {
-                first_word_delay += self.rom_control.first_block_byte_gap_length() as u16;
-            }
+        // The gaps are only waited for when reading from the cart, so writes use the bare transfer
+        // time
+        let first_word_delay = if self.rom_control.write_enabled() {
+            rom::normal::Key1Gaps::default()
+        } else {
+            gaps
         }
+        .first_word_latency(self.rom_output_len);
         let first_word_delay_cycles =
-            Timestamp((first_word_delay as u32 * self.rom_clk_pulse_duration) as RawTimestamp);
+            Timestamp((first_word_delay * self.rom_clk_pulse_duration) as RawTimestamp);
         if self.arm7_access {
             if self.rom_busy {
                 // NOTE: Not verified on hardware, only here to avoid locking up the whole emulator.
@@ -387,3 +468,116 @@ impl DsSlot {
         self.arm9_access = !arm7_access;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        utils::{zeroed_box, BoxedByteSlice},
+        Model,
+    };
+
+    #[test]
+    fn output_len_chunks() {
+        let len = RomOutputLen::from_block_count(1).unwrap();
+        assert_eq!(len.chunks(0x200).collect::<Vec<_>>(), [0..0x200]);
+        // The last chunk is cut short when the length isn't a multiple of the chunk size
+        assert_eq!(
+            len.chunks(0x180).collect::<Vec<_>>(),
+            [0..0x180, 0x180..0x200]
+        );
+        assert_eq!(
+            RomOutputLen::new(4).chunks(3).collect::<Vec<_>>(),
+            [0..3, 3..4]
+        );
+        assert_eq!(RomOutputLen::new(0).chunks(0x200).count(), 0);
+    }
+
+    #[test]
+    fn output_len_encoding() {
+        for (shift, len) in [
+            (0, 0),
+            (1, 0x200),
+            (4, 0x1000),
+            (6, 0x4000),
+            (7, 4),
+            (0xF, 4),
+        ] {
+            assert_eq!(RomOutputLen::from_block_size_shift(shift).get(), len);
+        }
+
+        for len in [0, 4, 0x200, 0x400, 0x800, 0x1000, 0x2000, 0x4000] {
+            assert_eq!(
+                RomOutputLen::from_bytes(len).map(RomOutputLen::get),
+                Some(len)
+            );
+        }
+        for len in [1, 8, 0x100, 0x204, 0x600, 0x3E00, 0x8000] {
+            assert_eq!(RomOutputLen::from_bytes(len).map(RomOutputLen::get), None);
+        }
+
+        assert_eq!(
+            RomOutputLen::from_block_count(32).map(RomOutputLen::get),
+            Some(0x4000)
+        );
+        for count in [3, 33, 64] {
+            assert_eq!(
+                RomOutputLen::from_block_count(count).map(RomOutputLen::get),
+                None
+            );
+        }
+    }
+
+    // Sends the unencrypted header command, returning the first 0x10 bytes of the response
+    fn read_header(ds_slot: &mut DsSlot) -> Vec<u8> {
+        let mut output = zeroed_box::<Bytes<0x4000>>();
+        ds_slot
+            .rom
+            .handle_rom_command(Bytes::new([0; 8]), &mut output, RomOutputLen::new(0x10));
+        output[..0x10].to_vec()
+    }
+
+    #[test]
+    fn eject_and_insert_cart() {
+        let mut contents = BoxedByteSlice::new_zeroed(0x1_0000);
+        contents[0xC..0x10].copy_from_slice(b"ABCE");
+        contents.write_le(0x20, 0x4000_u32);
+        let rom = rom::normal::Normal::new(
+            Box::new(contents),
+            None,
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+        let mut ds_slot = DsSlot::new(
+            rom::Rom::Normal(rom),
+            spi::Spi::Empty(spi::Empty::new(
+                #[cfg(feature = "log")]
+                slog::Logger::root(slog::Discard, slog::o!()),
+            )),
+            &mut arm7::Schedule::new(),
+            &mut arm9::Schedule::new(),
+        );
+        let chip_id = ds_slot.rom.chip_id();
+        assert!(ds_slot.is_cart_inserted());
+        assert_eq!(read_header(&mut ds_slot)[0xC..0x10], *b"ABCE");
+
+        // Without a cart, commands return an open bus
+        let ejected = ds_slot.eject_cart(
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        );
+        assert!(matches!(ejected, rom::Rom::Normal(_)));
+        assert!(!ds_slot.is_cart_inserted());
+        assert_eq!(read_header(&mut ds_slot), [0xFF; 0x10]);
+        assert_eq!(ds_slot.rom.chip_id(), 0);
+
+        let (prev_rom, result) = ds_slot.insert_cart(ejected);
+        assert!(matches!(prev_rom, rom::Rom::Empty(_)));
+        assert_eq!(result, Ok(()));
+        assert!(ds_slot.is_cart_inserted());
+        assert_eq!(read_header(&mut ds_slot)[0xC..0x10], *b"ABCE");
+        assert_eq!(ds_slot.rom.chip_id(), chip_id);
+    }
+}
@@ -1,62 +1,490 @@
+// Forwards the given `Contents` methods to `self.inner`, for decorators that only change the
+// behavior of some of them
+macro_rules! forward_contents_to_inner {
+    ($($method: ident),*) => {
+        $(forward_contents_to_inner!(@ $method);)*
+    };
+    (@ len) => {
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+    };
+    (@ actual_len) => {
+        fn actual_len(&self) -> usize {
+            self.inner.actual_len()
+        }
+    };
+    (@ game_code) => {
+        fn game_code(&self) -> u32 {
+            self.inner.game_code()
+        }
+    };
+    (@ secure_area_mut) => {
+        fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+            self.inner.secure_area_mut()
+        }
+    };
+    (@ secure_area) => {
+        fn secure_area(&mut self) -> Option<&[u8]> {
+            self.inner.secure_area()
+        }
+    };
+    (@ prefetch_secure_area) => {
+        fn prefetch_secure_area(&mut self) {
+            self.inner.prefetch_secure_area();
+        }
+    };
+    (@ dldi_area_mut) => {
+        fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+            self.inner.dldi_area_mut(addr, len)
+        }
+    };
+    (@ twl_secure_area_mut) => {
+        fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+            self.inner.twl_secure_area_mut()
+        }
+    };
+    (@ read_header) => {
+        fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+            self.inner.read_header(buf);
+        }
+    };
+}
+
+pub mod cached;
+pub mod callback;
+mod crc32;
+pub mod dump;
 mod empty;
-mod key1;
+pub mod key1;
+pub mod limited;
 pub use empty::Empty;
 pub mod header;
 pub mod icon;
 pub mod normal;
+pub mod patched;
+pub mod quirks;
+pub mod recorder;
+mod sha1;
+pub mod swapped;
+pub mod tracing;
+pub mod verify;
 
 use super::RomOutputLen;
 use crate::{
-    utils::{mem_prelude::*, BoxedByteSlice, Bytes, Savestate},
+    utils::{mem_prelude::*, zero, BoxedByteSlice, Bytes, Savestate},
     Model,
 };
+use core::{
+    any::Any,
+    array, mem,
+    ops::{Deref, DerefMut, RangeInclusive},
+};
+
+/// Reads `M` consecutive little-endian values of type `T` starting at `offset`.
+pub(crate) fn read_le_array<T: MemValue, const M: usize>(bytes: &[u8], offset: usize) -> [T; M] {
+    array::from_fn(|i| bytes.read_le::<T>(offset + i * mem::size_of::<T>()))
+}
 
 #[allow(clippy::len_without_is_empty)]
 pub trait Contents {
+    /// Returns the addressable size of the ROM, used to mask addresses. This is always a power of
+    /// two, and can be larger than [`actual_len`](Self::actual_len) for trimmed ROMs.
     fn len(&self) -> usize;
 
+    /// Returns the actual size of the ROM data, with reads past it returning padding.
+    fn actual_len(&self) -> usize {
+        self.len()
+    }
+
     fn game_code(&self) -> u32;
 
+    /// Returns the secure area for in-place decryption or encryption, or `None` if it can't be
+    /// read or the contents are read-only (see [`secure_area`](Self::secure_area)).
     fn secure_area_mut(&mut self) -> Option<&mut [u8]>;
+
+    /// Returns the secure area for reading only; read-only contents (i.e. memory-mapped files) can
+    /// implement this instead of [`secure_area_mut`](Self::secure_area_mut), in which case a copy
+    /// will be modified instead during setup.
+    fn secure_area(&mut self) -> Option<&[u8]> {
+        self.secure_area_mut().map(|secure_area| &*secure_area)
+    }
+
+    /// Loads the secure area ahead of time for contents that do so lazily on the first
+    /// [`secure_area_mut`](Self::secure_area_mut) call (which otherwise happens during boot),
+    /// i.e. so that it can be done on a loader thread; a no-op for in-memory contents.
+    fn prefetch_secure_area(&mut self) {}
+
     fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]>;
 
+    /// Returns the TWL secure area of DSi-enhanced carts (see [`twl_region`](Self::twl_region)),
+    /// like [`secure_area_mut`](Self::secure_area_mut) does for the NTR one; `None` for other
+    /// carts, or if the contents don't support it.
+    fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
     fn read_header(&mut self, buf: &mut Bytes<0x170>);
     fn read_slice(&mut self, addr: usize, output: &mut [u8]);
+
+    /// Returns the contents as [`Any`], so that embedders can get their own contents type back
+    /// from the `Box<dyn Contents>` owned by the ROM (i.e. to save its state along with the
+    /// emulator's); `None` for contents that don't opt into this.
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        None
+    }
+
+    /// Reads the whole first 0x200-byte sector of the ROM, i.e. the header followed by the padding
+    /// that the BIOS reads along with it (ROMs are always at least 0x200 bytes long).
+    fn read_header_sector(&mut self, buf: &mut Bytes<0x200>) {
+        self.read_slice(0, &mut **buf);
+    }
+
+    /// Calls `f` with the offset and data of each consecutive `block_len`-byte block of the ROM
+    /// data, up to [`actual_len`](Self::actual_len); the last block may be shorter. A single buffer
+    /// is read into for all blocks.
+    ///
+    /// # Panics
+    /// Panics if `block_len` is 0.
+    fn for_each_block(&mut self, block_len: usize, f: &mut dyn FnMut(usize, &[u8])) {
+        let len = self.actual_len();
+        for_each_block_until(self, len, block_len, f);
+    }
+
+    /// Returns the SHA-1 hash of the ROM data up to [`actual_len`](Self::actual_len), i.e. of the
+    /// trimmed dump, as used by No-Intro.
+    fn hash_sha1(&mut self) -> [u8; 20] {
+        let mut hasher = sha1::Sha1::new();
+        self.for_each_block(HASH_BLOCK_LEN, &mut |_, block| hasher.update(block));
+        hasher.finish()
+    }
+
+    /// Returns the SHA-1 hash of the whole addressable range up to [`len`](Self::len), including
+    /// the padding returned past the end of trimmed ROMs.
+    fn hash_padded_sha1(&mut self) -> [u8; 20] {
+        let mut hasher = sha1::Sha1::new();
+        let len = self.len();
+        for_each_block_until(self, len, HASH_BLOCK_LEN, &mut |_, block| {
+            hasher.update(block);
+        });
+        hasher.finish()
+    }
+
+    /// Returns the CRC-32 (as used by zip archives) of the ROM data up to
+    /// [`actual_len`](Self::actual_len), a cheaper identifier than [`hash_sha1`](Self::hash_sha1)
+    /// for i.e. caching banners.
+    fn crc32(&mut self) -> u32 {
+        let mut crc = crc32::Crc32::new();
+        self.for_each_block(HASH_BLOCK_LEN, &mut |_, block| crc.update(block));
+        crc.finish()
+    }
+
+    /// Returns whether the CRC16 of 0x4000..0x8000 matches the one stored in `header`; as it's
+    /// calculated over the encrypted secure area, this will fail once it's been decrypted in place
+    /// for direct boot (or if it was re-encrypted incorrectly).
+    fn verify_secure_area_crc(&mut self, header: &header::Header) -> bool {
+        if self.actual_len() < 0x8000 {
+            return false;
+        }
+        let mut crc = header::Crc16::new(0xFFFF);
+        let mut buf = [0; 0x800];
+        for addr in (0x4000..0x8000).step_by(buf.len()) {
+            self.read_slice(addr, &mut buf);
+            crc.update(&buf);
+        }
+        crc.finish() == header.secure_area_crc()
+    }
+
+    /// Reads the header's RSA-SHA1 signature, returning `None` if the ROM is too small to contain
+    /// one or the dump didn't retain it (i.e. the area is blank).
+    fn read_rsa_signature(&mut self) -> Option<Bytes<{ header::RSA_SIGNATURE_LEN }>> {
+        if self.actual_len() < header::RSA_SIGNATURE_START + header::RSA_SIGNATURE_LEN {
+            return None;
+        }
+        let mut signature = Bytes::new([0; header::RSA_SIGNATURE_LEN]);
+        self.read_slice(header::RSA_SIGNATURE_START, &mut *signature);
+        let is_blank = signature.iter().all(|&b| b == 0) || signature.iter().all(|&b| b == 0xFF);
+        (!is_blank).then_some(signature)
+    }
+
+    /// Reads the first 0x400 bytes of the ROM, containing both the regular header and the extended
+    /// one, returning `None` if the cart isn't DSi-enhanced or is too small to contain it (see
+    /// [`header::ExtendedHeader`]).
+    fn read_extended_header(&mut self) -> Option<Bytes<0x400>> {
+        if self.actual_len() < 0x400 {
+            return None;
+        }
+        let mut bytes = Bytes::new([0; 0x400]);
+        self.read_slice(0, &mut *bytes);
+        header::ExtendedHeader::parse(&bytes)?;
+        Some(bytes)
+    }
+
+    /// Returns the sizes of the `public.sav` and `private.sav` files declared in the extended
+    /// header, in that order, or `None` for carts without one.
+    fn dsi_save_sizes(&mut self) -> Option<(u32, u32)> {
+        let bytes = self.read_extended_header()?;
+        let header = header::ExtendedHeader::parse(&bytes)?;
+        Some((header.public_save_size(), header.private_save_size()))
+    }
+
+    /// Returns the capacity reported by the header if it doesn't match the addressable size of
+    /// the ROM (trimmed dumps still match, as they're addressed as the full-size cart), which
+    /// usually means that the dump is bad or overdumped.
+    fn capacity_mismatch(&mut self) -> Option<u64> {
+        let mut header_bytes = zero();
+        self.read_header(&mut header_bytes);
+        let reported_len = header::Header::parse(&header_bytes)?.device_capacity_bytes();
+        (reported_len != self.len() as u64).then_some(reported_len)
+    }
+
+    /// Reads the entries of the given overlay table (see [`header::Header::arm9_overlay_table`]
+    /// and [`header::Header::arm7_overlay_table`]), stopping at the end of the ROM data.
+    fn read_overlay_table(&mut self, table: header::OverlayTableInfo) -> Vec<header::OverlayEntry> {
+        let start = table.rom_offset as usize;
+        let len =
+            (table.len() * header::OverlayEntry::SIZE).min(self.actual_len().saturating_sub(start));
+        if len == 0 {
+            return Vec::new();
+        }
+        let mut bytes = BoxedByteSlice::new_zeroed(len);
+        self.read_slice(start, &mut bytes);
+        header::OverlayEntry::iter_table(&bytes).collect()
+    }
+
+    /// Returns the layout of the DSi-specific (TWL) region of the ROM as declared by the extended
+    /// header, or `None` for carts that aren't DSi-enhanced (see [`header::TwlRegionInfo`]); reads
+    /// of both regions go through [`read_slice`](Self::read_slice) with the same flat addressing.
+    fn twl_region(&mut self) -> Option<header::TwlRegionInfo> {
+        let bytes = self.read_extended_header()?;
+        header::ExtendedHeader::parse(&bytes)?.twl_region()
+    }
+}
+
+const HASH_BLOCK_LEN: usize = 0x1_0000;
+
+fn for_each_block_until<C: Contents + ?Sized>(
+    contents: &mut C,
+    len: usize,
+    block_len: usize,
+    f: &mut dyn FnMut(usize, &[u8]),
+) {
+    assert!(block_len != 0, "ROM blocks can't be empty");
+    let mut buf = vec![0; block_len.min(len)];
+    for start in (0..len).step_by(block_len) {
+        let block = &mut buf[..block_len.min(len - start)];
+        contents.read_slice(start, block);
+        f(start, block);
+    }
+}
+
+/// Copies the part of `overlay` (covering the ROM range starting at `start`) that overlaps a read
+/// of `output.len()` bytes at `addr` over the data that was read.
+pub fn apply_overlay(start: usize, overlay: &[u8], addr: usize, output: &mut [u8]) {
+    let end = start + overlay.len();
+    let end_addr = addr + output.len();
+    if addr < end && end_addr > start {
+        let copy_start = addr.max(start);
+        let copy_end = end_addr.min(end);
+        output[copy_start - addr..copy_end - addr]
+            .copy_from_slice(&overlay[copy_start - start..copy_end - start]);
+    }
+}
+
+pub(crate) fn new_secure_area_buf() -> Box<Bytes<0x800>> {
+    unsafe { Box::<Bytes<0x800>>::new_zeroed().assume_init() }
+}
+
+/// The in-memory copies of the secure area and DLDI area kept by contents that can't modify their
+/// data in place (i.e. file-backed, callback-backed or transformed ones), so that they can be
+/// handed out by [`Contents::secure_area_mut`] and [`Contents::dldi_area_mut`]; each is loaded on
+/// first access and then applied over all reads through [`apply`](Self::apply).
+///
+/// A secure area only loaded through [`secure_area`](Self::secure_area) (i.e. because setup found
+/// it didn't need to be modified) isn't applied, so reads keep going to the underlying data.
+#[derive(Default)]
+pub struct Overlays {
+    secure_area_start: usize,
+    secure_area: Option<Box<Bytes<0x800>>>,
+    secure_area_modified: bool,
+    dldi_area_start: usize,
+    dldi_area: Option<BoxedByteSlice>,
+    twl_secure_area_start: usize,
+    twl_secure_area: Option<Box<Bytes<0x800>>>,
 }
 
-impl Contents for BoxedByteSlice {
-    fn len(&self) -> usize {
-        (**self).len()
+impl Overlays {
+    pub fn new(secure_area_start: usize) -> Self {
+        Overlays {
+            secure_area_start,
+            ..Default::default()
+        }
     }
 
-    fn game_code(&self) -> u32 {
-        self.read_le::<u32>(0xC)
+    fn load_secure_area(&mut self, read: impl FnOnce(usize, &mut [u8])) -> &mut Bytes<0x800> {
+        let start = self.secure_area_start;
+        self.secure_area.get_or_insert_with(|| {
+            let mut buf = new_secure_area_buf();
+            read(start, &mut **buf);
+            buf
+        })
     }
 
-    fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
-        let arm9_rom_offset = self.read_le::<u32>(0x20) as usize;
-        self.get_mut(arm9_rom_offset..arm9_rom_offset + 0x800)
+    /// Returns the copy of the secure area for reading only, first reading it through
+    /// `read(addr, output)` if it wasn't loaded yet; it's only applied over reads once it's been
+    /// accessed through [`secure_area_mut`](Self::secure_area_mut).
+    pub fn secure_area(&mut self, read: impl FnOnce(usize, &mut [u8])) -> &[u8] {
+        self.load_secure_area(read).as_slice()
     }
 
-    fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
-        self.get_mut(addr..addr + len)
+    /// Returns the copy of the secure area, first reading it through `read(addr, output)` if it
+    /// wasn't loaded yet.
+    pub fn secure_area_mut(&mut self, read: impl FnOnce(usize, &mut [u8])) -> &mut [u8] {
+        self.secure_area_modified = true;
+        self.load_secure_area(read).as_mut_slice()
     }
 
-    fn read_header(&mut self, buf: &mut Bytes<0x170>) {
-        buf.copy_from_slice(&self[..0x170]);
+    /// Returns the copy of the DLDI area, first reading `len` bytes at `addr` through
+    /// `read(addr, output)` if it wasn't loaded yet (the range is fixed by the first call).
+    pub fn dldi_area_mut(
+        &mut self,
+        addr: usize,
+        len: usize,
+        read: impl FnOnce(usize, &mut [u8]),
+    ) -> &mut [u8] {
+        if self.dldi_area.is_none() {
+            let mut buf = BoxedByteSlice::new_zeroed(len);
+            read(addr, &mut buf);
+            self.dldi_area_start = addr;
+            self.dldi_area = Some(buf);
+        }
+        self.dldi_area.as_deref_mut().unwrap()
+    }
+
+    /// Returns the copy of the TWL secure area, first reading it at `addr` through
+    /// `read(addr, output)` if it wasn't loaded yet (the address is fixed by the first call).
+    pub fn twl_secure_area_mut(
+        &mut self,
+        addr: usize,
+        read: impl FnOnce(usize, &mut [u8]),
+    ) -> &mut [u8] {
+        if self.twl_secure_area.is_none() {
+            let mut buf = new_secure_area_buf();
+            read(addr, &mut **buf);
+            self.twl_secure_area_start = addr;
+            self.twl_secure_area = Some(buf);
+        }
+        self.twl_secure_area.as_mut().unwrap().as_mut_slice()
+    }
+
+    /// Returns the combined size of the overlays loaded so far.
+    pub fn loaded_len(&self) -> usize {
+        self.secure_area.as_ref().map_or(0, |_| 0x800)
+            + self.dldi_area.as_deref().map_or(0, <[u8]>::len)
+            + self.twl_secure_area.as_ref().map_or(0, |_| 0x800)
+    }
+
+    /// Applies the loaded overlays over `output`, which was read from the underlying data starting
+    /// at `addr`.
+    pub fn apply(&self, addr: usize, output: &mut [u8]) {
+        if let Some(secure_area) = self
+            .secure_area
+            .as_ref()
+            .filter(|_| self.secure_area_modified)
+        {
+            apply_overlay(self.secure_area_start, &secure_area[..], addr, output);
+        }
+        if let Some(dldi_area) = &self.dldi_area {
+            apply_overlay(self.dldi_area_start, dldi_area, addr, output);
+        }
+        if let Some(twl_secure_area) = &self.twl_secure_area {
+            apply_overlay(
+                self.twl_secure_area_start,
+                &twl_secure_area[..],
+                addr,
+                output,
+            );
+        }
+    }
+}
+
+/// In-memory ROM contents borrowed from an existing buffer, for embedders that already have the
+/// whole ROM loaded somewhere else.
+pub struct SliceContents<'a>(pub &'a mut [u8]);
+
+impl Deref for SliceContents<'_> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.0
     }
+}
 
-    fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
-        let end_addr = addr + output.len();
-        output.copy_from_slice(&self[addr..end_addr]);
+impl DerefMut for SliceContents<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.0
     }
 }
 
+// The secure area and DLDI overlays are applied in place for in-memory contents
+macro_rules! impl_in_memory_contents {
+    ($($ty: ty),*) => {
+        $(
+            impl Contents for $ty {
+                fn len(&self) -> usize {
+                    (**self).len()
+                }
+
+                fn game_code(&self) -> u32 {
+                    header::Header::new(self).map_or(0, |header| header.game_code().0)
+                }
+
+                fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+                    let arm9_rom_offset = header::Header::new(self)?.arm9_rom_offset() as usize;
+                    self.get_mut(arm9_rom_offset..arm9_rom_offset + 0x800)
+                }
+
+                fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+                    self.get_mut(addr..addr + len)
+                }
+
+                fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+                    let start = self.twl_region()?.secure_area_start;
+                    self.get_mut(start..start + 0x800)
+                }
+
+                fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+                    buf.copy_from_slice(&self[..0x170]);
+                }
+
+                fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+                    let end_addr = addr + output.len();
+                    output.copy_from_slice(&self[addr..end_addr]);
+                }
+            }
+        )*
+    };
+}
+
+impl_in_memory_contents!(BoxedByteSlice, SliceContents<'_>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetupError {
+    /// The secure area needs to be decrypted for direct boot (or encrypted to boot from the
+    /// firmware), but no ARM7 BIOS was provided to initialize the KEY1 tables.
+    MissingArm7Bios,
+    /// The secure area is cut off by the end of the ROM data, so it can't be decrypted or
+    /// encrypted (ROMs ending before it starts boot as-is).
+    InvalidSecureArea,
+}
+
 trait RomDevice {
     fn read(&mut self, addr: u32, output: &mut [u8]);
     fn read_header(&mut self, buf: &mut Bytes<0x170>);
     fn chip_id(&self) -> u32;
-    fn setup(&mut self, direct_boot: bool) -> Result<(), ()>;
+    fn setup(&mut self, direct_boot: bool) -> Result<(), SetupError>;
     fn handle_rom_command(
         &mut self,
         cmd: Bytes<8>,
@@ -81,11 +509,16 @@ impl Rom {
         forward_to_variants!(Rom; Normal, Empty; self, read_header(buf));
     }
 
+    /// Reads the whole first 0x200-byte sector of the ROM, see [`Contents::read_header_sector`].
+    pub fn read_header_sector(&mut self, buf: &mut Bytes<0x200>) {
+        self.read(0, &mut **buf);
+    }
+
     pub fn chip_id(&self) -> u32 {
         forward_to_variants!(Rom; Normal, Empty; self, chip_id())
     }
 
-    pub(crate) fn setup(&mut self, direct_boot: bool) -> Result<(), ()> {
+    pub(crate) fn setup(&mut self, direct_boot: bool) -> Result<(), SetupError> {
         forward_to_variants!(Rom; Normal, Empty; self, setup(direct_boot))
     }
 
@@ -98,6 +531,33 @@ impl Rom {
         forward_to_variants!(Rom; Normal, Empty; self, handle_rom_command(cmd, output, output_len));
     }
 
+    pub fn configure_key1_gaps(&mut self, gaps: normal::Key1Gaps) {
+        if let Rom::Normal(rom) = self {
+            rom.configure_key1_gaps(gaps);
+        }
+    }
+
+    pub fn requires_arm7_bios_for_boot(&mut self) -> bool {
+        match self {
+            Rom::Normal(rom) => rom.requires_arm7_bios_for_boot(),
+            Rom::Empty(_) => false,
+        }
+    }
+
+    pub fn arm7_bios_matches_cart(&mut self) -> Option<bool> {
+        match self {
+            Rom::Normal(rom) => rom.arm7_bios_matches_cart(),
+            Rom::Empty(_) => None,
+        }
+    }
+
+    pub fn secure_area_decryption_failed(&self) -> bool {
+        match self {
+            Rom::Normal(rom) => rom.secure_area_decryption_failed(),
+            Rom::Empty(_) => false,
+        }
+    }
+
     pub fn into_contents(self) -> Option<Box<dyn Contents>> {
         match self {
             Rom::Normal(rom) => Some(rom.into_contents()),
@@ -115,6 +575,15 @@ impl Rom {
 
 impl_from_variants!(Rom; Normal, Empty; normal::Normal, Empty);
 
+/// Returns whether the ROM is homebrew, going by its ARM9 binary not starting within the secure
+/// area range (0x4000..0x8000) used by retail carts; homebrew has no secure area to decrypt.
+pub fn is_homebrew(contents: &mut (impl Contents + ?Sized)) -> bool {
+    let mut header_bytes = zero();
+    contents.read_header(&mut header_bytes);
+    header::Header::parse(&header_bytes)
+        .is_some_and(|header| !(0x4000..0x8000).contains(&header.arm9_rom_offset()))
+}
+
 pub fn min_size_for_model(model: Model) -> usize {
     match model {
         Model::Ds | Model::Lite | Model::Ique | Model::IqueLite => 0x200,
@@ -122,6 +591,553 @@ pub fn min_size_for_model(model: Model) -> usize {
     }
 }
 
+// ROM addresses are 32-bit, so no larger ROMs can be fully addressed
+pub const MAX_SIZE: usize = (if usize::BITS > 32 {
+    1_u64 << 32
+} else {
+    1_u64 << (usize::BITS - 1)
+}) as usize;
+
+/// Returns the range of ROM sizes that can be valid for the given model; only the powers of two
+/// within it are accepted by [`is_valid_size`].
+pub fn size_bounds(model: Model) -> RangeInclusive<usize> {
+    min_size_for_model(model)..=MAX_SIZE
+}
+
 pub fn is_valid_size(len: usize, model: Model) -> bool {
-    len.is_power_of_two() && len >= min_size_for_model(model)
+    len.is_power_of_two() && size_bounds(model).contains(&len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        callback::CallbackContents,
+        header::{self, TwlRegionInfo},
+        normal::Normal,
+        read_le_array,
+        sha1::Sha1,
+        size_bounds, Contents, Overlays, Rom, RomDevice, SliceContents, MAX_SIZE,
+    };
+    use crate::{
+        cpu::arm7,
+        utils::{mem_prelude::*, zeroed_box, BoxedByteSlice, Bytes},
+        Model,
+    };
+
+    // Returns an ARM7 BIOS holding a synthetic level 0 KEY1 table at 0x30 (as the real one can't
+    // be distributed), for tests that need the secure area to be decrypted or encrypted
+    pub(super) fn test_arm7_bios() -> Box<Bytes<{ arm7::BIOS_SIZE }>> {
+        let mut bios = zeroed_box::<Bytes<{ arm7::BIOS_SIZE }>>();
+        for i in 0..0x412 {
+            bios.write_le(
+                0x30 + i * 4,
+                (i as u32).wrapping_mul(0x9E37_79B9) ^ 0x5A5A_A5A5,
+            );
+        }
+        bios
+    }
+
+    // In-memory contents for a trimmed ROM, padded with zeros up to the next power of two
+    pub(super) struct TrimmedContents(pub(super) BoxedByteSlice);
+
+    impl Contents for TrimmedContents {
+        fn len(&self) -> usize {
+            self.0.len().next_power_of_two()
+        }
+
+        fn actual_len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn game_code(&self) -> u32 {
+            self.0.game_code()
+        }
+
+        fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+            self.0.secure_area_mut()
+        }
+
+        fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+            self.0.dldi_area_mut(addr, len)
+        }
+
+        fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+            self.0.read_header(buf);
+        }
+
+        fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+            let read_len = self.0.len().saturating_sub(addr).min(output.len());
+            let (data, padding) = output.split_at_mut(read_len);
+            if !data.is_empty() {
+                self.0.read_slice(addr, data);
+            }
+            padding.fill(0);
+        }
+    }
+
+    #[test]
+    fn overlays_are_applied_over_reads() {
+        let data: Vec<u8> = (0..0x10000).map(|i| i as u8).collect();
+        let read = |addr: usize, output: &mut [u8]| {
+            output.copy_from_slice(&data[addr..addr + output.len()]);
+        };
+        let mut overlays = Overlays::new(0x4000);
+
+        // Nothing is applied before the overlays are loaded
+        let mut output = [0; 0x10];
+        read(0x3FF8, &mut output);
+        overlays.apply(0x3FF8, &mut output);
+        assert_eq!(output[..], data[0x3FF8..0x4008]);
+
+        let secure_area = overlays.secure_area_mut(read);
+        assert_eq!(secure_area[..], data[0x4000..0x4800]);
+        secure_area.fill(0xAA);
+        overlays.dldi_area_mut(0x8000, 4, read).fill(0xBB);
+
+        read(0x3FF8, &mut output);
+        overlays.apply(0x3FF8, &mut output);
+        assert_eq!(output[..8], data[0x3FF8..0x4000]);
+        assert!(output[8..].iter().all(|&byte| byte == 0xAA));
+
+        read(0x7FFE, &mut output);
+        overlays.apply(0x7FFE, &mut output);
+        assert_eq!(output[..2], data[0x7FFE..0x8000]);
+        assert_eq!(output[2..6], [0xBB; 4]);
+        assert_eq!(output[6..], data[0x8004..0x800E]);
+
+        // Once loaded, the overlays aren't read again
+        let secure_area = overlays.secure_area_mut(|_, _| panic!("secure area reloaded"));
+        assert_eq!(secure_area[0], 0xAA);
+    }
+
+    #[test]
+    fn non_power_of_two_len() {
+        let mut contents = CallbackContents::new(0x5000, |_, output: &mut [u8]| output.fill(0x55));
+        assert_eq!(contents.len(), 0x8000);
+        assert_eq!(contents.actual_len(), 0x5000);
+
+        // Reads within the addressable size but past the actual data return padding
+        let mut output = [0xFF; 0x10];
+        contents.read_slice(0x4FF8, &mut output);
+        assert_eq!(output[..8], [0x55; 8]);
+        assert_eq!(output[8..], [0; 8]);
+
+        // The addressable size is what ROM addresses are masked with
+        let mut rom = Normal::new(
+            Box::new(contents),
+            None,
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+        assert_eq!(rom.contents().actual_len(), 0x5000);
+        rom.read(0x8000 + 0x4FF8, &mut output);
+        assert_eq!(output[..8], [0x55; 8]);
+        assert_eq!(output[8..], [0; 8]);
+    }
+
+    #[test]
+    fn overlay_copy_at_nonzero_offset() {
+        let mut overlays = Overlays::new(0x4000);
+        overlays.secure_area_mut(|_, output| output.fill(0xAA));
+        // Reads starting just before the overlay, covering it whole, and starting inside it and
+        // running past its end
+        for (addr, len, overlay_range) in [
+            (0x3FFC, 0x10, 4..0x10),
+            (0x3F00, 0x1000, 0x100..0x900),
+            (0x47F8, 0x10, 0..8),
+        ] {
+            let mut output = vec![0x55; len];
+            overlays.apply(addr, &mut output);
+            for (i, &byte) in output.iter().enumerate() {
+                let expected = if overlay_range.contains(&i) {
+                    0xAA
+                } else {
+                    0x55
+                };
+                assert_eq!(
+                    byte, expected,
+                    "read of {len:#X} bytes at {addr:#X}, byte {i:#X}"
+                );
+            }
+        }
+    }
+
+    // Returns a DSi-enhanced ROM whose 1 MiB NTR region is directly followed by the TWL one, with
+    // the DSi ARM9 binary (and so the TWL secure area) 0x3000 bytes into it
+    fn hybrid_rom() -> BoxedByteSlice {
+        let mut rom = BoxedByteSlice::new_zeroed(0x20_0000);
+        rom[0xC..0x10].copy_from_slice(b"ABCE");
+        rom.write_le::<u32>(0x20, 0x4000);
+        rom[0x12] = 2;
+        rom.write_le::<u16>(0x90, 2);
+        rom.write_le::<u16>(0x92, 2);
+        rom.write_le::<u32>(0x1C0, 0x10_3000);
+        rom[0x4000..0x4008].copy_from_slice(b"NTRsecar");
+        rom[0x10_3000..0x10_3008].copy_from_slice(b"TWLsecar");
+        rom
+    }
+
+    #[test]
+    fn twl_region_of_hybrid_rom() {
+        let mut rom = hybrid_rom();
+        assert_eq!(
+            rom.twl_region(),
+            Some(TwlRegionInfo {
+                ntr_region_end: 0x10_0000,
+                twl_region_start: 0x10_0000,
+                secure_area_start: 0x10_3000,
+            })
+        );
+        assert_eq!(rom.secure_area_mut().unwrap()[..8], *b"NTRsecar");
+        assert_eq!(rom.twl_secure_area_mut().unwrap()[..8], *b"TWLsecar");
+
+        // Without a DSi ARM9 binary offset, the TWL secure area is assumed to start the TWL region
+        rom.write_le::<u32>(0x1C0, 0);
+        assert_eq!(
+            rom.twl_region()
+                .map(|twl_region| twl_region.secure_area_start),
+            Some(0x10_0000)
+        );
+
+        // DS-only carts have no TWL region, even if the fields aren't zeroed
+        rom[0x12] = 0;
+        assert_eq!(rom.twl_region(), None);
+        assert_eq!(rom.twl_secure_area_mut(), None);
+    }
+
+    #[test]
+    fn twl_secure_area_overlay() {
+        let rom = hybrid_rom();
+        let mut contents = CallbackContents::new(rom.len(), |addr, output: &mut [u8]| {
+            output.copy_from_slice(&rom[addr..addr + output.len()]);
+        });
+        let twl_secure_area = contents.twl_secure_area_mut().unwrap();
+        assert_eq!(twl_secure_area[..8], *b"TWLsecar");
+        twl_secure_area.fill(0xAA);
+
+        let mut output = [0; 0x10];
+        contents.read_slice(0x10_2FF8, &mut output);
+        assert_eq!(output[..8], [0; 8]);
+        assert_eq!(output[8..], [0xAA; 8]);
+        contents.read_slice(0x4000, &mut output[..8]);
+        assert_eq!(output[..8], *b"NTRsecar");
+    }
+
+    #[test]
+    fn slice_contents() {
+        // Normal needs `'static` contents, so the buffer is leaked
+        let data = vec![0; 0x8000].leak();
+        for (i, byte) in data.iter_mut().enumerate().skip(0x200) {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        data.write_le::<u32>(0x20, 0x4000);
+        let expected = data.to_vec();
+        let mut contents = SliceContents(data);
+        assert_eq!(contents.len(), 0x8000);
+
+        let mut output = [0; 0x10];
+        contents.read_slice(0x7FF0, &mut output);
+        assert_eq!(output[..], expected[0x7FF0..]);
+
+        // The secure area is modified in place in the borrowed buffer
+        contents.secure_area_mut().unwrap()[..8].fill(0xAA);
+        assert_eq!(contents.0[0x4000..0x4008], [0xAA; 8]);
+        contents.read_slice(0x3FF8, &mut output);
+        assert_eq!(output[..8], expected[0x3FF8..0x4000]);
+        assert_eq!(output[8..], [0xAA; 8]);
+
+        // Reads past the end wrap around to the start of the ROM
+        let mut rom = Normal::new(
+            Box::new(contents),
+            None,
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+        rom.read(0x7FF8, &mut output);
+        assert_eq!(output[..8], expected[0x7FF8..]);
+        assert_eq!(output[8..], expected[..8]);
+    }
+
+    #[test]
+    fn le_arrays() {
+        let bytes: Vec<u8> = (0..0x20).collect();
+        assert_eq!(read_le_array::<u16, 3>(&bytes, 0), [0x0100, 0x0302, 0x0504]);
+        // Offsets don't need to be aligned to the value size
+        assert_eq!(read_le_array::<u16, 2>(&bytes, 7), [0x0807, 0x0A09]);
+        assert_eq!(
+            read_le_array::<u32, 2>(&bytes, 0x10),
+            [0x1312_1110, 0x1716_1514]
+        );
+        assert_eq!(read_le_array::<u32, 1>(&bytes, 0x1B), [0x1E1D_1C1B]);
+        // The last value can end right at the end of the buffer
+        assert_eq!(
+            read_le_array::<u32, 2>(&bytes, 0x18),
+            [0x1B1A_1918, 0x1F1E_1D1C]
+        );
+        assert_eq!(read_le_array::<u16, 0>(&bytes, 0x20), []);
+    }
+
+    #[test]
+    fn for_each_block_covers_contents() {
+        let mut contents = BoxedByteSlice::new_zeroed(0x4000);
+        for (i, byte) in contents.iter_mut().enumerate() {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        let expected = contents.to_vec();
+
+        for block_len in [0x1000, 0x300, 0x4000, 0x8000] {
+            let mut copy = Vec::new();
+            contents.for_each_block(block_len, &mut |start, block| {
+                // Blocks are consecutive, so they neither overlap nor leave gaps
+                assert_eq!(start, copy.len());
+                assert!(block.len() <= block_len);
+                copy.extend_from_slice(block);
+            });
+            assert_eq!(copy, expected);
+        }
+    }
+
+    #[test]
+    fn for_each_block_reuses_buffer() {
+        let mut contents = TrimmedContents(BoxedByteSlice::new_zeroed(0x2100));
+        let mut blocks = Vec::new();
+        contents.for_each_block(0x800, &mut |start, block| {
+            blocks.push((start, block.as_ptr(), block.len()));
+        });
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|&(start, _, len)| (start, len))
+                .collect::<Vec<_>>(),
+            [
+                (0, 0x800),
+                (0x800, 0x800),
+                (0x1000, 0x800),
+                (0x1800, 0x800),
+                (0x2000, 0x100)
+            ]
+        );
+        assert!(blocks.iter().all(|&(_, ptr, _)| ptr == blocks[0].1));
+    }
+
+    #[test]
+    #[should_panic(expected = "can't be empty")]
+    fn for_each_block_rejects_empty_blocks() {
+        BoxedByteSlice::new_zeroed(0x200).for_each_block(0, &mut |_, _| {});
+    }
+
+    #[test]
+    fn model_size_bounds() {
+        for model in [Model::Ds, Model::Lite, Model::Ique, Model::IqueLite] {
+            assert_eq!(size_bounds(model), 0x200..=MAX_SIZE);
+        }
+        assert_eq!(size_bounds(Model::Dsi), 0x1000..=MAX_SIZE);
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(MAX_SIZE, 1 << 32);
+    }
+
+    #[test]
+    fn trimmed_and_padded_sha1() {
+        let sha1 = |data: &[u8]| {
+            let mut hasher = Sha1::new();
+            hasher.update(data);
+            hasher.finish()
+        };
+        let mut data = BoxedByteSlice::new_zeroed(0x3000);
+        data.fill(0x55);
+
+        let mut padded = data.to_vec();
+        padded.resize(0x4000, 0);
+        let mut trimmed = TrimmedContents(data.clone());
+        assert_eq!(trimmed.hash_sha1(), sha1(&data));
+        assert_eq!(trimmed.hash_padded_sha1(), sha1(&padded));
+        assert_ne!(trimmed.hash_sha1(), trimmed.hash_padded_sha1());
+
+        // Both hashes cover the same data for ROMs that aren't trimmed
+        let mut untrimmed = BoxedByteSlice::new_zeroed(0x4000);
+        untrimmed.copy_from_slice(&padded);
+        assert_eq!(untrimmed.hash_sha1(), untrimmed.hash_padded_sha1());
+    }
+
+    #[test]
+    fn rsa_signature() {
+        let mut contents = BoxedByteSlice::new_zeroed(0x2000);
+        assert!(contents.read_rsa_signature().is_none());
+        for (i, byte) in contents[0xF80..0x1000].iter_mut().enumerate() {
+            *byte = i as u8 ^ 0xA5;
+        }
+        let signature = contents.read_rsa_signature().unwrap();
+        assert_eq!(signature[..], contents[0xF80..0x1000]);
+
+        // Areas filled with 0xFF are blank too
+        contents[0xF80..0x1000].fill(0xFF);
+        assert!(contents.read_rsa_signature().is_none());
+
+        // ROMs ending before the end of the signature don't have one
+        let mut short = BoxedByteSlice::new_zeroed(0x800);
+        short.fill(0xA5);
+        assert!(short.read_rsa_signature().is_none());
+        assert_eq!(header::RSA_SIGNATURE_START, 0xF80);
+    }
+
+    #[test]
+    fn extended_header_title_id() {
+        let mut rom = hybrid_rom();
+        let title_id = 0x0003_0000_u64 << 32 | u64::from(u32::from_le_bytes(*b"ABCE"));
+        rom.write_le::<u64>(0x230, title_id);
+        let bytes = rom.read_extended_header().unwrap();
+        let header = header::ExtendedHeader::parse(&bytes).unwrap();
+        assert_eq!(header.title_id(), title_id);
+        assert_eq!(header.title_id() as u32, rom.game_code());
+
+        // Carts that aren't DSi-enhanced don't have an extended header
+        rom[0x12] = 0;
+        assert!(rom.read_extended_header().is_none());
+    }
+
+    #[test]
+    fn dsi_save_sizes() {
+        let mut rom = hybrid_rom();
+        assert_eq!(rom.dsi_save_sizes(), Some((0, 0)));
+        rom.write_le::<u32>(0x238, 0x4_0000);
+        rom.write_le::<u32>(0x23C, 0x800);
+        assert_eq!(rom.dsi_save_sizes(), Some((0x4_0000, 0x800)));
+
+        rom[0x12] = 0;
+        assert_eq!(rom.dsi_save_sizes(), None);
+    }
+
+    #[test]
+    fn secure_area_crc() {
+        let mut rom = BoxedByteSlice::new_zeroed(0x8000);
+        for (i, byte) in rom[0x4000..].iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(0x3B) ^ (i >> 8) as u8;
+        }
+        let crc = header::crc16(&rom[0x4000..0x8000]);
+        rom.write_le::<u16>(0x6C, crc);
+        let mut header_bytes = Bytes::new([0; 0x170]);
+        rom.read_header(&mut header_bytes);
+        let header = header::Header::parse(&header_bytes).unwrap();
+        assert!(rom.verify_secure_area_crc(&header));
+
+        // A single changed byte (i.e. from decrypting the secure area in place) breaks it
+        rom[0x7FFF] ^= 1;
+        assert!(!rom.verify_secure_area_crc(&header));
+        rom[0x7FFF] ^= 1;
+        rom[0x4000] ^= 1;
+        assert!(!rom.verify_secure_area_crc(&header));
+
+        // ROMs too small to contain the whole secure area never match
+        let mut short_rom = SliceContents(&mut rom[..0x7FFF]);
+        assert!(!short_rom.verify_secure_area_crc(&header));
+    }
+
+    #[test]
+    fn capacity_mismatch() {
+        // 2 MiB, i.e. 128 KiB << 4
+        let mut rom = hybrid_rom();
+        rom[0x14] = 4;
+        assert_eq!(rom.capacity_mismatch(), None);
+
+        // Trimmed dumps are still addressed as the full-size cart
+        let mut trimmed = TrimmedContents(BoxedByteSlice::new_zeroed(0x18_0000));
+        trimmed.0[..0x170].copy_from_slice(&rom[..0x170]);
+        assert_eq!(trimmed.capacity_mismatch(), None);
+
+        rom[0x14] = 5;
+        assert_eq!(rom.capacity_mismatch(), Some(0x40_0000));
+        rom[0x14] = 3;
+        assert_eq!(rom.capacity_mismatch(), Some(0x10_0000));
+    }
+
+    #[test]
+    fn overlay_table() {
+        let mut rom = BoxedByteSlice::new_zeroed(0x1_0000);
+        rom.write_le::<u32>(0x50, 0x5000);
+        rom.write_le::<u32>(0x54, 0x40);
+        for (i, entry) in rom[0x5000..0x5040].chunks_exact_mut(0x20).enumerate() {
+            let i = i as u32;
+            for (offset, value) in [
+                (0x00, i),
+                (0x04, 0x0210_0000 + i * 0x1000),
+                (0x08, 0x800),
+                (0x0C, 0x40),
+                (0x10, 0x0210_0700),
+                (0x14, 0x0210_0704),
+                (0x18, 2 + i),
+                (0x1C, 1 << 24 | 0x600),
+            ] {
+                entry.write_le::<u32>(offset, value);
+            }
+        }
+
+        let mut header_bytes = Bytes::new([0; 0x170]);
+        rom.read_header(&mut header_bytes);
+        let table = header::Header::parse(&header_bytes)
+            .unwrap()
+            .arm9_overlay_table();
+        let entries = rom.read_overlay_table(table);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1],
+            header::OverlayEntry {
+                id: 1,
+                ram_addr: 0x0210_1000,
+                ram_size: 0x800,
+                bss_size: 0x40,
+                static_init_start: 0x0210_0700,
+                static_init_end: 0x0210_0704,
+                file_id: 3,
+                compressed_size_and_flags: 0x0100_0600,
+            }
+        );
+        assert_eq!(entries[0].ram_addr, 0x0210_0000);
+        assert!(entries[0].is_compressed());
+        assert_eq!(entries[0].compressed_size(), 0x600);
+
+        // Tables running past the end of the ROM only yield the entries that fit completely
+        rom.copy_within(0x5000..0x5030, 0xFFD0);
+        let entries = rom.read_overlay_table(header::OverlayTableInfo {
+            rom_offset: 0xFFD0,
+            size: 0x40,
+        });
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, 0);
+    }
+
+    #[test]
+    fn header_sector_includes_padding() {
+        let mut contents = BoxedByteSlice::new_zeroed(0x8000);
+        contents.write_le::<u32>(0x20, 0x4000);
+        for (i, byte) in contents[0x170..0x200].iter_mut().enumerate() {
+            *byte = 0x80 | i as u8;
+        }
+        let expected = contents[..0x200].to_vec();
+
+        let mut sector = Bytes::new([0; 0x200]);
+        contents.read_header_sector(&mut sector);
+        assert_eq!(&sector[..], &expected[..]);
+
+        let mut rom = Rom::Normal(
+            Normal::new(
+                Box::new(contents),
+                None,
+                Model::Ds,
+                #[cfg(feature = "log")]
+                slog::Logger::root(slog::Discard, slog::o!()),
+            )
+            .unwrap(),
+        );
+        let mut sector = Bytes::new([0; 0x200]);
+        rom.read_header_sector(&mut sector);
+        assert_eq!(&sector[0x170..], &expected[0x170..]);
+        // The header part matches the 0x170-byte read
+        let mut header_bytes = Bytes::new([0; 0x170]);
+        rom.read_header(&mut header_bytes);
+        assert_eq!(&sector[..0x170], &header_bytes[..]);
+    }
 }
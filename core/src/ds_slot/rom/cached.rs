@@ -0,0 +1,212 @@
+use super::Contents;
+use crate::utils::{BoxedByteSlice, Bytes};
+
+pub const BLOCK_LEN: usize = 0x1_0000;
+
+struct Block {
+    start: usize,
+    data: BoxedByteSlice,
+    last_access: u64,
+}
+
+/// A [`Contents`] decorator caching the most recently used [`BLOCK_LEN`]-byte blocks of the inner
+/// contents, to avoid hitting slow backends (such as files) on every small read.
+pub struct CachedContents<C: Contents> {
+    inner: C,
+    blocks: Vec<Block>,
+    max_blocks: usize,
+    cur_access: u64,
+}
+
+impl<C: Contents> CachedContents<C> {
+    pub fn new(inner: C, max_blocks: usize) -> Self {
+        CachedContents {
+            inner,
+            blocks: Vec::with_capacity(max_blocks),
+            max_blocks: max_blocks.max(1),
+            cur_access: 0,
+        }
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Drops all cached blocks, forcing them to be reloaded from the inner contents.
+    pub fn invalidate(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Drops the cached blocks overlapping `start..start + len`.
+    pub fn invalidate_range(&mut self, start: usize, len: usize) {
+        let end = start + len;
+        self.blocks
+            .retain(|block| block.start >= end || block.start + BLOCK_LEN <= start);
+    }
+
+    fn block(&mut self, start: usize) -> &[u8] {
+        self.cur_access += 1;
+        let i = if let Some(i) = self.blocks.iter().position(|block| block.start == start) {
+            i
+        } else {
+            // Blocks past the end of the contents (which are only reached by out-of-bounds
+            // reads) are read as zeros
+            let len = BLOCK_LEN.min(self.inner.len().saturating_sub(start));
+            let i = if self.blocks.len() < self.max_blocks {
+                self.blocks.push(Block {
+                    start,
+                    data: BoxedByteSlice::new_zeroed(BLOCK_LEN),
+                    last_access: 0,
+                });
+                self.blocks.len() - 1
+            } else {
+                self.blocks
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, block)| block.last_access)
+                    .map(|(i, _)| i)
+                    .unwrap()
+            };
+            let block = &mut self.blocks[i];
+            block.start = start;
+            if len != 0 {
+                self.inner.read_slice(start, &mut block.data[..len]);
+            }
+            block.data[len..].fill(0);
+            i
+        };
+        let block = &mut self.blocks[i];
+        block.last_access = self.cur_access;
+        &block.data
+    }
+}
+
+impl<C: Contents> Contents for CachedContents<C> {
+    forward_contents_to_inner!(
+        len,
+        actual_len,
+        game_code,
+        secure_area,
+        prefetch_secure_area
+    );
+
+    // The overlays could get modified through the returned slices, so the cached data they cover
+    // can't be trusted anymore after handing them out
+    fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        self.invalidate_range(0x4000, 0x4000);
+        self.inner.secure_area_mut()
+    }
+
+    fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+        self.invalidate_range(addr, len);
+        self.inner.dldi_area_mut(addr, len)
+    }
+
+    fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        let start = self.inner.twl_region()?.secure_area_start;
+        self.invalidate_range(start, 0x800);
+        self.inner.twl_secure_area_mut()
+    }
+
+    fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+        buf.copy_from_slice(&self.block(0)[..0x170]);
+    }
+
+    fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+        let mut i = 0;
+        while i < output.len() {
+            let cur_addr = addr + i;
+            let block_start = cur_addr & !(BLOCK_LEN - 1);
+            let start_in_block = cur_addr - block_start;
+            let len = (BLOCK_LEN - start_in_block).min(output.len() - i);
+            output[i..i + len]
+                .copy_from_slice(&self.block(block_start)[start_in_block..start_in_block + len]);
+            i += len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts the reads reaching the wrapped contents
+    struct CountingContents {
+        inner: BoxedByteSlice,
+        reads: usize,
+    }
+
+    impl Contents for CountingContents {
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        fn game_code(&self) -> u32 {
+            0
+        }
+
+        fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+            self.inner.get_mut(0x4000..0x4800)
+        }
+
+        fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+            self.inner.dldi_area_mut(addr, len)
+        }
+
+        fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+            self.reads += 1;
+            self.inner.read_header(buf);
+        }
+
+        fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+            self.reads += 1;
+            self.inner.read_slice(addr, output);
+        }
+    }
+
+    fn cached_contents(len: usize, max_blocks: usize) -> CachedContents<CountingContents> {
+        let mut inner = BoxedByteSlice::new_zeroed(len);
+        for (i, byte) in inner.iter_mut().enumerate() {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        CachedContents::new(CountingContents { inner, reads: 0 }, max_blocks)
+    }
+
+    #[test]
+    fn repeated_reads_hit_the_cache() {
+        let mut contents = cached_contents(4 * BLOCK_LEN, 2);
+        let mut buf = [0; 0x200];
+        for _ in 0..16 {
+            contents.read_slice(0x1000, &mut buf);
+            assert_eq!(buf[0x10], (0x1010_usize ^ 0x10) as u8);
+        }
+        assert_eq!(contents.inner().reads, 1);
+    }
+
+    #[test]
+    fn secure_area_access_invalidates_overlapping_blocks() {
+        let mut contents = cached_contents(4 * BLOCK_LEN, 2);
+        let mut buf = [0; 0x10];
+        contents.read_slice(0x4000, &mut buf);
+        contents.read_slice(BLOCK_LEN, &mut buf);
+        contents.secure_area_mut().unwrap()[0] = 0xAA;
+        contents.read_slice(0x4000, &mut buf);
+        assert_eq!(buf[0], 0xAA);
+        contents.read_slice(BLOCK_LEN, &mut buf);
+        assert_eq!(contents.inner().reads, 3);
+    }
+
+    #[test]
+    fn reads_past_the_end_are_zero() {
+        let mut contents = cached_contents(BLOCK_LEN / 2, 1);
+        let mut buf = [0xFF; 0x10];
+        contents.read_slice(BLOCK_LEN, &mut buf);
+        assert_eq!(buf, [0; 0x10]);
+    }
+}
@@ -0,0 +1,224 @@
+use super::{header::Header, Contents, Overlays};
+use crate::utils::{zero, Bytes};
+
+/// ROM contents read on demand through a user-supplied callback (i.e. one reading ranges from
+/// JavaScript on the web), for environments where the ROM can't be opened as a file.
+///
+/// `read(addr, output)` is only ever called for ranges within the ROM's actual size; as with
+/// file-backed contents, the secure area and DLDI overlays are kept in memory so that they can be
+/// modified in place.
+pub struct CallbackContents<F: FnMut(usize, &mut [u8])> {
+    read: F,
+    len: usize,
+    game_code: u32,
+    overlays: Overlays,
+}
+
+impl<F: FnMut(usize, &mut [u8])> CallbackContents<F> {
+    /// Creates contents for a ROM of the given actual size (which should be at least 0x170 bytes,
+    /// to contain the header).
+    pub fn new(len: usize, mut read: F) -> Self {
+        let mut header_bytes: Bytes<0x170> = zero();
+        read(0, &mut *header_bytes);
+        let header = Header::parse(&header_bytes);
+        let game_code = header.map_or(0, |header| header.game_code().0);
+        let secure_area_start = header.map_or(0, |header| header.arm9_rom_offset() as usize);
+        CallbackContents {
+            read,
+            len,
+            game_code,
+            overlays: Overlays::new(secure_area_start),
+        }
+    }
+}
+
+fn read_padded(
+    read: &mut impl FnMut(usize, &mut [u8]),
+    len: usize,
+    addr: usize,
+    output: &mut [u8],
+) {
+    let read_len = output.len().min(len.saturating_sub(addr));
+    if read_len < output.len() {
+        output[read_len..].fill(0);
+    }
+    if read_len != 0 {
+        read(addr, &mut output[..read_len]);
+    }
+}
+
+impl<F: FnMut(usize, &mut [u8])> Contents for CallbackContents<F> {
+    fn len(&self) -> usize {
+        self.len.next_power_of_two()
+    }
+
+    fn actual_len(&self) -> usize {
+        self.len
+    }
+
+    fn game_code(&self) -> u32 {
+        self.game_code
+    }
+
+    fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        let CallbackContents {
+            read,
+            len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.secure_area_mut(|addr, output| read_padded(read, *len, addr, output)))
+    }
+
+    fn secure_area(&mut self) -> Option<&[u8]> {
+        let CallbackContents {
+            read,
+            len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.secure_area(|addr, output| read_padded(read, *len, addr, output)))
+    }
+
+    fn prefetch_secure_area(&mut self) {
+        self.secure_area();
+    }
+
+    fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+        let CallbackContents {
+            read,
+            len: rom_len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.dldi_area_mut(addr, len, |addr, output| {
+            read_padded(read, *rom_len, addr, output);
+        }))
+    }
+
+    fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        let start = self.twl_region()?.secure_area_start;
+        let CallbackContents {
+            read,
+            len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.twl_secure_area_mut(start, |addr, output| {
+            read_padded(read, *len, addr, output);
+        }))
+    }
+
+    fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+        read_padded(&mut self.read, self.len, 0, &mut **buf);
+    }
+
+    fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+        read_padded(&mut self.read, self.len, addr, output);
+        self.overlays.apply(addr, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{key1, normal::Normal, tests::test_arm7_bios, RomDevice},
+        *,
+    };
+    use crate::{utils::mem_prelude::*, Model};
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    #[test]
+    fn in_memory_reader() {
+        let mut data = vec![0; 0x6000];
+        for (i, byte) in data.iter_mut().enumerate().skip(0x200) {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        data[0xC..0x10].copy_from_slice(b"ABCE");
+        data.write_le(0x20, 0x4000_u32);
+        let expected = data.clone();
+        let mut contents = CallbackContents::new(data.len(), move |addr, output: &mut [u8]| {
+            assert!(addr + output.len() <= data.len());
+            output.copy_from_slice(&data[addr..addr + output.len()]);
+        });
+        assert_eq!(contents.actual_len(), 0x6000);
+        assert_eq!(contents.game_code(), u32::from_le_bytes(*b"ABCE"));
+
+        let mut output = [0; 0x20];
+        contents.read_slice(0x1234, &mut output);
+        assert_eq!(output[..], expected[0x1234..0x1254]);
+
+        // The secure area is modified in the overlay, and reads past the data are padded
+        contents.secure_area_mut().unwrap()[..8].fill(0xAA);
+        contents.read_slice(0x3FF0, &mut output);
+        assert_eq!(output[..0x10], expected[0x3FF0..0x4000]);
+        assert_eq!(output[0x10..0x18], [0xAA; 8]);
+        assert_eq!(output[0x18..], expected[0x4008..0x4010]);
+        contents.read_slice(0x5FF0, &mut output);
+        assert_eq!(output[..0x10], expected[0x5FF0..]);
+        assert_eq!(output[0x10..], [0; 0x10]);
+    }
+
+    #[test]
+    fn prefetched_secure_area() {
+        let reads = Rc::new(Cell::new(0));
+        let mut contents = CallbackContents::new(0x8000, {
+            let reads = Rc::clone(&reads);
+            move |addr, output: &mut [u8]| {
+                reads.set(reads.get() + 1);
+                output.fill(0x55);
+                if addr == 0 {
+                    output.write_le(0x20, 0x4000_u32);
+                }
+            }
+        });
+        assert_eq!(contents.overlays.loaded_len(), 0);
+
+        contents.prefetch_secure_area();
+        assert_eq!(contents.overlays.loaded_len(), 0x800);
+        // The overlay holds the secure area's data, so it isn't read again once it's needed
+        let reads_after_prefetch = reads.get();
+        assert_eq!(contents.secure_area_mut().unwrap(), &[0x55; 0x800][..]);
+        assert_eq!(reads.get(), reads_after_prefetch);
+    }
+
+    #[test]
+    fn unmodified_secure_area_reads_from_backing() {
+        let mut data = vec![0; 0x8000];
+        data.write_le(0x20, 0x4000_u32);
+        data.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+        let data = Rc::new(RefCell::new(data));
+        let contents = CallbackContents::new(0x8000, {
+            let data = Rc::clone(&data);
+            move |addr, output: &mut [u8]| {
+                output.copy_from_slice(&data.borrow()[addr..addr + output.len()]);
+            }
+        });
+        let bios = test_arm7_bios();
+        let mut rom = Normal::new(
+            Box::new(contents),
+            Some(&bios),
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+
+        // Direct boot doesn't need the already decrypted secure area to be modified, so changes to
+        // the backing data are seen by further reads
+        assert_eq!(rom.setup(true), Ok(()));
+        data.borrow_mut()[0x4100..0x4110].fill(0xAA);
+        let mut output = [0; 0x10];
+        rom.read(0x4100, &mut output);
+        assert_eq!(output, [0xAA; 0x10]);
+
+        // Once encrypted, reads come from the overlay instead
+        assert_eq!(rom.setup(false), Ok(()));
+        data.borrow_mut()[0x4100..0x4110].fill(0xBB);
+        rom.read(0x4100, &mut output);
+        assert_ne!(output, [0xBB; 0x10]);
+    }
+}
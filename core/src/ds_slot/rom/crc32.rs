@@ -0,0 +1,64 @@
+// The standard (zlib/zip) reflected CRC-32, using the 0xEDB88320 polynomial; only used as a cheap
+// identifier for ROM contents.
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                0xEDB8_8320 ^ value >> 1
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+};
+
+pub(super) struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = TABLE[(self.0 as u8 ^ byte) as usize] ^ self.0 >> 8;
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::Contents, *};
+    use crate::utils::BoxedByteSlice;
+
+    #[test]
+    fn check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+
+        // Splitting the data across updates doesn't change the result
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+
+        assert_eq!(Crc32::new().finish(), 0);
+        let mut contents = BoxedByteSlice::new_zeroed(9);
+        contents.copy_from_slice(b"123456789");
+        assert_eq!(contents.crc32(), 0xCBF4_3926);
+    }
+}
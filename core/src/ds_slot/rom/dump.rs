@@ -0,0 +1,232 @@
+use super::{header::Header, key1, new_secure_area_buf, normal::Normal, Contents};
+use crate::utils::{zero, BoxedByteSlice, Bytes};
+use std::io::{self, Write};
+
+const CHUNK_LEN: usize = 0x1_0000;
+
+#[derive(Debug)]
+pub enum DumpError {
+    MissingArm7Bios,
+    Io(io::Error),
+}
+
+impl From<io::Error> for DumpError {
+    fn from(value: io::Error) -> Self {
+        DumpError::Io(value)
+    }
+}
+
+fn read_secure_area(contents: &mut dyn Contents) -> Option<(usize, Box<Bytes<0x800>>)> {
+    let mut header_bytes = zero();
+    contents.read_header(&mut header_bytes);
+    let secure_area_start = Header::parse(&header_bytes)?.arm9_rom_offset() as usize;
+    if !(0x4000..0x8000).contains(&secure_area_start) {
+        return None;
+    }
+    let mut secure_area = new_secure_area_buf();
+    contents.read_slice(secure_area_start, &mut **secure_area);
+    Some((secure_area_start, secure_area))
+}
+
+fn write_with_secure_area(
+    contents: &mut dyn Contents,
+    secure_area: Option<(usize, &[u8])>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let len = contents.actual_len();
+    let mut buf = BoxedByteSlice::new_zeroed(CHUNK_LEN.min(len));
+    for chunk_start in (0..len).step_by(CHUNK_LEN) {
+        let chunk = &mut buf[..CHUNK_LEN.min(len - chunk_start)];
+        contents.read_slice(chunk_start, chunk);
+        if let Some((secure_area_start, secure_area)) = secure_area {
+            let chunk_end = chunk_start + chunk.len();
+            let secure_area_end = secure_area_start + secure_area.len();
+            if chunk_start < secure_area_end && chunk_end > secure_area_start {
+                let start = secure_area_start.max(chunk_start);
+                let end = secure_area_end.min(chunk_end);
+                chunk[start - chunk_start..end - chunk_start].copy_from_slice(
+                    &secure_area[start - secure_area_start..end - secure_area_start],
+                );
+            }
+        }
+        out.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+// Decrypts a secure area read with `read_secure_area` if needed
+fn decrypt_secure_area(normal: &Normal, secure_area: &mut [u8]) -> Result<(), DumpError> {
+    if !key1::is_secure_area_decrypted(secure_area) {
+        normal
+            .key_buf()
+            .ok_or(DumpError::MissingArm7Bios)?
+            .decrypt_secure_area(secure_area);
+    }
+    Ok(())
+}
+
+/// Returns the cart's 0x800-byte secure area in plaintext (starting with
+/// [`DECRYPTED_SECURE_AREA_ID`](key1::DECRYPTED_SECURE_AREA_ID) if decryption succeeded), as
+/// direct boot would decrypt it, or `None` for homebrew, which has no secure area.
+///
+/// # Errors
+/// - [`DumpError::MissingArm7Bios`]: the secure area needs to be decrypted, but no ARM7 BIOS was
+///   provided when creating the cart.
+pub fn export_secure_area(normal: &mut Normal) -> Result<Option<Box<Bytes<0x800>>>, DumpError> {
+    let Some((_, mut secure_area)) = read_secure_area(normal.contents()) else {
+        return Ok(None);
+    };
+    decrypt_secure_area(normal, &mut **secure_area)?;
+    Ok(Some(secure_area))
+}
+
+/// Writes the whole ROM to `out`, with its secure area in plaintext (starting with
+/// [`DECRYPTED_SECURE_AREA_ID`](key1::DECRYPTED_SECURE_AREA_ID)), regardless of whether it's
+/// currently encrypted or not.
+///
+/// # Errors
+/// - [`DumpError::MissingArm7Bios`]: the secure area needs to be decrypted, but no ARM7 BIOS was
+///   provided when creating the cart.
+/// - [`DumpError::Io`]: writing to `out` failed.
+pub fn dump_decrypted(normal: &mut Normal, out: &mut dyn Write) -> Result<(), DumpError> {
+    let secure_area = read_secure_area(normal.contents());
+    let secure_area = match secure_area {
+        Some((start, mut secure_area)) => {
+            decrypt_secure_area(normal, &mut **secure_area)?;
+            Some((start, secure_area))
+        }
+        None => None,
+    };
+    write_with_secure_area(
+        normal.contents(),
+        secure_area
+            .as_ref()
+            .map(|(start, secure_area)| (*start, &secure_area[..])),
+        out,
+    )?;
+    Ok(())
+}
+
+/// Writes the whole ROM to `out`, with its secure area encrypted the way it is on retail carts,
+/// using `key_buf` (which should be at level 2). Secure areas that are already encrypted and ROMs
+/// without one are copied verbatim.
+///
+/// # Errors
+/// Returns any error encountered while writing to `out`.
+pub fn encrypt_rom(
+    contents: &mut dyn Contents,
+    key_buf: &key1::KeyBuffer<false>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let secure_area = read_secure_area(contents).map(|(start, mut secure_area)| {
+        if key1::is_secure_area_decrypted(&**secure_area) {
+            key_buf.encrypt_secure_area(&mut **secure_area);
+        }
+        (start, secure_area)
+    });
+    write_with_secure_area(
+        contents,
+        secure_area
+            .as_ref()
+            .map(|(start, secure_area)| (*start, &secure_area[..])),
+        out,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::tests::{test_arm7_bios, TrimmedContents},
+        *,
+    };
+    use crate::{utils::mem_prelude::*, Model};
+
+    // Returns a trimmed 0x6000-byte ROM with its secure area at 0x4000, in plain text
+    fn decrypted_rom() -> BoxedByteSlice {
+        let mut rom = BoxedByteSlice::new_zeroed(0x6000);
+        for (i, byte) in rom.iter_mut().enumerate().skip(0x200) {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        rom[0xC..0x10].copy_from_slice(b"ABCE");
+        rom.write_le(0x20, 0x4000_u32);
+        rom.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+        rom
+    }
+
+    fn new_normal(rom: BoxedByteSlice) -> Normal {
+        Normal::new(
+            Box::new(TrimmedContents(rom)),
+            Some(&test_arm7_bios()),
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .expect("test ROM should have a valid size")
+    }
+
+    #[test]
+    fn dump_decrypted_round_trip() {
+        let original = decrypted_rom();
+        let mut encrypted = original.clone();
+        key1::KeyBuffer::<false>::new_boxed::<2>(u32::from_le_bytes(*b"ABCE"), &test_arm7_bios())
+            .encrypt_secure_area(&mut encrypted[0x4000..0x4800]);
+
+        for rom in [original.clone(), encrypted] {
+            let mut dump = Vec::new();
+            dump_decrypted(&mut new_normal(rom), &mut dump).unwrap();
+            assert_eq!(dump.len(), 0x6000);
+            assert_eq!(&dump[..], &original[..]);
+
+            let mut dump_rom = BoxedByteSlice::new_zeroed(dump.len());
+            dump_rom.copy_from_slice(&dump);
+            let mut reloaded = new_normal(dump_rom);
+            let secure_area = reloaded.contents().secure_area().unwrap();
+            assert!(key1::is_secure_area_decrypted(secure_area));
+        }
+    }
+
+    #[test]
+    fn encrypt_rom_round_trip() {
+        let original = decrypted_rom();
+        let mut normal = new_normal(original.clone());
+        let key_buf = normal.key_buf().unwrap().clone();
+        let mut encrypted = Vec::new();
+        encrypt_rom(normal.contents(), &key_buf, &mut encrypted).unwrap();
+        assert_eq!(encrypted.len(), 0x6000);
+        assert!(key_buf.can_decrypt_secure_area(&encrypted[0x4000..0x4800]));
+        assert_eq!(&encrypted[..0x4000], &original[..0x4000]);
+        assert_eq!(&encrypted[0x4800..], &original[0x4800..]);
+
+        let mut encrypted_rom = BoxedByteSlice::new_zeroed(encrypted.len());
+        encrypted_rom.copy_from_slice(&encrypted);
+        let mut decrypted = Vec::new();
+        dump_decrypted(&mut new_normal(encrypted_rom), &mut decrypted).unwrap();
+        assert_eq!(&decrypted[..], &original[..]);
+    }
+
+    #[test]
+    fn exported_secure_area_starts_with_marker() {
+        let original = decrypted_rom();
+        let mut encrypted = original.clone();
+        key1::KeyBuffer::<false>::new_boxed::<2>(u32::from_le_bytes(*b"ABCE"), &test_arm7_bios())
+            .encrypt_secure_area(&mut encrypted[0x4000..0x4800]);
+        let mut normal = new_normal(encrypted);
+        assert!(!key1::is_secure_area_decrypted(
+            normal.contents().secure_area().unwrap()
+        ));
+
+        let secure_area = export_secure_area(&mut normal).unwrap().unwrap();
+        assert_eq!(
+            secure_area.read_le::<u64>(0),
+            key1::DECRYPTED_SECURE_AREA_ID
+        );
+        assert_eq!(&secure_area[..], &original[0x4000..0x4800]);
+
+        // Homebrew has no secure area to export
+        let mut homebrew = decrypted_rom();
+        homebrew.write_le(0x20, 0x200_u32);
+        assert!(export_secure_area(&mut new_normal(homebrew))
+            .unwrap()
+            .is_none());
+    }
+}
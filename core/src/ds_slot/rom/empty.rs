@@ -3,6 +3,8 @@ use super::super::RomOutputLen;
 use crate::utils::mem_prelude::*;
 use crate::utils::{Bytes, Savestate};
 
+/// The ROM device used when no cartridge is inserted: every read and command returns an open bus
+/// (0xFF bytes), and the chip ID is 0.
 #[derive(Savestate)]
 pub struct Empty {
     #[cfg(feature = "log")]
@@ -39,7 +41,7 @@ impl super::RomDevice for Empty {
         0
     }
 
-    fn setup(&mut self, _direct_boot: bool) -> Result<(), ()> {
+    fn setup(&mut self, _direct_boot: bool) -> Result<(), super::SetupError> {
         Ok(())
     }
 
@@ -57,3 +59,33 @@ impl super::RomDevice for Empty {
         output[..output_len.get() as usize].fill(0xFF);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{super::RomDevice, *};
+
+    #[test]
+    fn open_bus() {
+        let mut rom = Empty::new(
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        );
+        assert_eq!(rom.setup(true), Ok(()));
+        assert_eq!(rom.chip_id(), 0);
+
+        let mut output = [0; 0x10];
+        rom.read(0x4000, &mut output);
+        assert_eq!(output, [0xFF; 0x10]);
+        let mut header = Bytes::new([0; 0x170]);
+        rom.read_header(&mut header);
+        assert!(header.iter().all(|&byte| byte == 0xFF));
+
+        // Only the transferred bytes are filled
+        for cmd in [[0; 8], [0xB7, 0, 0, 0x40, 0, 0, 0, 0], [0xB8; 8]] {
+            let mut output = Bytes::new([0; 0x4000]);
+            rom.handle_rom_command(Bytes::new(cmd), &mut output, RomOutputLen::new(0x200));
+            assert!(output[..0x200].iter().all(|&byte| byte == 0xFF));
+            assert!(output[0x200..].iter().all(|&byte| byte == 0));
+        }
+    }
+}
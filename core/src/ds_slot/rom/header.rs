@@ -1,9 +1,24 @@
-use crate::{ds_slot::RomControl, utils::mem_prelude::*};
+use crate::{
+    ds_slot::RomControl,
+    spi::firmware,
+    utils::{mem_prelude::*, Bytes},
+};
+
+pub use firmware::Crc16;
+
+/// Calculates the CRC16 used by the header and the banner (the standard Modbus variant, with the
+/// reflected 0xA001 polynomial and 0xFFFF as the initial value).
+#[inline]
+pub fn crc16(data: &[u8]) -> u16 {
+    firmware::crc16(0xFFFF, data)
+}
 
 #[derive(Clone, Copy)]
 pub struct Header<'a>(&'a [u8]);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum UnitCode {
     Ds = 0,
     DsAndDsi = 2,
@@ -16,6 +31,120 @@ mod bounded {
 }
 pub use bounded::*;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CodeBlockInfo {
+    pub rom_offset: u32,
+    pub entry_addr: u32,
+    pub ram_addr: u32,
+    pub size: u32,
+}
+
+impl CodeBlockInfo {
+    #[inline]
+    pub fn rom_end(&self) -> u64 {
+        self.rom_offset as u64 + self.size as u64
+    }
+
+    #[inline]
+    pub fn is_in_bounds(&self, rom_len: usize) -> bool {
+        self.rom_end() <= rom_len as u64
+    }
+}
+
+/// The location of an overlay table (an array of [`OverlayEntry`] structures) in the ROM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct OverlayTableInfo {
+    pub rom_offset: u32,
+    pub size: u32,
+}
+
+impl OverlayTableInfo {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size as usize / OverlayEntry::SIZE
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An entry of an ARM9 or ARM7 overlay table, describing a block of code loaded at runtime from
+/// the file with ID `file_id` in the FAT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct OverlayEntry {
+    pub id: u32,
+    pub ram_addr: u32,
+    pub ram_size: u32,
+    pub bss_size: u32,
+    pub static_init_start: u32,
+    pub static_init_end: u32,
+    pub file_id: u32,
+    /// The compressed size in the low 24 bits, followed by the flags (bit 0: compressed, bit 1:
+    /// authenticated) in the high 8.
+    pub compressed_size_and_flags: u32,
+}
+
+impl OverlayEntry {
+    pub const SIZE: usize = 0x20;
+
+    /// Parses an entry from the first [`SIZE`](Self::SIZE) bytes of `bytes`.
+    #[inline]
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.get(..Self::SIZE)?;
+        Some(OverlayEntry {
+            id: bytes.read_le::<u32>(0x00),
+            ram_addr: bytes.read_le::<u32>(0x04),
+            ram_size: bytes.read_le::<u32>(0x08),
+            bss_size: bytes.read_le::<u32>(0x0C),
+            static_init_start: bytes.read_le::<u32>(0x10),
+            static_init_end: bytes.read_le::<u32>(0x14),
+            file_id: bytes.read_le::<u32>(0x18),
+            compressed_size_and_flags: bytes.read_le::<u32>(0x1C),
+        })
+    }
+
+    /// Iterates over the whole entries contained in an overlay table's raw bytes, ignoring any
+    /// trailing partial one.
+    pub fn iter_table(table: &[u8]) -> impl Iterator<Item = Self> + '_ {
+        table.chunks_exact(Self::SIZE).filter_map(Self::parse)
+    }
+
+    #[inline]
+    pub fn compressed_size(&self) -> u32 {
+        self.compressed_size_and_flags & 0xFF_FFFF
+    }
+
+    #[inline]
+    pub fn is_compressed(&self) -> bool {
+        self.compressed_size_and_flags & 1 << 24 != 0
+    }
+}
+
+/// Byte offsets describing the ROM layout of DSi-enhanced and DSi-exclusive carts, whose TWL region
+/// is placed above the NTR one (which is all DS consoles can access).
+///
+/// The TWL secure area is encrypted with separate DSi-mode KEY1 tables, which aren't emulated, so
+/// it's left untouched during setup; it's still exposed (and kept in the in-memory overlays of
+/// contents that can't modify their data in place) through
+/// [`Contents::twl_secure_area_mut`](super::Contents::twl_secure_area_mut).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TwlRegionInfo {
+    /// The end of the NTR region, in bytes (stored in 512 KiB units in the header).
+    pub ntr_region_end: usize,
+    /// The start of the TWL region, in bytes (stored in 512 KiB units in the header).
+    pub twl_region_start: usize,
+    /// The start of the TWL secure area, i.e. of the DSi-specific ARM9 binary (see
+    /// [`ExtendedHeader::arm9i_rom_offset`]); assumed to be at the very start of the TWL region if
+    /// only the regular header is available.
+    pub secure_area_start: usize,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Region {
     Normal = 0,
@@ -23,6 +152,37 @@ pub enum Region {
     China = 0x80,
 }
 
+/// The region a game was released in, as derived from its game code's destination character (see
+/// [`Header::game_region`]); unlike [`Region`], this distinguishes all release regions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum GameRegion {
+    Japan,
+    Usa,
+    Europe,
+    Australia,
+    Korea,
+    China,
+    World,
+    Unknown,
+}
+
+bitflags::bitflags! {
+    // Bit positions match the order of the banner's title slots
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct LanguageSet: u8 {
+        const JAPANESE = 1 << 0;
+        const ENGLISH = 1 << 1;
+        const FRENCH = 1 << 2;
+        const GERMAN = 1 << 3;
+        const ITALIAN = 1 << 4;
+        const SPANISH = 1 << 5;
+        const CHINESE = 1 << 6;
+        const KOREAN = 1 << 7;
+    }
+}
+
 impl<'a> Header<'a> {
     #[inline]
     pub fn new(bytes: &'a [u8]) -> Option<Self> {
@@ -32,13 +192,20 @@ impl<'a> Header<'a> {
         Some(Header(bytes))
     }
 
+    #[inline]
+    pub fn parse(bytes: &'a Bytes<0x170>) -> Option<Self> {
+        Self::new(&**bytes)
+    }
+
+    /// Returns the up to 12-character ASCII title from the start of the header, without the NUL
+    /// or space padding after it, or `None` if it's not valid UTF-8.
     #[inline]
     pub fn game_title(&self) -> Option<&str> {
         let mut title_bytes = &self.0[0..0xC];
         if let Some(first_nul_pos) = title_bytes.iter().position(|b| *b == 0) {
             title_bytes = &title_bytes[..first_nul_pos];
         }
-        core::str::from_utf8(title_bytes).ok()
+        core::str::from_utf8(title_bytes).ok().map(str::trim_end)
     }
 
     #[inline]
@@ -53,6 +220,15 @@ impl<'a> Header<'a> {
         (code, core::str::from_utf8(&self.0[0x10..0x12]).ok())
     }
 
+    /// Returns the name of the publisher the maker code belongs to, for a few well-known ones.
+    pub fn publisher_name(&self) -> Option<&'static str> {
+        let maker_code: [u8; 2] = self.0[0x10..0x12].try_into().unwrap();
+        PUBLISHER_NAMES
+            .iter()
+            .find(|(code, _)| *code == maker_code)
+            .map(|(_, name)| *name)
+    }
+
     #[inline]
     pub fn unit_code(&self) -> Result<UnitCode, u8> {
         match self.0[0x12] {
@@ -63,6 +239,18 @@ impl<'a> Header<'a> {
         }
     }
 
+    #[inline]
+    pub fn is_dsi_enhanced(&self) -> bool {
+        matches!(self.unit_code(), Ok(UnitCode::DsAndDsi | UnitCode::Dsi))
+    }
+
+    /// Returns whether the cart is expected to carry an RSA-SHA1 signature over its header (see
+    /// [`RSA_SIGNATURE_START`]), which is only the case for DSi-enhanced and DSi-exclusive ones.
+    #[inline]
+    pub fn has_signature(&self) -> bool {
+        self.is_dsi_enhanced()
+    }
+
     #[inline]
     pub fn encryption_seed(&self) -> Result<EncryptionSeed, u8> {
         match self.0[0x13] {
@@ -77,6 +265,15 @@ impl<'a> Header<'a> {
         (shift, 1_usize.checked_shl(17 + shift as u32))
     }
 
+    /// Returns the cart's capacity in bytes as reported by the header, saturating for capacity
+    /// bytes too large to represent.
+    #[inline]
+    pub fn device_capacity_bytes(&self) -> u64 {
+        1_u64
+            .checked_shl(17 + self.0[0x14] as u32)
+            .unwrap_or(u64::MAX)
+    }
+
     #[inline]
     pub fn region(&self) -> Result<Region, u8> {
         match self.0[0x1D] {
@@ -87,6 +284,63 @@ impl<'a> Header<'a> {
         }
     }
 
+    /// Returns the region the game was released in, going by the last character of its game code.
+    pub fn game_region(&self) -> GameRegion {
+        // The region byte takes precedence, as Korean and Chinese releases can be region-locked
+        // even when their game code uses a different destination character
+        match self.region() {
+            Ok(Region::Korea) => return GameRegion::Korea,
+            Ok(Region::China) => return GameRegion::China,
+            _ => {}
+        }
+        match self.0[0xF] {
+            b'J' => GameRegion::Japan,
+            b'E' | b'L' | b'T' => GameRegion::Usa,
+            b'P' | b'D' | b'F' | b'I' | b'S' | b'H' | b'N' | b'R' | b'V' | b'W' | b'X' | b'Y'
+            | b'Z' => GameRegion::Europe,
+            b'U' => GameRegion::Australia,
+            b'K' => GameRegion::Korea,
+            b'C' => GameRegion::China,
+            // International releases, shared between the USA and Europe
+            b'A' | b'O' => GameRegion::World,
+            _ => GameRegion::Unknown,
+        }
+    }
+
+    /// Returns the languages the game is expected to support, going by its release region (see
+    /// [`game_region`](Self::game_region)).
+    pub fn supported_languages(&self) -> LanguageSet {
+        let region = self.game_region();
+        if region == GameRegion::Europe {
+            // Single-country European releases only include that country's language
+            match self.0[0xF] {
+                b'D' => return LanguageSet::GERMAN,
+                b'F' => return LanguageSet::FRENCH,
+                b'I' => return LanguageSet::ITALIAN,
+                b'S' => return LanguageSet::SPANISH,
+                _ => {}
+            }
+        }
+        match region {
+            GameRegion::Japan => LanguageSet::JAPANESE,
+            GameRegion::Usa => LanguageSet::ENGLISH | LanguageSet::FRENCH | LanguageSet::SPANISH,
+            GameRegion::Europe => {
+                LanguageSet::ENGLISH
+                    | LanguageSet::FRENCH
+                    | LanguageSet::GERMAN
+                    | LanguageSet::ITALIAN
+                    | LanguageSet::SPANISH
+            }
+            GameRegion::Australia => LanguageSet::ENGLISH,
+            GameRegion::Korea => LanguageSet::KOREAN,
+            GameRegion::China => LanguageSet::CHINESE,
+            GameRegion::World => LanguageSet::all() - LanguageSet::CHINESE - LanguageSet::KOREAN,
+            GameRegion::Unknown => LanguageSet::empty(),
+        }
+    }
+
+    /// Returns the ROM version, incremented for each revision of a game released with the same
+    /// game code (as distinguished by i.e. "(Rev 1)" entries in DAT files).
     #[inline]
     pub fn version(&self) -> u8 {
         self.0[0x1E]
@@ -97,6 +351,26 @@ impl<'a> Header<'a> {
         self.0[0x1F] & 1 << 2 != 0
     }
 
+    #[inline]
+    pub fn arm9(&self) -> CodeBlockInfo {
+        CodeBlockInfo {
+            rom_offset: self.arm9_rom_offset(),
+            entry_addr: self.arm9_entry_addr(),
+            ram_addr: self.arm9_ram_addr(),
+            size: self.arm9_size(),
+        }
+    }
+
+    #[inline]
+    pub fn arm7(&self) -> CodeBlockInfo {
+        CodeBlockInfo {
+            rom_offset: self.arm7_rom_offset(),
+            entry_addr: self.arm7_entry_addr(),
+            ram_addr: self.arm7_ram_addr(),
+            size: self.arm7_size(),
+        }
+    }
+
     #[inline]
     pub fn arm9_rom_offset(&self) -> u32 {
         self.0.read_le::<u32>(0x20)
@@ -177,6 +451,22 @@ impl<'a> Header<'a> {
         self.0.read_le::<u32>(0x5C)
     }
 
+    #[inline]
+    pub fn arm9_overlay_table(&self) -> OverlayTableInfo {
+        OverlayTableInfo {
+            rom_offset: self.arm9_overlay_offset(),
+            size: self.arm9_overlay_size(),
+        }
+    }
+
+    #[inline]
+    pub fn arm7_overlay_table(&self) -> OverlayTableInfo {
+        OverlayTableInfo {
+            rom_offset: self.arm7_overlay_offset(),
+            size: self.arm7_overlay_size(),
+        }
+    }
+
     #[inline]
     pub fn rom_control_normal(&self) -> RomControl {
         RomControl(self.0.read_le::<u32>(0x60))
@@ -187,6 +477,15 @@ impl<'a> Header<'a> {
         RomControl(self.0.read_le::<u32>(0x64))
     }
 
+    /// Returns the secure area disable field, which is either zero or the KEY1-encrypted
+    /// `NmMdOnly` magic (see [`key1::KeyBuffer::is_secure_area_disabled`]).
+    ///
+    /// [`key1::KeyBuffer::is_secure_area_disabled`]: super::key1::KeyBuffer::is_secure_area_disabled
+    #[inline]
+    pub fn secure_area_disable(&self) -> &'a [u8; 8] {
+        self.0[0x78..0x80].try_into().unwrap()
+    }
+
     #[inline]
     pub fn icon_title_offset(&self) -> u32 {
         self.0.read_le::<u32>(0x68)
@@ -207,6 +506,33 @@ impl<'a> Header<'a> {
         self.0.read_le::<u32>(0x84)
     }
 
+    #[inline]
+    pub fn ntr_region_end(&self) -> u16 {
+        self.0.read_le::<u16>(0x90)
+    }
+
+    #[inline]
+    pub fn twl_region_start(&self) -> u16 {
+        self.0.read_le::<u16>(0x92)
+    }
+
+    /// Returns the layout of the DSi-specific (TWL) part of the ROM, for DSi-enhanced and
+    /// DSi-exclusive carts; prefer [`ExtendedHeader::twl_region`] when the extended header is
+    /// available, as only it stores the TWL secure area's actual offset.
+    pub fn twl_region(&self) -> Option<TwlRegionInfo> {
+        if !self.is_dsi_enhanced() {
+            return None;
+        }
+        let twl_region_start = (self.twl_region_start() as usize) << 19;
+        Some(TwlRegionInfo {
+            ntr_region_end: (self.ntr_region_end() as usize) << 19,
+            twl_region_start,
+            // The TWL region usually starts with its own secure area, encrypted separately from the
+            // NTR one (see `TwlRegionInfo::secure_area_start`)
+            secure_area_start: twl_region_start,
+        })
+    }
+
     #[inline]
     pub fn nand_raw_rom_end(&self) -> u16 {
         self.0.read_le::<u16>(0x94)
@@ -217,8 +543,565 @@ impl<'a> Header<'a> {
         self.0.read_le::<u16>(0x98)
     }
 
+    #[inline]
+    pub fn logo_bytes(&self) -> &'a [u8; 0x9C] {
+        self.0[0xC0..0x15C].try_into().unwrap()
+    }
+
+    #[inline]
+    pub fn logo_crc(&self) -> u16 {
+        self.0.read_le::<u16>(0x15C)
+    }
+
+    /// Returns whether the compressed Nintendo logo is intact, which the BIOS requires to boot the
+    /// cart; its CRC is fixed, so this doesn't depend on the stored [`logo_crc`](Self::logo_crc).
+    #[inline]
+    pub fn logo_valid(&self) -> bool {
+        crc16(self.logo_bytes()) == LOGO_CRC
+    }
+
     #[inline]
     pub fn header_crc(&self) -> u16 {
         self.0.read_le::<u16>(0x15E)
     }
+
+    #[inline]
+    pub fn header_crc_valid(&self) -> bool {
+        crc16(&self.0[..0x15E]) == self.header_crc()
+    }
+}
+
+/// The DSi-specific part of the header, present on DSi-enhanced and DSi-exclusive carts right after
+/// the regular header (which it contains, at 0x000..0x170).
+#[derive(Clone, Copy)]
+pub struct ExtendedHeader<'a>(&'a [u8]);
+
+impl<'a> ExtendedHeader<'a> {
+    #[inline]
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < 0x400 || !Header::new(bytes)?.is_dsi_enhanced() {
+            return None;
+        }
+        Some(ExtendedHeader(bytes))
+    }
+
+    #[inline]
+    pub fn parse(bytes: &'a Bytes<0x400>) -> Option<Self> {
+        Self::new(&**bytes)
+    }
+
+    #[inline]
+    pub fn header(&self) -> Header<'a> {
+        Header(self.0)
+    }
+
+    /// Returns the ROM offset of the DSi-specific ARM9 binary, which starts with the TWL secure
+    /// area.
+    #[inline]
+    pub fn arm9i_rom_offset(&self) -> u32 {
+        self.0.read_le::<u32>(0x1C0)
+    }
+
+    /// Returns the layout of the TWL part of the ROM, like [`Header::twl_region`] but with the TWL
+    /// secure area's offset taken from [`arm9i_rom_offset`](Self::arm9i_rom_offset).
+    pub fn twl_region(&self) -> Option<TwlRegionInfo> {
+        let mut twl_region = self.header().twl_region()?;
+        let arm9i_rom_offset = self.arm9i_rom_offset();
+        if arm9i_rom_offset != 0 {
+            twl_region.secure_area_start = arm9i_rom_offset as usize;
+        }
+        Some(twl_region)
+    }
+
+    #[inline]
+    pub fn access_control(&self) -> u32 {
+        self.0.read_le::<u32>(0x1B4)
+    }
+
+    #[inline]
+    pub fn arm7_scfg_ext_mask(&self) -> u32 {
+        self.0.read_le::<u32>(0x1B8)
+    }
+
+    /// Returns the 64-bit TWL title ID, whose high word is the title category (i.e. `0x0003_0004`
+    /// for `DSiWare` titles) and whose low word is the game code.
+    #[inline]
+    pub fn title_id(&self) -> u64 {
+        self.0.read_le::<u64>(0x230)
+    }
+
+    /// Returns the size in bytes of the `public.sav` file to create in NAND, or 0 if none is used.
+    #[inline]
+    pub fn public_save_size(&self) -> u32 {
+        self.0.read_le::<u32>(0x238)
+    }
+
+    /// Returns the size in bytes of the `private.sav` file to create in NAND, or 0 if none is used.
+    #[inline]
+    pub fn private_save_size(&self) -> u32 {
+        self.0.read_le::<u32>(0x23C)
+    }
+}
+
+pub const LOGO_CRC: u16 = 0xCF56;
+
+static PUBLISHER_NAMES: &[([u8; 2], &str)] = &[
+    (*b"01", "Nintendo"),
+    (*b"08", "Capcom"),
+    (*b"18", "Hudson Soft"),
+    (*b"41", "Ubisoft"),
+    (*b"4F", "Eidos"),
+    (*b"4Q", "Disney Interactive"),
+    (*b"52", "Activision"),
+    (*b"5D", "Midway"),
+    (*b"5G", "Majesco"),
+    (*b"64", "LucasArts"),
+    (*b"69", "Electronic Arts"),
+    (*b"70", "Atari"),
+    (*b"78", "THQ"),
+    (*b"8P", "Sega"),
+    (*b"A4", "Konami"),
+    (*b"AF", "Namco"),
+    (*b"B2", "Bandai"),
+    (*b"C8", "Koei"),
+    (*b"E9", "Natsume"),
+    (*b"EB", "Atlus"),
+    (*b"GD", "Square Enix"),
+];
+
+/// The ROM offset of the header's RSA-SHA1 signature, right before the end of the 0x1000-byte
+/// extended header area.
+pub const RSA_SIGNATURE_START: usize = 0xF80;
+pub const RSA_SIGNATURE_LEN: usize = 0x80;
+
+// Serialized as a struct of the parsed fields, with stable names
+#[cfg(feature = "serde")]
+impl serde::Serialize for Header<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("Header", 21)?;
+        state.serialize_field("game_title", &self.game_title())?;
+        state.serialize_field("game_code", &self.game_code().1)?;
+        state.serialize_field("maker_code", &self.maker_code().1)?;
+        state.serialize_field("unit_code", &self.unit_code().ok())?;
+        state.serialize_field("region", &self.game_region())?;
+        state.serialize_field("capacity", &self.capacity().1)?;
+        state.serialize_field("version", &self.version())?;
+        state.serialize_field("auto_start", &self.auto_start())?;
+        state.serialize_field("arm9", &self.arm9())?;
+        state.serialize_field("arm7", &self.arm7())?;
+        state.serialize_field("fnt_offset", &self.fnt_offset())?;
+        state.serialize_field("fnt_size", &self.fnt_size())?;
+        state.serialize_field("fat_offset", &self.fat_offset())?;
+        state.serialize_field("fat_size", &self.fat_size())?;
+        state.serialize_field("icon_title_offset", &self.icon_title_offset())?;
+        state.serialize_field("secure_area_crc", &self.secure_area_crc())?;
+        state.serialize_field("used_rom_size", &self.used_rom_size())?;
+        state.serialize_field("header_size", &self.header_size())?;
+        state.serialize_field("twl_region", &self.twl_region())?;
+        state.serialize_field("is_dsi_enhanced", &self.is_dsi_enhanced())?;
+        state.serialize_field("header_crc", &self.header_crc())?;
+        state.end()
+    }
+}
+
+pub struct HeaderBuilder<'a> {
+    pub game_title: &'a str,
+    pub game_code: [u8; 4],
+    pub maker_code: [u8; 2],
+    pub unit_code: UnitCode,
+    pub capacity_shift: u8,
+    pub arm9: CodeBlockInfo,
+    pub arm7: CodeBlockInfo,
+    pub icon_title_offset: u32,
+    pub rom_control_normal: RomControl,
+    pub rom_control_key1: RomControl,
+    // The (possibly encrypted) contents of 0x4000..0x8000, used to calculate the secure area CRC
+    pub secure_area: Option<&'a [u8]>,
+    pub logo: Option<&'a [u8; 0x9C]>,
+}
+
+impl<'a> HeaderBuilder<'a> {
+    #[inline]
+    pub fn new(
+        game_title: &'a str,
+        game_code: [u8; 4],
+        maker_code: [u8; 2],
+        arm9: CodeBlockInfo,
+        arm7: CodeBlockInfo,
+    ) -> Self {
+        HeaderBuilder {
+            game_title,
+            game_code,
+            maker_code,
+            unit_code: UnitCode::Ds,
+            capacity_shift: 0,
+            arm9,
+            arm7,
+            icon_title_offset: 0,
+            // Default values used by retail carts
+            rom_control_normal: RomControl(0x0058_6000),
+            rom_control_key1: RomControl(0x0018_08F8),
+            secure_area: None,
+            logo: None,
+        }
+    }
+
+    pub fn build(&self) -> Bytes<0x170> {
+        let mut bytes = Bytes::new([0; 0x170]);
+
+        let title_len = self.game_title.len().min(0xC);
+        bytes[..title_len].copy_from_slice(&self.game_title.as_bytes()[..title_len]);
+        bytes[0xC..0x10].copy_from_slice(&self.game_code);
+        bytes[0x10..0x12].copy_from_slice(&self.maker_code);
+        bytes[0x12] = self.unit_code as u8;
+        bytes[0x14] = self.capacity_shift;
+
+        for (base, info) in [(0x20, &self.arm9), (0x30, &self.arm7)] {
+            bytes.write_le(base, info.rom_offset);
+            bytes.write_le(base + 4, info.entry_addr);
+            bytes.write_le(base + 8, info.ram_addr);
+            bytes.write_le(base + 0xC, info.size);
+        }
+
+        bytes.write_le(0x60, self.rom_control_normal.0);
+        bytes.write_le(0x64, self.rom_control_key1.0);
+        bytes.write_le(0x68, self.icon_title_offset);
+        if let Some(secure_area) = self.secure_area {
+            bytes.write_le(0x6C, crc16(secure_area));
+        }
+        // Secure area delay, in 131 kHz units
+        bytes.write_le(0x6E, 0x051E_u16);
+
+        let mut used_rom_size = self.arm9.rom_end().max(self.arm7.rom_end());
+        if self.icon_title_offset != 0 {
+            used_rom_size = used_rom_size.max(self.icon_title_offset as u64 + 0x840);
+        }
+        bytes.write_le(0x80, used_rom_size as u32);
+        bytes.write_le(0x84, 0x4000_u32);
+
+        if let Some(logo) = self.logo {
+            bytes[0xC0..0x15C].copy_from_slice(logo);
+        }
+        bytes.write_le(0x15C, LOGO_CRC);
+        let header_crc = crc16(&bytes[..0x15E]);
+        bytes.write_le(0x15E, header_crc);
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    // A stand-in for the real logo (which can't be distributed), with its last two bytes chosen to
+    // give it the same CRC
+    pub(in super::super) fn test_logo() -> [u8; 0x9C] {
+        let mut logo = [0; 0x9C];
+        for (i, byte) in logo[..0x9A].iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(0x1D).wrapping_add(0x24);
+        }
+        logo[0x9A..].copy_from_slice(&[0x1F, 0xF3]);
+        logo
+    }
+
+    fn header_bytes(game_code: [u8; 4], region: u8) -> [u8; 0x170] {
+        let mut bytes = [0; 0x170];
+        bytes[0xC..0x10].copy_from_slice(&game_code);
+        bytes[0x1D] = region;
+        bytes
+    }
+
+    // A header laid out like a retail cart's, with the fields filled in by hand at their offsets
+    fn retail_header_bytes() -> [u8; 0x170] {
+        let mut bytes = [0; 0x170];
+        bytes[..0xC].copy_from_slice(b"RETAILGAME\0\0");
+        bytes[0xC..0x10].copy_from_slice(b"ARGE");
+        bytes[0x10..0x12].copy_from_slice(b"01");
+        bytes[0x14] = 9;
+        for (offset, value) in [
+            (0x20, 0x4000),
+            (0x24, 0x0200_0800),
+            (0x28, 0x0200_0000),
+            (0x2C, 0xB_C000),
+            (0x30, 0xC_0000),
+            (0x34, 0x0238_0000),
+            (0x38, 0x0238_0000),
+            (0x3C, 0x2_6000),
+            (0x60, 0x0058_6000),
+            (0x64, 0x0018_08F8),
+            (0x68, 0xE_6000),
+        ] {
+            bytes.write_le::<u32>(offset, value);
+        }
+        bytes.write_le::<u16>(0x6C, 0x1234);
+        let header_crc = crc16(&bytes[..0x15E]);
+        bytes.write_le::<u16>(0x15E, header_crc);
+        bytes
+    }
+
+    #[test]
+    fn parse_header_fields() {
+        let bytes = Bytes::new(retail_header_bytes());
+        let header = Header::parse(&bytes).unwrap();
+        assert_eq!(header.game_title(), Some("RETAILGAME"));
+        assert_eq!(
+            header.game_code(),
+            (u32::from_le_bytes(*b"ARGE"), Some("ARGE"))
+        );
+        assert_eq!(
+            header.maker_code(),
+            (u16::from_le_bytes(*b"01"), Some("01"))
+        );
+        assert_eq!(header.unit_code(), Ok(UnitCode::Ds));
+        assert_eq!(header.capacity(), (9, Some(0x400_0000)));
+        assert_eq!(header.device_capacity_bytes(), 0x400_0000);
+        assert_eq!(header.arm9_rom_offset(), 0x4000);
+        assert_eq!(header.arm9_entry_addr(), 0x0200_0800);
+        assert_eq!(header.arm9_ram_addr(), 0x0200_0000);
+        assert_eq!(header.arm9_size(), 0xB_C000);
+        assert_eq!(header.arm7_rom_offset(), 0xC_0000);
+        assert_eq!(header.arm7_entry_addr(), 0x0238_0000);
+        assert_eq!(header.arm7_ram_addr(), 0x0238_0000);
+        assert_eq!(header.arm7_size(), 0x2_6000);
+        assert_eq!(header.rom_control_normal().0, 0x0058_6000);
+        assert_eq!(header.rom_control_key1().0, 0x0018_08F8);
+        assert_eq!(header.icon_title_offset(), 0xE_6000);
+        assert_eq!(header.secure_area_crc(), 0x1234);
+        assert_eq!(header.header_crc(), crc16(&bytes[..0x15E]));
+        assert!(header.header_crc_valid());
+
+        // Only the first 0x170 bytes are needed
+        assert!(Header::new(&bytes[..0x16F]).is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_to_json() {
+        let bytes = Bytes::new(retail_header_bytes());
+        let json = serde_json::to_value(Header::parse(&bytes).unwrap()).unwrap();
+        assert_eq!(json["game_title"], "RETAILGAME");
+        assert_eq!(json["game_code"], "ARGE");
+        assert_eq!(json["maker_code"], "01");
+        assert_eq!(json["unit_code"], "ds");
+        assert_eq!(json["capacity"], 0x400_0000);
+        assert_eq!(json["arm9"]["rom_offset"], 0x4000);
+        assert_eq!(json["arm7"]["size"], 0x2_6000);
+        assert_eq!(json["secure_area_crc"], 0x1234);
+        assert_eq!(json["twl_region"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn code_block_info() {
+        let mut bytes = retail_header_bytes();
+        let header = Header::new(&bytes).unwrap();
+        let arm9 = header.arm9();
+        let arm7 = header.arm7();
+        assert_eq!(
+            arm9,
+            CodeBlockInfo {
+                rom_offset: 0x4000,
+                entry_addr: 0x0200_0800,
+                ram_addr: 0x0200_0000,
+                size: 0xB_C000,
+            }
+        );
+        assert_eq!(
+            arm7,
+            CodeBlockInfo {
+                rom_offset: 0xC_0000,
+                entry_addr: 0x0238_0000,
+                ram_addr: 0x0238_0000,
+                size: 0x2_6000,
+            }
+        );
+
+        assert_eq!(arm7.rom_end(), 0xE_6000);
+        assert!(arm9.is_in_bounds(0xE_6000) && arm7.is_in_bounds(0xE_6000));
+        assert!(arm9.is_in_bounds(0xE_5FFF) && !arm7.is_in_bounds(0xE_5FFF));
+
+        // The end is calculated without overflowing, so blocks wrapping around are rejected too
+        bytes.write_le::<u32>(0x30, 0xFFFF_F000);
+        let arm7 = Header::new(&bytes).unwrap().arm7();
+        assert_eq!(arm7.rom_end(), 0x1_0002_5000);
+        assert!(!arm7.is_in_bounds(0x1000_0000));
+    }
+
+    #[test]
+    fn builder_round_trip() {
+        let arm9 = CodeBlockInfo {
+            rom_offset: 0x200,
+            entry_addr: 0x0200_0000,
+            ram_addr: 0x0200_0000,
+            size: 0x1_0000,
+        };
+        let arm7 = CodeBlockInfo {
+            rom_offset: 0x1_0200,
+            entry_addr: 0x0380_0000,
+            ram_addr: 0x0380_0000,
+            size: 0x8000,
+        };
+        let secure_area: Vec<u8> = (0..0x4000).map(|i| i as u8).collect();
+        let mut builder = HeaderBuilder::new("HOMEBREW", *b"HBRE", *b"00", arm9, arm7);
+        builder.unit_code = UnitCode::DsAndDsi;
+        builder.capacity_shift = 2;
+        builder.icon_title_offset = 0x1_8200;
+        builder.secure_area = Some(&secure_area);
+        let bytes = builder.build();
+
+        let header = Header::parse(&bytes).unwrap();
+        assert_eq!(header.game_title(), Some("HOMEBREW"));
+        assert_eq!(header.game_code().1, Some("HBRE"));
+        assert_eq!(header.maker_code().1, Some("00"));
+        assert_eq!(header.unit_code(), Ok(UnitCode::DsAndDsi));
+        assert_eq!(header.capacity(), (2, Some(0x8_0000)));
+        assert_eq!(header.arm9(), arm9);
+        assert_eq!(header.arm7(), arm7);
+        assert_eq!(header.icon_title_offset(), 0x1_8200);
+        assert_eq!(header.rom_control_normal().0, 0x0058_6000);
+        assert_eq!(header.rom_control_key1().0, 0x0018_08F8);
+        // The banner (0x840 bytes) ends after both code blocks
+        assert_eq!(header.used_rom_size(), 0x1_8A40);
+        assert_eq!(header.secure_area_crc(), crc16(&secure_area));
+        assert_eq!(header.logo_crc(), LOGO_CRC);
+        assert_eq!(header.header_crc(), crc16(&bytes[..0x15E]));
+        assert!(header.header_crc_valid());
+    }
+
+    #[test]
+    fn logo_validity() {
+        let mut bytes = retail_header_bytes();
+        bytes[0xC0..0x15C].copy_from_slice(&test_logo());
+        assert!(Header::new(&bytes).unwrap().logo_valid());
+
+        bytes[0xD0] ^= 0x40;
+        assert!(!Header::new(&bytes).unwrap().logo_valid());
+        // The stored CRC isn't what's checked
+        bytes.write_le::<u16>(0x15C, LOGO_CRC);
+        assert!(!Header::new(&bytes).unwrap().logo_valid());
+    }
+
+    #[test]
+    fn unit_code_values() {
+        let mut bytes = header_bytes(*b"ABCE", 0);
+        for (byte, unit_code, is_dsi_enhanced) in [
+            (0, Ok(UnitCode::Ds), false),
+            (2, Ok(UnitCode::DsAndDsi), true),
+            (3, Ok(UnitCode::Dsi), true),
+            (1, Err(1), false),
+            (0xFF, Err(0xFF), false),
+        ] {
+            bytes[0x12] = byte;
+            let header = Header::new(&bytes).unwrap();
+            assert_eq!(header.unit_code(), unit_code);
+            assert_eq!(header.is_dsi_enhanced(), is_dsi_enhanced);
+        }
+    }
+
+    #[test]
+    fn region_byte() {
+        for (byte, region) in [
+            (0, Ok(Region::Normal)),
+            (0x40, Ok(Region::Korea)),
+            (0x80, Ok(Region::China)),
+            (0x20, Err(0x20)),
+        ] {
+            let bytes = header_bytes(*b"ABCE", byte);
+            assert_eq!(Header::new(&bytes).unwrap().region(), region);
+        }
+    }
+
+    #[test]
+    fn game_region_from_game_code() {
+        for (game_code, region) in [
+            (*b"ABCJ", GameRegion::Japan),
+            (*b"ABCE", GameRegion::Usa),
+            (*b"ABCP", GameRegion::Europe),
+            (*b"ABCD", GameRegion::Europe),
+            (*b"ABCU", GameRegion::Australia),
+            (*b"ABCK", GameRegion::Korea),
+            (*b"ABCA", GameRegion::World),
+            (*b"ABC#", GameRegion::Unknown),
+        ] {
+            let bytes = header_bytes(game_code, 0);
+            assert_eq!(Header::new(&bytes).unwrap().game_region(), region);
+        }
+    }
+
+    #[test]
+    fn region_byte_overrides_game_code() {
+        let bytes = header_bytes(*b"ABCE", 0x80);
+        assert_eq!(
+            Header::new(&bytes).unwrap().game_region(),
+            GameRegion::China
+        );
+    }
+
+    #[test]
+    fn supported_languages() {
+        let bytes = header_bytes(*b"ABCD", 0);
+        assert_eq!(
+            Header::new(&bytes).unwrap().supported_languages(),
+            LanguageSet::GERMAN
+        );
+        let bytes = header_bytes(*b"ABCJ", 0);
+        assert_eq!(
+            Header::new(&bytes).unwrap().supported_languages(),
+            LanguageSet::JAPANESE
+        );
+    }
+
+    #[test]
+    fn version_byte() {
+        let mut bytes = header_bytes(*b"ABCE", 0);
+        assert_eq!(Header::new(&bytes).unwrap().version(), 0);
+        bytes[0x1E] = 2;
+        assert_eq!(Header::new(&bytes).unwrap().version(), 2);
+    }
+
+    #[test]
+    fn game_title_padding() {
+        let mut bytes = header_bytes(*b"ABCE", 0);
+        bytes[..0xC].copy_from_slice(b"GAME TITLE  ");
+        assert_eq!(
+            Header::new(&bytes).unwrap().game_title(),
+            Some("GAME TITLE")
+        );
+        bytes[..0xC].copy_from_slice(b"GAME\0\0\0\0\0\0\0\0");
+        assert_eq!(Header::new(&bytes).unwrap().game_title(), Some("GAME"));
+        bytes[..0xC].fill(0);
+        assert_eq!(Header::new(&bytes).unwrap().game_title(), Some(""));
+    }
+
+    #[test]
+    fn publisher_names() {
+        let mut bytes = header_bytes(*b"ABCE", 0);
+        bytes[0x10..0x12].copy_from_slice(b"01");
+        assert_eq!(
+            Header::new(&bytes).unwrap().publisher_name(),
+            Some("Nintendo")
+        );
+        bytes[0x10..0x12].copy_from_slice(b"78");
+        assert_eq!(Header::new(&bytes).unwrap().publisher_name(), Some("THQ"));
+        // Maker codes outside the table (including a blank one) have no name
+        bytes[0x10..0x12].copy_from_slice(b"ZZ");
+        assert_eq!(Header::new(&bytes).unwrap().publisher_name(), None);
+        bytes[0x10..0x12].fill(0);
+        assert_eq!(Header::new(&bytes).unwrap().publisher_name(), None);
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        // CRC-16/MODBUS, which the header and logo CRCs use
+        assert_eq!(crc16(b"123456789"), 0x4B37);
+        assert_eq!(crc16(b""), 0xFFFF);
+
+        let mut bytes = retail_header_bytes();
+        let header_crc = crc16(&bytes[..0x15E]);
+        bytes.write_le(0x15E, header_crc);
+        assert!(Header::new(&bytes).unwrap().header_crc_valid());
+        bytes[0] ^= 1;
+        assert!(!Header::new(&bytes).unwrap().header_crc_valid());
+    }
 }
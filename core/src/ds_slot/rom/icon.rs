@@ -1,37 +1,167 @@
-use super::{header::Header, Contents};
+use super::{
+    header::{crc16, Header, LanguageSet},
+    read_le_array, Contents,
+};
 use crate::utils::{mem_prelude::*, Bytes};
+use core::array;
+use std::io::{Read, Seek, SeekFrom};
 
-pub fn decode_to_rgba8(
-    icon_title_offset: usize,
-    rom_contents: &mut impl Contents,
-) -> Option<[u32; 32 * 32]> {
-    let mut icon_data = Bytes::new([0; 0x220]);
-    if icon_title_offset + 0x240 > rom_contents.len() {
-        return None;
-    }
-    rom_contents.read_slice(icon_title_offset + 0x20, &mut *icon_data);
-
+fn decode_palette(raw_palette: [u16; 16]) -> [u32; 16] {
     let mut palette = [0; 16];
-    for (i, color) in palette.iter_mut().enumerate().skip(1) {
-        let raw_color = icon_data.read_le::<u16>(0x200 | i << 1) as u32;
+    for (color, raw_color) in palette.iter_mut().zip(raw_palette).skip(1) {
+        let raw_color = raw_color as u32;
         let rgb6 =
             (raw_color << 1 & 0x3E) | (raw_color << 4 & 0x3E00) | (raw_color << 7 & 0x3E_0000);
         *color = 0xFF00_0000 | rgb6 << 2 | (rgb6 >> 4 & 0x03_0303);
     }
+    palette
+}
 
+fn decode_bitmap(bitmap: &[u8], palette: &[u32; 16], flip_h: bool, flip_v: bool) -> [u32; 32 * 32] {
     let mut pixels = [0; 32 * 32];
     for src_tile_line_base in (0..0x200).step_by(4) {
-        let src_line = icon_data.read_le::<u32>(src_tile_line_base);
+        let src_line = bitmap.read_le::<u32>(src_tile_line_base);
         let tile_y = src_tile_line_base >> 7;
         let tile_x = src_tile_line_base >> 5 & 3;
         let y_in_tile = src_tile_line_base >> 2 & 7;
-        let dst_tile_line_base = tile_y << 8 | y_in_tile << 5 | tile_x << 3;
+        let mut y = tile_y << 3 | y_in_tile;
+        if flip_v {
+            y = 31 - y;
+        }
         for x_in_tile in 0..8 {
-            pixels[dst_tile_line_base | x_in_tile] =
-                palette[(src_line >> (x_in_tile << 2)) as usize & 0xF];
+            let mut x = tile_x << 3 | x_in_tile;
+            if flip_h {
+                x = 31 - x;
+            }
+            pixels[y << 5 | x] = palette[(src_line >> (x_in_tile << 2)) as usize & 0xF];
         }
     }
-    Some(pixels)
+    pixels
+}
+
+pub fn decode_to_rgba8(
+    icon_title_offset: usize,
+    rom_contents: &mut impl Contents,
+) -> Option<[u32; 32 * 32]> {
+    let mut icon_data = Bytes::new([0; 0x220]);
+    if icon_title_offset + 0x240 > rom_contents.len() {
+        return None;
+    }
+    rom_contents.read_slice(icon_title_offset + 0x20, &mut *icon_data);
+
+    let palette = decode_palette(read_le_array::<u16, 16>(&*icon_data, 0x200));
+    Some(decode_bitmap(&icon_data[..0x200], &palette, false, false))
+}
+
+/// Like [`decode_to_rgba8`], but reads the icon directly from `reader` (i.e. a ROM file) instead
+/// of going through [`Contents`].
+pub fn decode_from_reader(
+    mut reader: impl Read + Seek,
+    icon_title_offset: usize,
+) -> Option<[u32; 32 * 32]> {
+    let mut icon_data = Bytes::new([0; 0x220]);
+    reader
+        .seek(SeekFrom::Start(icon_title_offset as u64 + 0x20))
+        .ok()?;
+    reader.read_exact(&mut *icon_data).ok()?;
+
+    let palette = decode_palette(read_le_array::<u16, 16>(&*icon_data, 0x200));
+    Some(decode_bitmap(&icon_data[..0x200], &palette, false, false))
+}
+
+pub struct AnimationFrame {
+    pub pixels: [u32; 32 * 32],
+    /// The frame's duration, in 60 Hz frames.
+    pub duration: u8,
+}
+
+/// Decodes the animated icon present in the banners of DSi-enhanced titles (banner version
+/// 0x0103), returning `None` for still icons.
+///
+/// Each sequence token selects one of the 8 bitmaps and one of the 8 palettes independently (along
+/// with its flip flags), so frames can reuse a bitmap with a different palette.
+pub fn decode_animated_to_rgba8(
+    icon_title_offset: usize,
+    rom_contents: &mut impl Contents,
+) -> Option<Vec<AnimationFrame>> {
+    let mut version = [0; 2];
+    if icon_title_offset + 0x23C0 > rom_contents.len() {
+        return None;
+    }
+    rom_contents.read_slice(icon_title_offset, &mut version);
+    if u16::from_le_bytes(version) < 0x0103 {
+        return None;
+    }
+
+    let mut anim_data = Bytes::new([0; 0x1180]);
+    rom_contents.read_slice(icon_title_offset + 0x1240, &mut *anim_data);
+    let palettes: [[u32; 16]; 8] =
+        array::from_fn(|i| decode_palette(read_le_array(&*anim_data, 0x1000 | i << 5)));
+
+    let mut frames = Vec::new();
+    for entry in read_le_array::<u16, 64>(&*anim_data, 0x1100) {
+        let duration = entry as u8;
+        if duration == 0 {
+            break;
+        }
+        let bitmap_start = (entry as usize >> 8 & 7) << 9;
+        frames.push(AnimationFrame {
+            pixels: decode_bitmap(
+                &anim_data[bitmap_start..bitmap_start + 0x200],
+                &palettes[entry as usize >> 11 & 7],
+                entry & 1 << 14 != 0,
+                entry & 1 << 15 != 0,
+            ),
+            duration,
+        });
+    }
+    (!frames.is_empty()).then_some(frames)
+}
+
+#[cfg(feature = "image")]
+fn pixels_to_image(pixels: &[u32; 32 * 32]) -> image::RgbaImage {
+    image::RgbaImage::from_fn(32, 32, |x, y| {
+        image::Rgba(pixels[(y << 5 | x) as usize].to_le_bytes())
+    })
+}
+
+#[cfg(feature = "image")]
+pub fn decode_to_image(
+    icon_title_offset: usize,
+    rom_contents: &mut impl Contents,
+) -> Option<image::RgbaImage> {
+    decode_to_rgba8(icon_title_offset, rom_contents).map(|pixels| pixels_to_image(&pixels))
+}
+
+#[cfg(feature = "image")]
+pub fn decode_animated_to_frames(
+    icon_title_offset: usize,
+    rom_contents: &mut impl Contents,
+) -> Option<Vec<image::Frame>> {
+    decode_animated_to_rgba8(icon_title_offset, rom_contents).map(|frames| {
+        frames
+            .iter()
+            .map(|frame| {
+                image::Frame::from_parts(
+                    pixels_to_image(&frame.pixels),
+                    0,
+                    0,
+                    image::Delay::from_numer_denom_ms(frame.duration as u32 * 1000, 60),
+                )
+            })
+            .collect()
+    })
+}
+
+/// Returns whether the banner's CRC16 for its original (version 1) part, covering the icon and the
+/// first 6 titles, matches its contents.
+pub fn verify_crc(icon_title_offset: usize, rom_contents: &mut impl Contents) -> Option<bool> {
+    if icon_title_offset + 0x840 > rom_contents.len() {
+        return None;
+    }
+    let mut banner = Bytes::new([0; 0x840]);
+    rom_contents.read_slice(icon_title_offset, &mut *banner);
+    Some(crc16(&banner[0x20..]) == banner.read_le::<u16>(2))
 }
 
 pub fn read_header_and_decode_to_rgba8(rom_contents: &mut impl Contents) -> Option<[u32; 32 * 32]> {
@@ -41,3 +171,320 @@ pub fn read_header_and_decode_to_rgba8(rom_contents: &mut impl Contents) -> Opti
     let icon_title_offset = header.icon_title_offset() as usize;
     decode_to_rgba8(icon_title_offset, rom_contents)
 }
+
+/// The languages of the banner's title slots, in slot order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Language {
+    Japanese,
+    English,
+    French,
+    German,
+    Italian,
+    Spanish,
+    Chinese,
+    Korean,
+}
+
+impl Language {
+    pub const ALL: [Language; 8] = [
+        Language::Japanese,
+        Language::English,
+        Language::French,
+        Language::German,
+        Language::Italian,
+        Language::Spanish,
+        Language::Chinese,
+        Language::Korean,
+    ];
+
+    #[inline]
+    pub fn flag(self) -> LanguageSet {
+        LanguageSet::from_bits_truncate(1 << self as u8)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BannerTitles {
+    titles: [Option<String>; 8],
+}
+
+impl BannerTitles {
+    /// Parses the title slots from `titles`, which should contain the first `titles.len() /
+    /// 0x100` slots of the banner (at offset 0x240); missing and blank slots are left empty.
+    pub fn parse(titles: &[u8]) -> Self {
+        BannerTitles {
+            titles: array::from_fn(|i| {
+                let raw_title = titles.get(i << 8..(i + 1) << 8)?;
+                let title = char::decode_utf16(
+                    raw_title
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                        .take_while(|&c| c != 0),
+                )
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect::<String>();
+                (!title.trim().is_empty()).then_some(title)
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, language: Language) -> Option<&str> {
+        self.titles[language as usize].as_deref()
+    }
+
+    /// Returns the full title (usually the game's name, subtitle and publisher on separate lines)
+    /// for the first language in `prefs` that has one, falling back to English and then Japanese.
+    pub fn best_full(&self, prefs: &[Language]) -> Option<&str> {
+        prefs
+            .iter()
+            .chain(&[Language::English, Language::Japanese])
+            .find_map(|&language| self.get(language))
+    }
+
+    /// Returns the first line of the title picked by [`best_full`](Self::best_full), to display
+    /// on a single line, or an empty string if there's none.
+    pub fn best(&self, prefs: &[Language]) -> &str {
+        self.best_full(prefs)
+            .and_then(|title| title.lines().map(str::trim).find(|line| !line.is_empty()))
+            .unwrap_or_default()
+    }
+
+    /// Returns the languages with an actual title of their own, skipping blank slots and ones that
+    /// just repeat the English title (or the title of a previous slot), as unused slots are
+    /// often filled with.
+    pub fn available(&self) -> impl Iterator<Item = Language> + '_ {
+        let english_title = self.get(Language::English);
+        Language::ALL
+            .into_iter()
+            .enumerate()
+            .filter(move |&(i, language)| {
+                let Some(title) = self.get(language) else {
+                    return false;
+                };
+                language == Language::English
+                    || (english_title != Some(title)
+                        && !self.titles[..i]
+                            .iter()
+                            .any(|other| other.as_deref() == Some(title)))
+            })
+            .map(|(_, language)| language)
+    }
+}
+
+/// Reads the title slots present in the banner, depending on its version.
+pub fn read_titles(
+    icon_title_offset: usize,
+    rom_contents: &mut impl Contents,
+) -> Option<BannerTitles> {
+    let mut version = [0; 2];
+    if icon_title_offset + 0x340 > rom_contents.len() {
+        return None;
+    }
+    rom_contents.read_slice(icon_title_offset, &mut version);
+    let slots = match u16::from_le_bytes(version) & 0xFF {
+        0 | 1 => 6,
+        2 => 7,
+        _ => 8,
+    }
+    .min((rom_contents.len() - icon_title_offset - 0x240) >> 8);
+
+    let mut titles = Bytes::new([0; 0x800]);
+    rom_contents.read_slice(icon_title_offset + 0x240, &mut titles[..slots << 8]);
+    Some(BannerTitles::parse(&titles[..slots << 8]))
+}
+
+/// Like [`read_header_and_decode_to_rgba8`], but only reads the banner's titles, skipping the icon
+/// data entirely (i.e. to build a text-only game list).
+pub fn read_header_and_titles_only(rom_contents: &mut impl Contents) -> Option<BannerTitles> {
+    let mut header_bytes = Bytes::new([0; 0x170]);
+    rom_contents.read_header(&mut header_bytes);
+    let header = Header::new(&*header_bytes)?;
+    let icon_title_offset = header.icon_title_offset() as usize;
+    read_titles(icon_title_offset, rom_contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::BoxedByteSlice;
+
+    const BANNER_OFFSET: usize = 0x1000;
+
+    // Encodes `titles` into their slots, as laid out at offset 0x240 of the banner
+    fn title_slots(titles: &[(Language, &str)]) -> [u8; 0x800] {
+        let mut slots = [0; 0x800];
+        for &(language, title) in titles {
+            for (i, c) in title.encode_utf16().enumerate() {
+                slots.write_le((language as usize) << 8 | i << 1, c);
+            }
+        }
+        slots
+    }
+
+    // Returns a ROM with a banner of the given version, whose still icon has a pure red pixel at
+    // (0, 0) and transparent ones everywhere else
+    fn banner_rom(version: u16) -> BoxedByteSlice {
+        let mut rom = BoxedByteSlice::new_zeroed(0x4000);
+        rom.write_le(0x68, BANNER_OFFSET as u32);
+        rom.write_le(BANNER_OFFSET, version);
+        rom[BANNER_OFFSET + 0x20] = 1;
+        rom.write_le::<u16>(BANNER_OFFSET + 0x222, 0x001F);
+        rom
+    }
+
+    #[test]
+    fn decode_still_icon() {
+        let pixels = decode_to_rgba8(BANNER_OFFSET, &mut banner_rom(1)).unwrap();
+        assert_eq!(pixels[0], 0xFF00_00FB);
+        assert!(pixels[1..].iter().all(|&pixel| pixel == 0));
+        assert!(decode_to_rgba8(0x3E00, &mut banner_rom(1)).is_none());
+    }
+
+    #[test]
+    fn decode_still_icon_from_reader() {
+        let mut rom = banner_rom(1);
+        let pixels = decode_from_reader(std::io::Cursor::new(&rom[..]), BANNER_OFFSET).unwrap();
+        assert_eq!(pixels, decode_to_rgba8(BANNER_OFFSET, &mut rom).unwrap());
+        assert_eq!(pixels[0], 0xFF00_00FB);
+        // The icon data ends past the end of the reader
+        assert!(decode_from_reader(std::io::Cursor::new(&rom[..0x1100]), BANNER_OFFSET).is_none());
+    }
+
+    #[test]
+    fn decode_animated_icon() {
+        let mut rom = banner_rom(0x0103);
+        // Bitmap 2 has color 2 at (1, 0), which is pure blue in palette 1
+        rom[BANNER_OFFSET + 0x1640] = 0x20;
+        rom.write_le::<u16>(BANNER_OFFSET + 0x2264, 0x7C00);
+        rom.write_le::<u16>(BANNER_OFFSET + 0x2340, 1 << 11 | 2 << 8 | 0x0A);
+        // The same bitmap and palette, flipped horizontally
+        rom.write_le::<u16>(BANNER_OFFSET + 0x2342, 1 << 14 | 1 << 11 | 2 << 8 | 0x14);
+        let frames = decode_animated_to_rgba8(BANNER_OFFSET, &mut rom).unwrap();
+        assert_eq!(frames.len(), 2);
+
+        assert_eq!(frames[0].duration, 10);
+        assert_eq!(frames[0].pixels[1], 0xFFFB_0000);
+        assert_eq!(
+            frames[0].pixels.iter().filter(|&&pixel| pixel != 0).count(),
+            1
+        );
+        assert_eq!(frames[1].duration, 20);
+        assert_eq!(frames[1].pixels[30], 0xFFFB_0000);
+        assert_eq!(
+            frames[1].pixels.iter().filter(|&&pixel| pixel != 0).count(),
+            1
+        );
+
+        // Banners before version 0x0103 only have a still icon
+        rom.write_le::<u16>(BANNER_OFFSET, 3);
+        assert!(decode_animated_to_rgba8(BANNER_OFFSET, &mut rom).is_none());
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn decode_to_images() {
+        let image = decode_to_image(BANNER_OFFSET, &mut banner_rom(1)).unwrap();
+        assert_eq!(image.dimensions(), (32, 32));
+        assert_eq!(*image.get_pixel(0, 0), image::Rgba([0xFB, 0, 0, 0xFF]));
+        assert_eq!(*image.get_pixel(31, 0), image::Rgba([0; 4]));
+
+        // A single half-second frame, using bitmap 0 with palette 1 (where color 1 is pure green)
+        let mut rom = banner_rom(0x0103);
+        rom[BANNER_OFFSET + 0x1240] = 1;
+        rom.write_le::<u16>(BANNER_OFFSET + 0x2262, 0x03E0);
+        rom.write_le::<u16>(BANNER_OFFSET + 0x2340, 1 << 11 | 0x1E);
+        let frames = decode_animated_to_frames(BANNER_OFFSET, &mut rom).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].buffer().dimensions(), (32, 32));
+        assert_eq!(
+            *frames[0].buffer().get_pixel(0, 0),
+            image::Rgba([0, 0xFB, 0, 0xFF])
+        );
+        assert_eq!(
+            std::time::Duration::from(frames[0].delay()),
+            std::time::Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn available_languages() {
+        let titles = BannerTitles::parse(&title_slots(&[
+            (Language::Japanese, "Geemu"),
+            (Language::English, "Game"),
+            // Unused slots repeating the English title don't count
+            (Language::French, "Game"),
+            (Language::German, "Game"),
+        ]));
+        assert_eq!(
+            titles.available().collect::<Vec<_>>(),
+            [Language::Japanese, Language::English]
+        );
+        assert_eq!(BannerTitles::default().available().count(), 0);
+    }
+
+    #[test]
+    fn best_title() {
+        let titles = BannerTitles::parse(&title_slots(&[
+            (Language::Japanese, "Geemu\nPaburisshaa"),
+            (Language::English, "\n  Game \nSubtitle\nPublisher"),
+            (Language::German, "Spiel\nHerausgeber"),
+        ]));
+        // The first preferred language with a title is picked, skipping missing ones
+        let prefs = [Language::French, Language::German, Language::English];
+        assert_eq!(titles.best(&prefs), "Spiel");
+        assert_eq!(titles.best_full(&prefs), Some("Spiel\nHerausgeber"));
+        // Without any available preferred language, English is used before Japanese, with blank
+        // lines skipped in the single-line title
+        let prefs = [Language::Spanish, Language::Korean];
+        assert_eq!(titles.best(&prefs), "Game");
+        assert_eq!(
+            titles.best_full(&prefs),
+            Some("\n  Game \nSubtitle\nPublisher")
+        );
+        assert_eq!(titles.best(&[Language::Japanese]), "Geemu");
+
+        let japanese_only = BannerTitles::parse(&title_slots(&[(Language::Japanese, "Geemu")]));
+        assert_eq!(japanese_only.best(&[Language::French]), "Geemu");
+        assert_eq!(BannerTitles::default().best(&[Language::English]), "");
+        assert_eq!(BannerTitles::default().best_full(&[]), None);
+    }
+
+    #[test]
+    fn titles_only() {
+        let mut rom = banner_rom(2);
+        let slots = title_slots(&[
+            (Language::Japanese, "Geemu"),
+            (Language::English, "Game\nSubtitle\nPublisher"),
+            (Language::Chinese, "Youxi"),
+            (Language::Korean, "Geim"),
+        ]);
+        rom[BANNER_OFFSET + 0x240..BANNER_OFFSET + 0xA40].copy_from_slice(&slots);
+
+        // Version 2 banners have 7 slots, so the Korean one isn't read
+        let full = BannerTitles::parse(&rom[BANNER_OFFSET + 0x240..BANNER_OFFSET + 0x940]);
+        let titles = read_header_and_titles_only(&mut rom).unwrap();
+        assert_eq!(titles, full);
+        assert_eq!(
+            titles.get(Language::English),
+            Some("Game\nSubtitle\nPublisher")
+        );
+        assert_eq!(titles.get(Language::Chinese), Some("Youxi"));
+        assert_eq!(titles.get(Language::Korean), None);
+        assert!(read_header_and_decode_to_rgba8(&mut rom).is_some());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_titles_to_json() {
+        let titles = BannerTitles::parse(&title_slots(&[(Language::English, "Game\nPublisher")]));
+        let json = serde_json::to_value(titles).unwrap();
+        assert_eq!(json["titles"][0], serde_json::Value::Null);
+        assert_eq!(json["titles"][1], "Game\nPublisher");
+        assert_eq!(json["titles"][2], serde_json::Value::Null);
+        assert_eq!(serde_json::to_value(Language::English).unwrap(), "english");
+    }
+}
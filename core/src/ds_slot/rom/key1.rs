@@ -1,8 +1,46 @@
+use super::read_le_array;
 use crate::{
     cpu::arm7,
     utils::{mem_prelude::*, Bytes},
 };
 
+/// The first 8 bytes of a decrypted secure area (two `udf` instructions), which the BIOS
+/// substitutes for the "encryObj" ID after a successful decryption.
+pub const DECRYPTED_SECURE_AREA_ID: u64 = 0xE7FF_DEFF_E7FF_DEFF;
+
+/// The magic stored (encrypted) in the header's secure area disable field by carts that skip
+/// secure area encryption altogether, i.e. some development carts.
+pub const SECURE_AREA_DISABLE_MAGIC: &[u8; 8] = b"NmMdOnly";
+
+/// The ID the first 8 bytes of the secure area are replaced with before encrypting it; a secure
+/// area that starts with it in plain text was already encrypted (i.e. by a previous setup).
+pub const ENCRYPTED_SECURE_AREA_ID: &[u8; 8] = b"encryObj";
+
+/// Returns whether a secure area is already decrypted, going by its first 8 bytes being
+/// [`DECRYPTED_SECURE_AREA_ID`].
+pub fn is_secure_area_decrypted(secure_area: &[u8]) -> bool {
+    secure_area.read_le::<u64>(0) == DECRYPTED_SECURE_AREA_ID
+}
+
+// Calls `f` on each 8-byte block of a 0x800-byte secure area; as blocks are encrypted independently
+// at level 3, this is done in parallel if the `rayon` feature is enabled.
+fn for_each_block(secure_area: &mut [u8], f: impl Fn(&mut [u8]) + Send + Sync) {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        secure_area[..0x800].par_chunks_exact_mut(8).for_each(f);
+    }
+    #[cfg(not(feature = "rayon"))]
+    for_each_block_serial(secure_area, f);
+}
+
+// The serial version of `for_each_block`, kept available with `rayon` to check the parallel one
+// against
+#[cfg_attr(all(feature = "rayon", not(test)), allow(dead_code))]
+fn for_each_block_serial(secure_area: &mut [u8], f: impl Fn(&mut [u8])) {
+    secure_area[..0x800].chunks_exact_mut(8).for_each(f);
+}
+
 #[derive(Clone)]
 pub struct KeyBuffer<const LEVEL_3: bool> {
     key_buf: [u32; 0x412],
@@ -14,11 +52,15 @@ impl<const LEVEL_3: bool> KeyBuffer<LEVEL_3> {
         id_code: u32,
         arm7_bios: &Bytes<{ arm7::BIOS_SIZE }>,
     ) -> Box<Self> {
+        Self::from_key_table::<MODULO>(id_code, &read_le_array(&**arm7_bios, 0x30))
+    }
+
+    /// Initializes the key buffer from a level 0 key table supplied directly instead of the one
+    /// stored in the ARM7 BIOS at 0x30 (i.e. for development carts using different keys).
+    pub fn from_key_table<const MODULO: usize>(id_code: u32, table: &[u32; 0x412]) -> Box<Self> {
         let mut result = unsafe { Box::<Self>::new_zeroed().assume_init() };
         result.key_code = [id_code, id_code >> 1, id_code << 1];
-        for (i, word) in result.key_buf.iter_mut().enumerate() {
-            *word = arm7_bios.read_le(0x30 + (i << 2));
-        }
+        result.key_buf = *table;
         result.apply_key_code::<MODULO>();
         result.apply_key_code::<MODULO>();
         result
@@ -80,4 +122,145 @@ impl KeyBuffer<false> {
         result.apply_key_code::<MODULO>();
         result
     }
+
+    /// Decrypts a 0x800-byte secure area in place (`self` should be at level 2), replacing the
+    /// "encryObj" ID with [`DECRYPTED_SECURE_AREA_ID`] if decryption succeeded.
+    pub fn decrypt_secure_area(&self, secure_area: &mut [u8]) {
+        let res = self.decrypt_64_bit([secure_area.read_le(0), secure_area.read_le(4)]);
+        secure_area.write_le(0, res[0]);
+        secure_area.write_le(4, res[1]);
+
+        let level_3_key_buf = self.level_3::<2>();
+        for_each_block(secure_area, |block| {
+            let res = level_3_key_buf.decrypt_64_bit([block.read_le(0), block.read_le(4)]);
+            block.write_le(0, res[0]);
+            block.write_le(4, res[1]);
+        });
+
+        if &secure_area[..8] == ENCRYPTED_SECURE_AREA_ID {
+            secure_area.write_le(0, DECRYPTED_SECURE_AREA_ID);
+        }
+    }
+
+    /// Returns whether the first block of an encrypted secure area decrypts to the "encryObj" ID,
+    /// without modifying it; if it doesn't, the ARM7 BIOS the KEY1 tables were loaded from is
+    /// most likely patched or not a DS one (or the secure area is corrupted).
+    pub fn can_decrypt_secure_area(&self, secure_area: &[u8]) -> bool {
+        let res = self.decrypt_64_bit([secure_area.read_le(0), secure_area.read_le(4)]);
+        let res = self.level_3::<2>().decrypt_64_bit(res);
+        let mut id = [0; 8];
+        id.write_le(0, res[0]);
+        id.write_le(4, res[1]);
+        &id == ENCRYPTED_SECURE_AREA_ID
+    }
+
+    /// Returns whether the header's secure area disable field (see
+    /// [`Header::secure_area_disable`](super::header::Header::secure_area_disable)) decrypts to
+    /// [`SECURE_AREA_DISABLE_MAGIC`] (`self` should be at level 2); dumps that already store it in
+    /// plain text are also accepted.
+    pub fn is_secure_area_disabled(&self, field: &[u8; 8]) -> bool {
+        if field == SECURE_AREA_DISABLE_MAGIC {
+            return true;
+        }
+        let res = self.decrypt_64_bit([field.read_le(0), field.read_le(4)]);
+        let mut magic = [0; 8];
+        magic.write_le(0, res[0]);
+        magic.write_le(4, res[1]);
+        &magic == SECURE_AREA_DISABLE_MAGIC
+    }
+
+    /// Encrypts a decrypted 0x800-byte secure area in place (`self` should be at level 2),
+    /// replacing its first 8 bytes with the "encryObj" ID.
+    pub fn encrypt_secure_area(&self, secure_area: &mut [u8]) {
+        secure_area[..8].copy_from_slice(ENCRYPTED_SECURE_AREA_ID);
+        let level_3_key_buf = self.level_3::<2>();
+        for_each_block(secure_area, |block| {
+            let res = level_3_key_buf.encrypt_64_bit([block.read_le(0), block.read_le(4)]);
+            block.write_le(0, res[0]);
+            block.write_le(4, res[1]);
+        });
+        let res = self.encrypt_64_bit([secure_area.read_le(0), secure_area.read_le(4)]);
+        secure_area.write_le(0, res[0]);
+        secure_area.write_le(4, res[1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::tests::test_arm7_bios, *};
+    use core::array;
+    use proptest::prelude::*;
+    use std::sync::OnceLock;
+
+    // Built once from a synthetic level 0 key table (as the real one can't be distributed), and
+    // shared between all cases to keep them fast
+    fn key_buf() -> &'static KeyBuffer<false> {
+        static KEY_BUF: OnceLock<Box<KeyBuffer<false>>> = OnceLock::new();
+        KEY_BUF.get_or_init(|| {
+            let table = array::from_fn(|i| (i as u32).wrapping_mul(0x9E37_79B9) ^ 0x5A5A_A5A5);
+            KeyBuffer::from_key_table::<2>(u32::from_le_bytes(*b"ABCE"), &table)
+        })
+    }
+
+    fn level_3_key_buf() -> &'static KeyBuffer<true> {
+        static KEY_BUF: OnceLock<KeyBuffer<true>> = OnceLock::new();
+        KEY_BUF.get_or_init(|| key_buf().level_3::<2>())
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(block: [u32; 2]) {
+            let key_buf = key_buf();
+            prop_assert_eq!(key_buf.decrypt_64_bit(key_buf.encrypt_64_bit(block)), block);
+            prop_assert_eq!(key_buf.encrypt_64_bit(key_buf.decrypt_64_bit(block)), block);
+        }
+
+        #[test]
+        fn level_3_round_trip(block: [u32; 2]) {
+            let key_buf = level_3_key_buf();
+            prop_assert_eq!(key_buf.decrypt_64_bit(key_buf.encrypt_64_bit(block)), block);
+            prop_assert_eq!(key_buf.encrypt_64_bit(key_buf.decrypt_64_bit(block)), block);
+        }
+
+        #[test]
+        fn secure_area_round_trip(mut secure_area in prop::collection::vec(any::<u8>(), 0x800)) {
+            secure_area.write_le(0, DECRYPTED_SECURE_AREA_ID);
+            let original = secure_area.clone();
+            let key_buf = key_buf();
+            key_buf.encrypt_secure_area(&mut secure_area);
+            prop_assert!(key_buf.can_decrypt_secure_area(&secure_area));
+            key_buf.decrypt_secure_area(&mut secure_area);
+            prop_assert_eq!(secure_area, original);
+        }
+
+        #[cfg(feature = "rayon")]
+        #[test]
+        fn parallel_matches_serial(secure_area in prop::collection::vec(any::<u8>(), 0x800)) {
+            let key_buf = level_3_key_buf();
+            let encrypt_block = |block: &mut [u8]| {
+                let res = key_buf.encrypt_64_bit([block.read_le(0), block.read_le(4)]);
+                block.write_le(0, res[0]);
+                block.write_le(4, res[1]);
+            };
+            let mut parallel = secure_area.clone();
+            for_each_block(&mut parallel, encrypt_block);
+            let mut serial = secure_area;
+            for_each_block_serial(&mut serial, encrypt_block);
+            prop_assert_eq!(parallel, serial);
+        }
+    }
+
+    #[test]
+    fn key_table_matches_bios() {
+        // `test_arm7_bios` stores the same synthetic table at 0x30
+        let from_bios =
+            KeyBuffer::<false>::new_boxed::<2>(u32::from_le_bytes(*b"ABCE"), &test_arm7_bios());
+        let from_table = key_buf();
+        assert_eq!(from_bios.key_buf, from_table.key_buf);
+        assert_eq!(from_bios.key_code, from_table.key_code);
+        assert_eq!(
+            from_bios.encrypt_64_bit([0x1234_5678, 0x9ABC_DEF0]),
+            from_table.encrypt_64_bit([0x1234_5678, 0x9ABC_DEF0])
+        );
+    }
 }
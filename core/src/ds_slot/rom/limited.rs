@@ -0,0 +1,125 @@
+use super::Contents;
+use crate::utils::Bytes;
+
+/// What [`LimitedContents`] does when a read exceeds its limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitAction {
+    /// Panics, i.e. to make a fuzzer report the input that caused the read.
+    Panic,
+    /// Only records the read, see [`LimitedContents::oversized_reads`].
+    Flag,
+}
+
+/// A [`Contents`] decorator checking that no single read is larger than a given limit, to catch
+/// command handlers that misbehave (i.e. by looping over the whole ROM) when fuzzing them.
+pub struct LimitedContents<C: Contents> {
+    inner: C,
+    max_read_len: usize,
+    action: LimitAction,
+    oversized_reads: u64,
+    largest_read: usize,
+}
+
+impl<C: Contents> LimitedContents<C> {
+    pub fn new(inner: C, max_read_len: usize, action: LimitAction) -> Self {
+        LimitedContents {
+            inner,
+            max_read_len,
+            action,
+            oversized_reads: 0,
+            largest_read: 0,
+        }
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    #[inline]
+    pub fn max_read_len(&self) -> usize {
+        self.max_read_len
+    }
+
+    /// Returns the number of reads that exceeded the limit so far.
+    #[inline]
+    pub fn oversized_reads(&self) -> u64 {
+        self.oversized_reads
+    }
+
+    /// Returns the length of the largest read so far.
+    #[inline]
+    pub fn largest_read(&self) -> usize {
+        self.largest_read
+    }
+
+    fn check(&mut self, addr: usize, len: usize) {
+        self.largest_read = self.largest_read.max(len);
+        if len <= self.max_read_len {
+            return;
+        }
+        self.oversized_reads += 1;
+        assert!(
+            self.action != LimitAction::Panic,
+            "ROM read of {len:#X} bytes at {addr:#X} exceeds the limit of {:#X} bytes",
+            self.max_read_len
+        );
+    }
+}
+
+impl<C: Contents> Contents for LimitedContents<C> {
+    forward_contents_to_inner!(
+        len,
+        actual_len,
+        game_code,
+        secure_area_mut,
+        secure_area,
+        prefetch_secure_area,
+        dldi_area_mut,
+        twl_secure_area_mut,
+        read_header
+    );
+
+    fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+        self.check(addr, output.len());
+        self.inner.read_slice(addr, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::BoxedByteSlice;
+
+    #[test]
+    fn oversized_read_is_flagged() {
+        let mut contents =
+            LimitedContents::new(BoxedByteSlice::new_zeroed(0x8000), 0x200, LimitAction::Flag);
+        let mut output = vec![0xFF; 0x200];
+        contents.read_slice(0x1000, &mut output);
+        assert_eq!(contents.oversized_reads(), 0);
+
+        output.resize(0x201, 0);
+        contents.read_slice(0x1000, &mut output);
+        assert_eq!(contents.oversized_reads(), 1);
+        assert_eq!(contents.largest_read(), 0x201);
+        // Reads still go through to the inner contents
+        assert!(output.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds the limit")]
+    fn oversized_read_panics() {
+        let mut contents = LimitedContents::new(
+            BoxedByteSlice::new_zeroed(0x8000),
+            0x200,
+            LimitAction::Panic,
+        );
+        contents.read_slice(0, &mut [0; 0x400]);
+    }
+}
@@ -1,26 +1,115 @@
-use super::{super::RomOutputLen, is_valid_size, key1, Contents};
+use super::{
+    super::RomOutputLen, apply_overlay, header::Header, is_valid_size, key1, new_secure_area_buf,
+    quirks, Contents, SetupError,
+};
 use crate::{
     cpu::arm7,
-    utils::{make_zero, mem_prelude::*, zero, Bytes, Savestate},
+    utils::{make_zero, mem_prelude::*, zero, Bytes, ReadSavestate, Savestate, WriteSavestate},
     Model,
 };
 
+/// The layout version of [`Normal`]'s savestate data; must be bumped whenever its saved fields
+/// change. It's stored before all other fields, and loading a savestate with a different version
+/// fails right away with the [`ReadSavestate::invalid_enum`] error, instead of misinterpreting the
+/// rest of the data; the versions that didn't match can then be retrieved through
+/// [`Normal::savestate_version_mismatch`].
+pub const SAVESTATE_VERSION: u32 = 2;
+
+/// Returned by [`check_savestate_version`] for data saved with a different layout version, i.e. by
+/// an older build.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SavestateVersionMismatch {
+    pub found: u32,
+    pub expected: u32,
+}
+
+/// Checks the layout version stored at the start of [`Normal`]'s savestate data.
+///
+/// # Errors
+/// - [`SavestateVersionMismatch`]: `found` isn't [`SAVESTATE_VERSION`].
+pub fn check_savestate_version(found: u32) -> Result<(), SavestateVersionMismatch> {
+    if found == SAVESTATE_VERSION {
+        Ok(())
+    } else {
+        Err(SavestateVersionMismatch {
+            found,
+            expected: SAVESTATE_VERSION,
+        })
+    }
+}
+
+fn load_savestate_version<S: ReadSavestate>(
+    save: &mut S,
+    mismatch: &mut Option<SavestateVersionMismatch>,
+) -> Result<u32, S::Error> {
+    let version = save.load_raw::<u32>()?;
+    // The savestate error type can't carry the mismatch itself, so it's kept around for
+    // `Normal::savestate_version_mismatch`
+    *mismatch = check_savestate_version(version).err();
+    if mismatch.is_some() {
+        return Err(S::invalid_enum());
+    }
+    Ok(version)
+}
+
+fn store_savestate_version<S: WriteSavestate>(version: u32, save: &mut S) {
+    save.store_raw(version);
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CreationError {
     InvalidSize,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Savestate)]
-enum Stage {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Savestate)]
+pub enum Stage {
     Initial,
     Key1,
     Key2,
     // Invalid,
 }
 
+/// The gaps programmed through ROMCTRL for KEY1 commands, in CLK pulses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Savestate)]
+pub struct Key1Gaps {
+    /// Applied once after the command is sent.
+    pub gap1: u16,
+    /// Applied before the first word of every 0x200-byte block.
+    pub gap2: u8,
+}
+
+impl Key1Gaps {
+    /// Returns the number of CLK pulses between the start of a command and its first data word
+    /// being ready, as used by the slot controller's transfer timing: the command itself takes 8
+    /// pulses to transfer, and every data byte takes 4 (the DS game card slot can only transfer 8
+    /// bits on every CLK cycle).
+    pub fn first_word_latency(&self, output_len: RomOutputLen) -> u32 {
+        if output_len.get() == 0 {
+            8 + self.gap1 as u32
+        } else {
+            12 + self.gap1 as u32 + self.gap2 as u32
+        }
+    }
+}
+
+/// Returns the chip ID reported by a Macronix ROM chip of the given (power-of-two) size.
+pub fn default_chip_id(len: usize) -> u32 {
+    0x0000_00C2
+        | match len as u32 {
+            0..=0xF_FFFF => 0,
+            len @ 0x10_0000..=0xFFF_FFFF => (len >> 20) - 1,
+            len @ 0x1000_0000..=0xFFFF_FFFF => 0x100 - (len >> 28),
+        }
+}
+
 #[derive(Savestate)]
 #[load(in_place_only)]
 pub struct Normal {
+    #[load(with_in_place = "*savestate_version = load_savestate_version(save, version_mismatch)?")]
+    #[store(with = "store_savestate_version(*savestate_version, save)")]
+    savestate_version: u32,
+    #[savestate(skip)]
+    version_mismatch: Option<SavestateVersionMismatch>,
     #[cfg(feature = "log")]
     #[savestate(skip)]
     logger: slog::Logger,
@@ -32,9 +121,85 @@ pub struct Normal {
     chip_id: u32,
     #[savestate(skip)]
     key_buf: Option<Box<key1::KeyBuffer<false>>>, // Always at level 2
+    #[savestate(skip)]
+    quirk: Option<Box<dyn quirks::CommandQuirk>>,
+    #[savestate(skip)]
+    chip_id_provider: Option<Box<dyn quirks::ChipIdProvider>>,
+    #[savestate(skip)]
+    linear_low_reads: bool,
+    #[savestate(skip)]
+    open_bus_past_end: bool,
+    #[savestate(skip)]
+    strict: bool,
+    #[savestate(skip)]
+    uncertain_responses: u64,
+    // A decrypted/encrypted copy of the secure area and its start address, for contents that only
+    // expose it as read-only
+    #[savestate(skip)]
+    owned_secure_area: Option<(usize, Box<Bytes<0x800>>)>,
+    #[savestate(skip)]
+    secure_area_decryption_failed: bool,
+    key1_gaps: Key1Gaps,
     stage: Stage,
 }
 
+/// Collects the less common options for creating a [`Normal`] ROM on top of the ones taken by
+/// [`Normal::new`]; all of them can also be changed after creation through the setters on `Normal`.
+pub struct NormalBuilder<'a> {
+    #[cfg(feature = "log")]
+    pub logger: slog::Logger,
+    pub contents: Box<dyn Contents>,
+    pub arm7_bios: Option<&'a Bytes<{ arm7::BIOS_SIZE }>>,
+    pub model: Model,
+    /// Overrides the chip ID derived from the ROM's size, see [`Normal::set_chip_id`].
+    pub chip_id: Option<u32>,
+    /// See [`Normal::set_linear_low_reads`].
+    pub linear_low_reads: bool,
+    /// Returns the command quirks to use for a given game code.
+    pub quirk_table: fn(u32) -> Option<Box<dyn quirks::CommandQuirk>>,
+}
+
+impl<'a> NormalBuilder<'a> {
+    #[inline]
+    pub fn new(
+        contents: Box<dyn Contents>,
+        arm7_bios: Option<&'a Bytes<{ arm7::BIOS_SIZE }>>,
+        model: Model,
+        #[cfg(feature = "log")] logger: slog::Logger,
+    ) -> Self {
+        NormalBuilder {
+            #[cfg(feature = "log")]
+            logger,
+            contents,
+            arm7_bios,
+            model,
+            chip_id: None,
+            linear_low_reads: false,
+            quirk_table: quirks::for_game_code,
+        }
+    }
+
+    /// # Errors
+    /// - [`CreationError::InvalidSize`](CreationError::InvalidSize): the ROM contents' size is
+    ///   either not a power of two or too small.
+    pub fn build(self) -> Result<Normal, CreationError> {
+        let game_code = self.contents.game_code();
+        let mut rom = Normal::new(
+            self.contents,
+            self.arm7_bios,
+            self.model,
+            #[cfg(feature = "log")]
+            self.logger,
+        )?;
+        if let Some(chip_id) = self.chip_id {
+            rom.set_chip_id(chip_id);
+        }
+        rom.set_linear_low_reads(self.linear_low_reads);
+        rom.set_command_quirk((self.quirk_table)(game_code));
+        Ok(rom)
+    }
+}
+
 impl Normal {
     /// # Errors
     /// - [`CreationError::InvalidSize`](CreationError::InvalidSize): the ROM contents' size is
@@ -50,20 +215,26 @@ impl Normal {
             return Err(CreationError::InvalidSize);
         }
         let rom_mask = (len - 1) as u32;
-        let chip_id = 0x0000_00C2
-            | match len as u32 {
-                0..=0xF_FFFF => 0,
-                len @ 0x10_0000..=0xFFF_FFFF => (len >> 20) - 1,
-                len @ 0x1000_0000..=0xFFFF_FFFF => 0x100 - (len >> 28),
-            };
+        let chip_id = default_chip_id(len);
         let game_code = contents.game_code();
         Ok(Normal {
+            savestate_version: SAVESTATE_VERSION,
+            version_mismatch: None,
             #[cfg(feature = "log")]
             logger,
             contents,
             rom_mask,
             chip_id,
             key_buf: arm7_bios.map(|bios| key1::KeyBuffer::new_boxed::<2>(game_code, bios)),
+            quirk: quirks::for_game_code(game_code),
+            chip_id_provider: None,
+            linear_low_reads: false,
+            open_bus_past_end: false,
+            strict: false,
+            uncertain_responses: 0,
+            owned_secure_area: None,
+            secure_area_decryption_failed: false,
+            key1_gaps: Key1Gaps::default(),
             stage: Stage::Initial,
         })
     }
@@ -76,11 +247,277 @@ impl Normal {
         &mut *self.contents
     }
 
+    /// Returns the layout versions that didn't match if the last savestate load failed because of
+    /// them (see [`SAVESTATE_VERSION`]).
+    #[inline]
+    pub fn savestate_version_mismatch(&self) -> Option<SavestateVersionMismatch> {
+        self.version_mismatch
+    }
+
+    #[inline]
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// Forces the command encryption stage, i.e. to replay commands captured in a specific one.
+    #[inline]
+    pub fn set_stage(&mut self, stage: Stage) {
+        self.stage = stage;
+    }
+
+    /// Overrides the chip ID returned by the chip ID commands (which is normally derived from the
+    /// ROM's size, see [`default_chip_id`]).
+    #[inline]
+    pub fn set_chip_id(&mut self, chip_id: u32) {
+        self.chip_id = chip_id;
+    }
+
+    #[inline]
+    pub fn set_chip_id_provider(&mut self, provider: Option<Box<dyn quirks::ChipIdProvider>>) {
+        self.chip_id_provider = provider;
+    }
+
+    /// Returns whether the header's secure area disable field is set, in which case the secure
+    /// area is neither encrypted nor decrypted during setup.
+    pub fn is_secure_area_disabled(&mut self) -> bool {
+        let mut header_bytes = zero();
+        self.contents.read_header(&mut header_bytes);
+        let Some(header) = Header::parse(&header_bytes) else {
+            return false;
+        };
+        let field = header.secure_area_disable();
+        match &self.key_buf {
+            Some(key_buf) => key_buf.is_secure_area_disabled(field),
+            None => field == key1::SECURE_AREA_DISABLE_MAGIC,
+        }
+    }
+
+    fn current_chip_id(&self) -> u32 {
+        match &self.chip_id_provider {
+            Some(provider) => provider.chip_id(self.stage, self.chip_id),
+            None => self.chip_id,
+        }
+    }
+
+    #[inline]
+    pub fn set_command_quirk(&mut self, quirk: Option<Box<dyn quirks::CommandQuirk>>) {
+        self.quirk = quirk;
+    }
+
+    /// Sets whether KEY2 data reads (command 0xB7) below 0x8000 read the ROM linearly, as some
+    /// flashcarts do, instead of being redirected to `0x8000 | (addr & 0x1FF)` like on retail
+    /// carts (the default).
+    #[inline]
+    pub fn set_linear_low_reads(&mut self, value: bool) {
+        self.linear_low_reads = value;
+    }
+
+    /// Sets whether reads between the end of the ROM data and the end of the addressable range
+    /// (which only differ for trimmed ROMs) return open bus (0xFF) like on retail carts, instead of
+    /// whatever the contents return as padding (the default).
+    #[inline]
+    pub fn set_open_bus_past_end(&mut self, value: bool) {
+        self.open_bus_past_end = value;
+    }
+
+    /// Sets whether to count commands whose response isn't known (unrecognized ones, and ones
+    /// with a TODO about their return value), i.e. so that accuracy tests can fail on them.
+    #[inline]
+    pub fn set_strict(&mut self, value: bool) {
+        self.strict = value;
+    }
+
+    /// Returns the number of commands with an unknown response handled in strict mode so far.
+    #[inline]
+    pub fn uncertain_responses(&self) -> u64 {
+        self.uncertain_responses
+    }
+
+    #[inline]
+    fn note_uncertain_response(&mut self) {
+        if self.strict {
+            self.uncertain_responses += 1;
+        }
+    }
+
+    /// Returns the gaps last programmed while in the KEY1 stage.
+    #[inline]
+    pub fn key1_gaps(&self) -> Key1Gaps {
+        self.key1_gaps
+    }
+
+    /// Called by the slot controller with the gaps configured in ROMCTRL before every command;
+    /// they're only recorded while in the KEY1 stage, as other stages use their own values.
+    #[inline]
+    pub fn configure_key1_gaps(&mut self, gaps: Key1Gaps) {
+        if self.stage == Stage::Key1 {
+            self.key1_gaps = gaps;
+        }
+    }
+
+    fn handle_quirk(
+        &mut self,
+        cmd: &Bytes<8>,
+        output: &mut Bytes<0x4000>,
+        output_len: RomOutputLen,
+    ) -> bool {
+        let stage = self.stage;
+        self.quirk
+            .as_mut()
+            .is_some_and(|quirk| quirk.handle(stage, cmd, output, output_len))
+    }
+
+    pub(super) fn key_buf(&self) -> Option<&key1::KeyBuffer<false>> {
+        self.key_buf.as_deref()
+    }
+
+    /// Returns whether the cart is homebrew, see [`super::is_homebrew`].
+    #[inline]
+    pub fn is_homebrew(&mut self) -> bool {
+        super::is_homebrew(&mut *self.contents)
+    }
+
+    /// Returns the secure area for reading only, without copying it for read-only contents unless
+    /// it's already been modified.
+    fn secure_area<'a>(
+        contents: &'a mut dyn Contents,
+        owned_secure_area: Option<&'a (usize, Box<Bytes<0x800>>)>,
+    ) -> Option<&'a [u8]> {
+        match owned_secure_area {
+            Some((_, secure_area)) => Some(&secure_area[..]),
+            None => contents.secure_area(),
+        }
+    }
+
+    /// Returns the secure area to decrypt or encrypt in place; for contents that only provide
+    /// read-only access to it, this is a copy that's applied over reads instead. Takes the fields
+    /// separately so that the key buffer can still be borrowed alongside the result.
+    fn secure_area_mut<'a>(
+        contents: &'a mut dyn Contents,
+        owned_secure_area: &'a mut Option<(usize, Box<Bytes<0x800>>)>,
+    ) -> Option<&'a mut [u8]> {
+        if owned_secure_area.is_none() && contents.secure_area_mut().is_none() {
+            let mut header_bytes = zero();
+            contents.read_header(&mut header_bytes);
+            let start = Header::parse(&header_bytes)?.arm9_rom_offset() as usize;
+            let secure_area = contents.secure_area()?;
+            let mut buf = new_secure_area_buf();
+            let len = secure_area.len().min(0x800);
+            buf[..len].copy_from_slice(&secure_area[..len]);
+            *owned_secure_area = Some((start, buf));
+        }
+        match owned_secure_area {
+            Some((_, secure_area)) => Some(&mut secure_area[..]),
+            None => contents.secure_area_mut(),
+        }
+    }
+
+    // Returns whether the secure area starts within the ROM data, but extends past its end
+    fn is_secure_area_truncated(&mut self) -> bool {
+        let mut header_bytes = zero();
+        self.contents.read_header(&mut header_bytes);
+        Header::parse(&header_bytes).is_some_and(|header| {
+            let start = header.arm9_rom_offset() as usize;
+            let len = self.contents.actual_len();
+            start < len && start + 0x800 > len
+        })
+    }
+
+    /// Returns whether direct booting needs the ARM7 BIOS' KEY1 tables, i.e. whether the cart's
+    /// secure area is still encrypted (homebrew doesn't have one).
+    pub fn requires_arm7_bios_for_boot(&mut self) -> bool {
+        !self.is_homebrew()
+            && Self::secure_area(&mut *self.contents, self.owned_secure_area.as_ref())
+                .is_some_and(|secure_area| !key1::is_secure_area_decrypted(secure_area))
+    }
+
+    /// Returns whether the ARM7 BIOS' KEY1 tables can decrypt the cart's secure area, or `None` if
+    /// that can't be checked (no BIOS was provided, the cart is homebrew or its secure area is
+    /// already decrypted).
+    pub fn arm7_bios_matches_cart(&mut self) -> Option<bool> {
+        if self.key_buf.is_none() || self.is_homebrew() {
+            return None;
+        }
+        let secure_area = Self::secure_area(&mut *self.contents, self.owned_secure_area.as_ref())?;
+        if key1::is_secure_area_decrypted(secure_area) {
+            return None;
+        }
+        Some(self.key_buf.as_ref()?.can_decrypt_secure_area(secure_area))
+    }
+
+    /// Returns whether decrypting the secure area for direct boot during the last setup didn't
+    /// produce the expected [`key1::DECRYPTED_SECURE_AREA_ID`] marker, meaning that the wrong KEY1
+    /// tables were used (i.e. because of a bad ARM7 BIOS) and the game will most likely crash.
+    #[inline]
+    pub fn secure_area_decryption_failed(&self) -> bool {
+        self.secure_area_decryption_failed
+    }
+
     #[must_use]
-    pub fn reset(self) -> Self {
-        Normal {
-            stage: Stage::Initial,
-            ..self
+    pub fn reset(mut self) -> Self {
+        self.reset_in_place();
+        self
+    }
+
+    /// Resets the command encryption state (but not the key tables or any other configuration)
+    /// without consuming `self`, like [`reset`](Self::reset).
+    pub fn reset_in_place(&mut self) {
+        self.key1_gaps = Key1Gaps::default();
+        self.stage = Stage::Initial;
+    }
+
+    fn read_contents(&mut self, addr: usize, output: &mut [u8]) {
+        self.contents.read_slice(addr, output);
+        if let Some((start, secure_area)) = &self.owned_secure_area {
+            apply_overlay(*start, &secure_area[..], addr, output);
+        }
+        if self.open_bus_past_end {
+            let valid_len = self
+                .contents
+                .actual_len()
+                .saturating_sub(addr)
+                .min(output.len());
+            output[valid_len..].fill(0xFF);
+        }
+    }
+
+    /// Returns the ROM address a KEY2 data read (command 0xB7) starting at `addr` actually reads
+    /// from.
+    fn data_read_addr(&self, addr: u32) -> usize {
+        let addr = (addr & self.rom_mask) as usize;
+        if addr < 0x8000 && !self.linear_low_reads {
+            0x8000 | (addr & 0x1FF)
+        } else {
+            addr
+        }
+    }
+
+    /// Reads `count` consecutive 512-byte blocks starting at block number `block` into the start
+    /// of `output`, as a sequence of KEY2 data reads (command 0xB7) would; unlike
+    /// [`read`](super::RomDevice::read), this applies the redirection of reads below 0x8000.
+    ///
+    /// # Panics
+    /// Panics if `output` is shorter than `count` blocks.
+    pub fn read_blocks(&mut self, block: u16, count: u16, output: &mut [u8]) {
+        let output = &mut output[..count as usize * 0x200];
+        for (i, block_output) in output.chunks_exact_mut(0x200).enumerate() {
+            let addr = self.data_read_addr((block as u32 + i as u32) << 9);
+            self.read_within_page(addr, block_output);
+        }
+    }
+
+    /// Reads `output.len()` bytes starting at `addr`, wrapping around to the start of the
+    /// 0x1000-byte page `addr` is in (or to the start of the ROM, if it's smaller than that).
+    fn read_within_page(&mut self, mut addr: usize, output: &mut [u8]) {
+        let page_len = (self.rom_mask as usize + 1).min(0x1000);
+        let page_start = addr & !(page_len - 1);
+        let page_end = page_start + page_len;
+        let mut start_i = 0;
+        while start_i < output.len() {
+            let len = (page_end - addr).min(output.len() - start_i);
+            self.read_contents(addr, &mut output[start_i..start_i + len]);
+            addr = page_start;
+            start_i += len;
         }
     }
 }
@@ -91,14 +528,13 @@ impl super::RomDevice for Normal {
         let rom_len = self.rom_mask as usize + 1;
         let first_read_max_len = rom_len - addr;
         if output.len() <= first_read_max_len {
-            self.contents.read_slice(addr, output);
+            self.read_contents(addr, output);
         } else {
-            self.contents
-                .read_slice(addr, &mut output[..first_read_max_len]);
+            self.read_contents(addr, &mut output[..first_read_max_len]);
             let mut i = first_read_max_len;
             while i < output.len() {
                 let end_i = (i + rom_len).min(output.len());
-                self.contents.read_slice(0, &mut output[i..end_i]);
+                self.read_contents(0, &mut output[i..end_i]);
                 i += rom_len;
             }
         }
@@ -109,61 +545,68 @@ impl super::RomDevice for Normal {
     }
 
     fn chip_id(&self) -> u32 {
-        self.chip_id
+        self.current_chip_id()
     }
 
-    fn setup(&mut self, direct_boot: bool) -> Result<(), ()> {
-        let mut buf = zero();
-        self.contents.read_header(&mut buf);
-        let secure_area_start = buf.read_le::<u32>(0x20);
-        let is_homebrew = !(0x4000..0x8000).contains(&secure_area_start);
-
+    fn setup(&mut self, direct_boot: bool) -> Result<(), SetupError> {
+        self.secure_area_decryption_failed = false;
         if direct_boot {
             self.stage = Stage::Key2;
-            if is_homebrew {
-                return Ok(());
-            }
-            let Some(secure_area) = self.contents.secure_area_mut() else {
-                return Ok(());
+        }
+        // Homebrew doesn't use the secure area, avoid loading it at all
+        if self.is_homebrew() || self.is_secure_area_disabled() {
+            return Ok(());
+        }
+
+        // Check whether the secure area needs to be modified at all first, so that read-only
+        // contents don't need to be copied if it's already in the right state
+        let Some(secure_area) =
+            Self::secure_area(&mut *self.contents, self.owned_secure_area.as_ref())
+        else {
+            // ROMs that end before the secure area starts can still boot (as they always could),
+            // but one that's cut off partway can be neither decrypted nor encrypted
+            return if self.is_secure_area_truncated() {
+                Err(SetupError::InvalidSecureArea)
+            } else {
+                Ok(())
             };
-            if secure_area.read_le::<u64>(0) != 0xE7FF_DEFF_E7FF_DEFF {
-                let Some(key_buf) = self.key_buf.as_ref() else {
-                    return Err(());
-                };
+        };
+        // Only a secure area starting with the decrypted marker gets encrypted, so re-loading a
+        // dump that was encrypted before (even one starting with the plain-text "encryObj" ID)
+        // doesn't encrypt it a second time
+        if key1::is_secure_area_decrypted(secure_area) == direct_boot {
+            return Ok(());
+        }
 
-                let res = key_buf.decrypt_64_bit([secure_area.read_le(0), secure_area.read_le(4)]);
-                secure_area.write_le(0, res[0]);
-                secure_area.write_le(4, res[1]);
+        let Some(secure_area) =
+            Self::secure_area_mut(&mut *self.contents, &mut self.owned_secure_area)
+        else {
+            return Err(SetupError::InvalidSecureArea);
+        };
 
-                let level_3_key_buf = key_buf.level_3::<2>();
-                for i in (0..0x800).step_by(8) {
-                    let res = level_3_key_buf
-                        .decrypt_64_bit([secure_area.read_le(i), secure_area.read_le(i + 4)]);
-                    secure_area.write_le(i, res[0]);
-                    secure_area.write_le(i + 4, res[1]);
+        if direct_boot {
+            if secure_area.read_le::<u64>(0) != key1::DECRYPTED_SECURE_AREA_ID {
+                let Some(key_buf) = self.key_buf.as_ref() else {
+                    return Err(SetupError::MissingArm7Bios);
+                };
+                key_buf.decrypt_secure_area(secure_area);
+                self.secure_area_decryption_failed =
+                    secure_area.read_le::<u64>(0) != key1::DECRYPTED_SECURE_AREA_ID;
+                #[cfg(feature = "log")]
+                if self.secure_area_decryption_failed {
+                    slog::warn!(
+                        self.logger,
+                        "Secure area decryption failed, the ARM7 BIOS might not be a valid one"
+                    );
                 }
             }
-        } else {
-            let Some(secure_area) = self.contents.secure_area_mut() else {
-                return Ok(());
+        } else if secure_area.read_le::<u64>(0) == key1::DECRYPTED_SECURE_AREA_ID {
+            // Booting from the firmware needs the secure area to be encrypted, which isn't possible
+            // without the KEY1 tables
+            let Some(key_buf) = self.key_buf.as_ref() else {
+                return Err(SetupError::MissingArm7Bios);
             };
-            let key_buf = self
-                .key_buf
-                .as_ref()
-                .expect("key_buf should be initialized");
-            if secure_area.read_le::<u64>(0) == 0xE7FF_DEFF_E7FF_DEFF {
-                secure_area[..8].copy_from_slice(b"encryObj");
-                let level_3_key_buf = key_buf.level_3::<2>();
-                for i in (0..0x800).step_by(8) {
-                    let res = level_3_key_buf
-                        .encrypt_64_bit([secure_area.read_le(i), secure_area.read_le(i + 4)]);
-                    secure_area.write_le(i, res[0]);
-                    secure_area.write_le(i + 4, res[1]);
-                }
-                let res = key_buf.encrypt_64_bit([secure_area.read_le(0), secure_area.read_le(4)]);
-                secure_area.write_le(0, res[0]);
-                secure_area.write_le(4, res[1]);
-            }
+            key_buf.encrypt_secure_area(secure_area);
         }
         Ok(())
     }
@@ -178,6 +621,9 @@ impl super::RomDevice for Normal {
             Stage::Initial => {
                 #[cfg(feature = "log")]
                 slog::trace!(self.logger, "Raw: {:016X}", cmd.read_be::<u64>(0));
+                if self.handle_quirk(&cmd, output, output_len) {
+                    return;
+                }
                 match cmd[0] {
                     0x9F => {
                         if cmd.read_be::<u64>(0) & 0x00FF_FFFF_FFFF_FFFF == 0 {
@@ -187,19 +633,18 @@ impl super::RomDevice for Normal {
                     }
 
                     0x00 => {
-                        if cmd.read_be::<u64>(0) & 0x00FF_FFFF_FFFF_FFFF == 0 {
-                            for start_i in (0..output_len.get() as usize).step_by(0x1000) {
-                                let len = 0x1000.min(output_len.get() as usize - start_i);
-                                self.contents
-                                    .read_slice(0, &mut output[start_i..start_i + len]);
-                            }
-                            return;
-                        }
+                        // The header command reads from the address in bytes 1-4, wrapping
+                        // within its 0x1000-byte page (or the whole ROM, if it's smaller than
+                        // that) for the whole transfer; the BIOS always uses address 0, which
+                        // returns the header area repeated
+                        let addr = (cmd.read_be::<u32>(1) & self.rom_mask) as usize;
+                        self.read_within_page(addr, &mut output[..output_len.get() as usize]);
+                        return;
                     }
 
                     0x90 => {
                         if cmd.read_be::<u64>(0) & 0x00FF_FFFF_FFFF_FFFF == 0 {
-                            let chip_id = self.chip_id;
+                            let chip_id = self.current_chip_id();
                             for i in (0..output_len.get() as usize).step_by(4) {
                                 output.write_le(i, chip_id);
                             }
@@ -216,6 +661,7 @@ impl super::RomDevice for Normal {
                     _ => {}
                 }
                 // TODO: What value is returned?
+                self.note_uncertain_response();
                 #[cfg(feature = "log")]
                 slog::warn!(
                     self.logger,
@@ -244,17 +690,21 @@ impl super::RomDevice for Normal {
                     cmd.read_be::<u64>(0),
                     prev_cmd.read_be::<u64>(0)
                 );
+                if self.handle_quirk(&cmd, output, output_len) {
+                    return;
+                }
                 // TODO: Handle repeated commands for larger carts (bit 31 of chip ID set)
                 // TODO: Check other command bytes for correctness too
                 match cmd[0] >> 4 {
                     0x4 => {
                         // TODO: What value is returned?
+                        self.note_uncertain_response();
                         output[..output_len.get() as usize].fill(0xFF);
                         return;
                     }
 
                     0x1 => {
-                        let chip_id = self.chip_id;
+                        let chip_id = self.current_chip_id();
                         for i in (0..output_len.get() as usize).step_by(4) {
                             output.write_le(i, chip_id);
                         }
@@ -266,10 +716,8 @@ impl super::RomDevice for Normal {
                         // TODO: What happens if the read goes out of bounds? (Though it can only
                         //       happen for homebrew)
                         let start_addr = 0x4000 | (cmd[2] as usize & 0x30) << 8;
-                        for start_i in (0..output_len.get() as usize).step_by(0x1000) {
-                            let len = (output_len.get() as usize - start_i).min(0x1000);
-                            self.contents
-                                .read_slice(start_addr, &mut output[start_i..start_i + len]);
+                        for range in output_len.chunks(0x1000) {
+                            self.read_contents(start_addr, &mut output[range]);
                         }
                         return;
                     }
@@ -289,6 +737,7 @@ impl super::RomDevice for Normal {
                     _ => {}
                 }
                 // TODO: What value is returned?
+                self.note_uncertain_response();
                 #[cfg(feature = "log")]
                 slog::warn!(
                     self.logger,
@@ -301,39 +750,31 @@ impl super::RomDevice for Normal {
             Stage::Key2 => {
                 #[cfg(feature = "log")]
                 slog::trace!(self.logger, "KEY2: {:016X}", cmd.read_be::<u64>(0));
+                if self.handle_quirk(&cmd, output, output_len) {
+                    return;
+                }
                 match cmd[0] {
                     0xB7 => {
                         // if cmd.read_be::<u32>(4) & 0x00FF_FFFF == 0 {
-                        let mut addr = (cmd.read_be::<u32>(1) & self.rom_mask) as usize;
-                        if addr < 0x8000 {
-                            addr = 0x8000 | (addr & 0x1FF);
-                        }
-                        let page_start = addr & !0xFFF;
-                        let page_end = page_start + 0x1000;
-                        let mut start_i = 0;
-                        while start_i < output_len.get() as usize {
-                            let len = (page_end - addr).min(output_len.get() as usize - start_i);
-                            self.contents
-                                .read_slice(addr, &mut output[start_i..start_i + len]);
-                            addr = page_start;
-                            start_i += len;
-                        }
+                        let addr = self.data_read_addr(cmd.read_be::<u32>(1));
+                        self.read_within_page(addr, &mut output[..output_len.get() as usize]);
                         return;
                         // }
                     }
 
                     0xB8 => {
-                        if cmd.read_be::<u64>(0) & 0x00FF_FFFF_FFFF_FFFF == 0 {
-                            let chip_id = self.chip_id;
-                            for i in (0..output_len.get() as usize).step_by(4) {
-                                output.write_le(i, chip_id);
-                            }
-                            return;
+                        // The remaining command bytes used to be required to be zero, but carts
+                        // seem to ignore them and return the chip ID regardless
+                        let chip_id = self.current_chip_id();
+                        for i in (0..output_len.get() as usize).step_by(4) {
+                            output.write_le(i, chip_id);
                         }
+                        return;
                     }
 
                     _ => {}
                 }
+                self.note_uncertain_response();
                 #[cfg(feature = "log")]
                 slog::warn!(
                     self.logger,
@@ -354,3 +795,801 @@ impl super::RomDevice for Normal {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            is_homebrew,
+            tests::{test_arm7_bios, TrimmedContents},
+            tracing::TracingContents,
+            Overlays, RomDevice,
+        },
+        *,
+    };
+    use crate::utils::{BoxedByteSlice, PersistentReadSavestate, PersistentWriteSavestate};
+    use std::{cell::RefCell, rc::Rc};
+
+    // Returns a ROM of `len` bytes with its ARM9 binary (and so its secure area, if any) starting
+    // at `arm9_rom_offset`, filled with a pattern so that reads from different places differ
+    fn test_rom(len: usize, arm9_rom_offset: u32) -> BoxedByteSlice {
+        let mut rom = BoxedByteSlice::new_zeroed(len);
+        for (i, byte) in rom.iter_mut().enumerate().skip(0x200) {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        rom.write_le(0x20, arm9_rom_offset);
+        rom
+    }
+
+    fn new_normal(rom: BoxedByteSlice) -> Normal {
+        Normal::new(
+            Box::new(rom),
+            None,
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .expect("test ROM should have a valid size")
+    }
+
+    // Encrypts a KEY1 command the way the BIOS does before sending it, to be decrypted by `rom`
+    fn key1_command(rom: &Normal, cmd: [u8; 8]) -> Bytes<8> {
+        let mut cmd = Bytes::new(cmd);
+        let res = rom
+            .key_buf()
+            .expect("KEY1 commands need an ARM7 BIOS")
+            .encrypt_64_bit([cmd.read_be(4), cmd.read_be(0)]);
+        cmd.write_be(4, res[0]);
+        cmd.write_be(0, res[1]);
+        cmd
+    }
+
+    fn read_secure_area(rom: &mut Normal) -> Box<[u8]> {
+        let mut secure_area = vec![0; 0x800].into_boxed_slice();
+        rom.read(0x4000, &mut secure_area);
+        secure_area
+    }
+
+    #[test]
+    fn missing_secure_area_still_boots() {
+        for direct_boot in [false, true] {
+            let mut rom = new_normal(test_rom(0x4000, 0x4000));
+            assert_eq!(rom.setup(direct_boot), Ok(()));
+        }
+    }
+
+    #[test]
+    fn truncated_secure_area_fails_setup() {
+        for direct_boot in [false, true] {
+            let mut rom = new_normal(test_rom(0x8000, 0x7C00));
+            assert_eq!(rom.setup(direct_boot), Err(SetupError::InvalidSecureArea));
+        }
+    }
+
+    #[test]
+    fn missing_arm7_bios_fails_setup() {
+        let mut rom = new_normal(test_rom(0x8000, 0x4000));
+        assert_eq!(rom.setup(true), Err(SetupError::MissingArm7Bios));
+
+        let mut contents = test_rom(0x8000, 0x4000);
+        contents.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+        let mut rom = new_normal(contents);
+        assert_eq!(rom.setup(false), Err(SetupError::MissingArm7Bios));
+    }
+
+    #[test]
+    fn header_command_wraps() {
+        let mut contents = test_rom(0x200, 0);
+        for (i, byte) in contents.iter_mut().enumerate().skip(0x30) {
+            *byte = i as u8 ^ 0x5A;
+        }
+        let expected = contents.to_vec();
+        let mut rom = new_normal(contents);
+        let mut output = zero();
+        rom.handle_rom_command(zero(), &mut output, RomOutputLen::new(0x800));
+        for chunk in output[..0x800].chunks_exact(0x200) {
+            assert_eq!(chunk, &expected[..]);
+        }
+
+        let contents = test_rom(0x8000, 0x4000);
+        let expected = contents[..0x1000].to_vec();
+        let mut rom = new_normal(contents);
+        rom.handle_rom_command(zero(), &mut output, RomOutputLen::new(0x2000));
+        for chunk in output[..0x2000].chunks_exact(0x1000) {
+            assert_eq!(chunk, &expected[..]);
+        }
+    }
+
+    #[test]
+    fn header_command_uses_address() {
+        let contents = test_rom(0x8000, 0x4000);
+        let expected = [&contents[0x1200..0x2000], &contents[0x1000..0x1200]].concat();
+        let mut rom = new_normal(contents);
+        let mut output = zero();
+        let cmd = Bytes::new([0, 0, 0, 0x12, 0, 0, 0, 0]);
+        rom.handle_rom_command(cmd, &mut output, RomOutputLen::new(0x1000));
+        assert_eq!(&output[..0x1000], &expected[..]);
+    }
+
+    #[test]
+    fn chip_id_output_byte_order() {
+        let mut rom = new_normal(test_rom(0x8000, 0x4000));
+        rom.set_chip_id(0x8012_34C2);
+        let mut output = zero();
+        rom.handle_rom_command(
+            Bytes::new([0x90, 0, 0, 0, 0, 0, 0, 0]),
+            &mut output,
+            RomOutputLen::new(8),
+        );
+        assert_eq!(
+            output[..8],
+            [0xC2, 0x34, 0x12, 0x80, 0xC2, 0x34, 0x12, 0x80]
+        );
+
+        rom.set_stage(Stage::Key2);
+        output[..8].fill(0);
+        rom.handle_rom_command(
+            Bytes::new([0xB8, 0, 0, 0, 0, 0, 0, 0]),
+            &mut output,
+            RomOutputLen::new(4),
+        );
+        assert_eq!(output[..4], [0xC2, 0x34, 0x12, 0x80]);
+    }
+
+    #[test]
+    fn key1_chip_id_command() {
+        let mut rom = Normal::new(
+            Box::new(test_rom(0x8000, 0x4000)),
+            Some(&test_arm7_bios()),
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+        rom.set_chip_id(0x8012_34C2);
+        rom.set_stage(Stage::Key1);
+        let cmd = key1_command(&rom, [0x10, 0, 0, 0, 0, 0, 0, 0]);
+        let mut output = zero();
+        rom.handle_rom_command(cmd, &mut output, RomOutputLen::new(8));
+        assert_eq!(
+            output[..8],
+            [0xC2, 0x34, 0x12, 0x80, 0xC2, 0x34, 0x12, 0x80]
+        );
+        assert_eq!(rom.stage(), Stage::Key1);
+    }
+
+    #[test]
+    fn data_read_command_address_byte_order() {
+        let contents = test_rom(0x2_0000, 0x4000);
+        let expected = contents[0x1_8123..0x1_8133].to_vec();
+        let mut rom = new_normal(contents);
+        rom.set_stage(Stage::Key2);
+        let mut cmd = Bytes::new([0; 8]);
+        cmd[0] = 0xB7;
+        cmd[2] = 0x01;
+        cmd[3] = 0x81;
+        cmd[4] = 0x23;
+        assert_eq!(cmd.read_be::<u32>(1), 0x0001_8123);
+        let mut output = zero();
+        rom.handle_rom_command(cmd, &mut output, RomOutputLen::new(0x10));
+        assert_eq!(&output[..0x10], &expected[..]);
+    }
+
+    #[test]
+    fn key1_gaps_latency() {
+        let mut rom = new_normal(test_rom(0x8000, 0x4000));
+        let gaps = Key1Gaps {
+            gap1: 0x8F8,
+            gap2: 0x18,
+        };
+        rom.configure_key1_gaps(gaps);
+        assert_eq!(rom.key1_gaps(), Key1Gaps::default());
+
+        rom.set_stage(Stage::Key1);
+        rom.configure_key1_gaps(gaps);
+        assert_eq!(rom.key1_gaps(), gaps);
+        assert_eq!(
+            rom.key1_gaps().first_word_latency(RomOutputLen::new(0x200)),
+            12 + 0x8F8 + 0x18
+        );
+        assert_eq!(
+            rom.key1_gaps().first_word_latency(RomOutputLen::new(0)),
+            8 + 0x8F8
+        );
+    }
+
+    #[test]
+    fn savestate_version_mismatch() {
+        assert_eq!(check_savestate_version(SAVESTATE_VERSION), Ok(()));
+        assert_eq!(
+            check_savestate_version(SAVESTATE_VERSION + 1),
+            Err(SavestateVersionMismatch {
+                found: SAVESTATE_VERSION + 1,
+                expected: SAVESTATE_VERSION,
+            })
+        );
+    }
+
+    #[test]
+    fn real_savestate_with_bumped_version() {
+        let mut rom = new_normal(test_rom(0x8000, 0x4000));
+        let mut savestate = Vec::new();
+        assert!(PersistentWriteSavestate::new(&mut savestate)
+            .store(&mut rom)
+            .is_ok());
+        assert!(PersistentReadSavestate::new(&savestate)
+            .unwrap()
+            .load_into(&mut rom)
+            .is_ok());
+        assert_eq!(rom.savestate_version_mismatch(), None);
+
+        rom.savestate_version = SAVESTATE_VERSION + 1;
+        let mut savestate = Vec::new();
+        assert!(PersistentWriteSavestate::new(&mut savestate)
+            .store(&mut rom)
+            .is_ok());
+        let mut rom = new_normal(test_rom(0x8000, 0x4000));
+        assert!(PersistentReadSavestate::new(&savestate)
+            .unwrap()
+            .load_into(&mut rom)
+            .is_err());
+        assert_eq!(
+            rom.savestate_version_mismatch(),
+            Some(SavestateVersionMismatch {
+                found: SAVESTATE_VERSION + 1,
+                expected: SAVESTATE_VERSION,
+            })
+        );
+        assert_eq!(rom.savestate_version, SAVESTATE_VERSION);
+    }
+
+    #[test]
+    fn encrypted_id_secure_area_is_left_untouched() {
+        let mut contents = test_rom(0x8000, 0x4000);
+        contents[0x4000..0x4008].copy_from_slice(key1::ENCRYPTED_SECURE_AREA_ID);
+        let expected = contents[0x4000..0x4800].to_vec();
+        let mut rom = new_normal(contents);
+        assert_eq!(rom.setup(false), Ok(()));
+        assert_eq!(&*read_secure_area(&mut rom), &expected[..]);
+    }
+
+    // Contents that load the secure area lazily through reads of the inner ones (like file-backed
+    // contents do), so that tracing them shows whether it was accessed at all
+    struct LazyContents {
+        inner: TracingContents<BoxedByteSlice>,
+        overlays: Overlays,
+    }
+
+    impl Contents for LazyContents {
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        fn game_code(&self) -> u32 {
+            self.inner.game_code()
+        }
+
+        fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+            let inner = &mut self.inner;
+            Some(
+                self.overlays
+                    .secure_area_mut(|addr, output| inner.read_slice(addr, output)),
+            )
+        }
+
+        fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+            let inner = &mut self.inner;
+            Some(
+                self.overlays
+                    .dldi_area_mut(addr, len, |addr, output| inner.read_slice(addr, output)),
+            )
+        }
+
+        fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+            self.inner.read_header(buf);
+        }
+
+        fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+            self.inner.read_slice(addr, output);
+            self.overlays.apply(addr, output);
+        }
+    }
+
+    #[test]
+    fn homebrew_direct_boot_skips_secure_area() {
+        for (arm9_rom_offset, secure_area_read) in [(0x200, false), (0x4000, true)] {
+            let mut contents = test_rom(0x8000, arm9_rom_offset);
+            contents.write_le(arm9_rom_offset as usize, key1::DECRYPTED_SECURE_AREA_ID);
+            let reads = Rc::new(RefCell::new(Vec::new()));
+            let mut inner = TracingContents::new(contents, 9);
+            inner.set_sink(Some(Box::new({
+                let reads = Rc::clone(&reads);
+                move |addr, len| reads.borrow_mut().push((addr, len))
+            })));
+            let mut rom = Normal::new(
+                Box::new(LazyContents {
+                    inner,
+                    overlays: Overlays::new(arm9_rom_offset as usize),
+                }),
+                None,
+                Model::Ds,
+                #[cfg(feature = "log")]
+                slog::Logger::root(slog::Discard, slog::o!()),
+            )
+            .unwrap();
+            reads.borrow_mut().clear();
+
+            assert_eq!(rom.setup(true), Ok(()));
+            let non_header_reads = reads
+                .borrow()
+                .iter()
+                .filter(|&&read| read != (0, 0x170))
+                .count();
+            assert_eq!(non_header_reads, usize::from(secure_area_read));
+        }
+    }
+
+    #[test]
+    fn arm7_bios_requirement() {
+        // Retail dumps have an encrypted secure area
+        let mut rom = new_normal(test_rom(0x8000, 0x4000));
+        assert!(rom.requires_arm7_bios_for_boot());
+
+        let mut contents = test_rom(0x8000, 0x4000);
+        contents.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+        let mut rom = new_normal(contents);
+        assert!(!rom.requires_arm7_bios_for_boot());
+
+        let mut rom = new_normal(test_rom(0x8000, 0x200));
+        assert!(!rom.requires_arm7_bios_for_boot());
+    }
+
+    #[test]
+    fn arm7_bios_matching() {
+        let new_normal_with_bios = |contents: BoxedByteSlice, bios: &Bytes<{ arm7::BIOS_SIZE }>| {
+            Normal::new(
+                Box::new(contents),
+                Some(bios),
+                Model::Ds,
+                #[cfg(feature = "log")]
+                slog::Logger::root(slog::Discard, slog::o!()),
+            )
+            .unwrap()
+        };
+        let bios = test_arm7_bios();
+        let mut contents = test_rom(0x8000, 0x4000);
+        contents.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+        let decrypted = contents.clone();
+        key1::KeyBuffer::<false>::new_boxed::<2>(0, &bios)
+            .encrypt_secure_area(&mut contents[0x4000..0x4800]);
+
+        let mut rom = new_normal_with_bios(contents.clone(), &bios);
+        assert_eq!(rom.arm7_bios_matches_cart(), Some(true));
+
+        // A BIOS with a patched KEY1 table produces the wrong keys
+        let mut altered_bios = test_arm7_bios();
+        altered_bios.write_le(0x40, bios.read_le::<u32>(0x40) ^ 0x8000_0001);
+        let mut rom = new_normal_with_bios(contents.clone(), &altered_bios);
+        assert_eq!(rom.arm7_bios_matches_cart(), Some(false));
+
+        // Nothing can be checked without a BIOS or an encrypted secure area
+        assert_eq!(new_normal(contents).arm7_bios_matches_cart(), None);
+        let mut rom = new_normal_with_bios(decrypted, &bios);
+        assert_eq!(rom.arm7_bios_matches_cart(), None);
+    }
+
+    #[test]
+    fn linear_low_data_reads() {
+        let contents = test_rom(0x1_0000, 0x4000);
+        let expected = contents.to_vec();
+        let mut rom = new_normal(contents);
+        rom.set_stage(Stage::Key2);
+        let mut cmd = Bytes::new([0; 8]);
+        cmd[0] = 0xB7;
+        cmd.write_be::<u32>(1, 0x0000_5120);
+        let mut output = zero();
+
+        // Retail carts redirect reads below 0x8000 to the 0x8000..0x8200 page
+        rom.handle_rom_command(cmd.clone(), &mut output, RomOutputLen::new(0x10));
+        assert_eq!(&output[..0x10], &expected[0x8120..0x8130]);
+
+        rom.set_linear_low_reads(true);
+        rom.handle_rom_command(cmd, &mut output, RomOutputLen::new(0x10));
+        assert_eq!(&output[..0x10], &expected[0x5120..0x5130]);
+    }
+
+    #[test]
+    fn homebrew_detection() {
+        for (arm9_rom_offset, expected) in [
+            (0x4000, false),
+            (0x7FFC, false),
+            (0x200, true),
+            (0x8000, true),
+        ] {
+            let mut contents = test_rom(0x1_0000, arm9_rom_offset);
+            assert_eq!(is_homebrew(&mut contents), expected);
+            assert_eq!(new_normal(contents).is_homebrew(), expected);
+        }
+    }
+
+    #[test]
+    fn open_bus_past_end() {
+        let contents = test_rom(0x5000, 0x4000);
+        let expected = contents.to_vec();
+        let mut rom = Normal::new(
+            Box::new(TrimmedContents(contents)),
+            None,
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+
+        // The gap between the end of the data at 0x5000 and the end of the addressable range at
+        // 0x8000 returns the contents' padding by default
+        let mut output = [0; 0x10];
+        rom.read(0x4FF8, &mut output);
+        assert_eq!(output[..8], expected[0x4FF8..]);
+        assert_eq!(output[8..], [0; 8]);
+
+        rom.set_open_bus_past_end(true);
+        rom.read(0x4FF8, &mut output);
+        assert_eq!(output[..8], expected[0x4FF8..]);
+        assert_eq!(output[8..], [0xFF; 8]);
+        rom.read(0x7FF0, &mut output);
+        assert_eq!(output, [0xFF; 0x10]);
+        // Reads within the data are unaffected
+        rom.read(0x1000, &mut output);
+        assert_eq!(output[..], expected[0x1000..0x1010]);
+    }
+
+    // Contents that only expose the secure area for reading, like memory-mapped files
+    struct ReadOnlyContents(BoxedByteSlice);
+
+    impl Contents for ReadOnlyContents {
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn game_code(&self) -> u32 {
+            self.0.game_code()
+        }
+
+        fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+            None
+        }
+
+        fn secure_area(&mut self) -> Option<&[u8]> {
+            self.0.secure_area_mut().map(|secure_area| &*secure_area)
+        }
+
+        fn dldi_area_mut(&mut self, _addr: usize, _len: usize) -> Option<&mut [u8]> {
+            None
+        }
+
+        fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+            self.0.read_header(buf);
+        }
+
+        fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+            self.0.read_slice(addr, output);
+        }
+    }
+
+    #[test]
+    fn read_only_secure_area_decryption() {
+        let bios = test_arm7_bios();
+        let mut contents = test_rom(0x8000, 0x4000);
+        contents.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+        let decrypted = contents[0x4000..0x4800].to_vec();
+        key1::KeyBuffer::<false>::new_boxed::<2>(0, &bios)
+            .encrypt_secure_area(&mut contents[0x4000..0x4800]);
+        let encrypted = contents[0x4000..0x4800].to_vec();
+        let mut rom = Normal::new(
+            Box::new(ReadOnlyContents(contents)),
+            Some(&bios),
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+
+        assert_eq!(rom.setup(true), Ok(()));
+        assert!(!rom.secure_area_decryption_failed());
+        // The decrypted copy is applied over reads, while the contents themselves are untouched
+        assert_eq!(&*read_secure_area(&mut rom), &decrypted[..]);
+        assert_eq!(rom.contents().secure_area().unwrap(), &encrypted[..]);
+        assert!(!rom.requires_arm7_bios_for_boot());
+    }
+
+    #[test]
+    fn key2_chip_id_ignores_parameters() {
+        let mut rom = new_normal(test_rom(0x8000, 0x4000));
+        rom.set_chip_id(0x8012_34C2);
+        rom.set_stage(Stage::Key2);
+        rom.set_strict(true);
+        let mut output = zero();
+        rom.handle_rom_command(
+            Bytes::new([0xB8, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE]),
+            &mut output,
+            RomOutputLen::new(8),
+        );
+        assert_eq!(
+            output[..8],
+            [0xC2, 0x34, 0x12, 0x80, 0xC2, 0x34, 0x12, 0x80]
+        );
+        assert_eq!(rom.uncertain_responses(), 0);
+    }
+
+    #[test]
+    fn builder_defaults() {
+        let bios = test_arm7_bios();
+        let contents = test_rom(0x1_0000, 0x4000);
+        let expected = contents.to_vec();
+        let mut builder = NormalBuilder::new(
+            Box::new(contents),
+            Some(&bios),
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        );
+        assert_eq!(builder.chip_id, None);
+        assert!(!builder.linear_low_reads);
+        builder.chip_id = Some(0x8012_34C2);
+        let mut rom = builder.build().unwrap();
+
+        assert_eq!(rom.chip_id(), 0x8012_34C2);
+        assert!(rom.key_buf().is_some());
+        assert_eq!(rom.stage(), Stage::Initial);
+        assert_eq!(rom.key1_gaps(), Key1Gaps::default());
+        // Data reads below 0x8000 are still redirected
+        rom.set_stage(Stage::Key2);
+        let mut output = zero();
+        rom.handle_rom_command(
+            Bytes::new([0xB7, 0, 0, 0x10, 0, 0, 0, 0]),
+            &mut output,
+            RomOutputLen::new(0x10),
+        );
+        assert_eq!(&output[..0x10], &expected[0x8000..0x8010]);
+
+        // Without an override, the chip ID is derived from the ROM's size like with `Normal::new`
+        let rom = NormalBuilder::new(
+            Box::new(test_rom(0x1_0000, 0x4000)),
+            None,
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .build()
+        .unwrap();
+        assert_eq!(
+            rom.chip_id(),
+            new_normal(test_rom(0x1_0000, 0x4000)).chip_id()
+        );
+        assert!(rom.key_buf().is_none());
+    }
+
+    #[test]
+    fn strict_mode() {
+        let mut rom = new_normal(test_rom(0x1_0000, 0x4000));
+        rom.set_stage(Stage::Key2);
+        let unknown_cmd = Bytes::new([0xD6, 0, 0, 0, 0, 0, 0, 0]);
+        let mut output = zero::<Bytes<0x4000>>();
+        output[..4].fill(0xFF);
+        rom.handle_rom_command(unknown_cmd.clone(), &mut output, RomOutputLen::new(4));
+        assert_eq!(output[..4], [0; 4]);
+        assert_eq!(rom.uncertain_responses(), 0);
+
+        rom.set_strict(true);
+        rom.handle_rom_command(unknown_cmd.clone(), &mut output, RomOutputLen::new(4));
+        rom.handle_rom_command(unknown_cmd, &mut output, RomOutputLen::new(4));
+        assert_eq!(rom.uncertain_responses(), 2);
+
+        // Known commands aren't counted
+        rom.handle_rom_command(
+            Bytes::new([0xB7, 0, 0, 0x80, 0, 0, 0, 0]),
+            &mut output,
+            RomOutputLen::new(4),
+        );
+        assert_eq!(rom.uncertain_responses(), 2);
+
+        rom.set_strict(false);
+        rom.handle_rom_command(
+            Bytes::new([0xD6, 0, 0, 0, 0, 0, 0, 0]),
+            &mut output,
+            RomOutputLen::new(4),
+        );
+        assert_eq!(rom.uncertain_responses(), 2);
+    }
+
+    #[test]
+    fn reset_in_place() {
+        let mut rom = new_normal(test_rom(0x2_0000, 0x4000));
+        let rom_mask = rom.rom_mask;
+        rom.set_chip_id(0x8012_34C2);
+        rom.set_stage(Stage::Key1);
+        rom.configure_key1_gaps(Key1Gaps {
+            gap1: 0x657,
+            gap2: 0x18,
+        });
+        rom.set_stage(Stage::Key2);
+
+        rom.reset_in_place();
+        assert_eq!(rom.stage(), Stage::Initial);
+        assert_eq!(rom.key1_gaps(), Key1Gaps::default());
+        assert_eq!(rom.rom_mask, rom_mask);
+        assert_eq!(rom.chip_id(), 0x8012_34C2);
+
+        // Commands are interpreted as unencrypted ones again
+        let mut output = zero();
+        rom.handle_rom_command(
+            Bytes::new([0x90, 0, 0, 0, 0, 0, 0, 0]),
+            &mut output,
+            RomOutputLen::new(4),
+        );
+        assert_eq!(output.read_le::<u32>(0), 0x8012_34C2);
+    }
+
+    // Reports a distinct chip ID in every stage, derived from the default one
+    struct StageChipIds;
+
+    impl quirks::ChipIdProvider for StageChipIds {
+        fn chip_id(&self, stage: Stage, default: u32) -> u32 {
+            default
+                | match stage {
+                    Stage::Initial => 0x100,
+                    Stage::Key1 => 0x200,
+                    Stage::Key2 => 0x300,
+                }
+        }
+    }
+
+    #[test]
+    fn chip_id_provider() {
+        let mut rom = Normal::new(
+            Box::new(test_rom(0x1_0000, 0x4000)),
+            Some(&test_arm7_bios()),
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+        rom.set_chip_id(0x8000_00C2);
+        rom.set_chip_id_provider(Some(Box::new(StageChipIds)));
+
+        let mut output = zero();
+        for (stage, cmd, expected) in [
+            (Stage::Initial, [0x90, 0, 0, 0, 0, 0, 0, 0], 0x8000_01C2),
+            (Stage::Key1, [0x10, 0, 0, 0, 0, 0, 0, 0], 0x8000_02C2),
+            (Stage::Key2, [0xB8, 0, 0, 0, 0, 0, 0, 0], 0x8000_03C2),
+        ] {
+            rom.set_stage(stage);
+            let cmd = if stage == Stage::Key1 {
+                key1_command(&rom, cmd)
+            } else {
+                Bytes::new(cmd)
+            };
+            rom.handle_rom_command(cmd, &mut output, RomOutputLen::new(4));
+            assert_eq!(output.read_le::<u32>(0), expected, "{stage:?}");
+            // The ID written to RAM on direct boot comes from the provider too
+            assert_eq!(rom.chip_id(), expected);
+        }
+
+        rom.set_chip_id_provider(None);
+        assert_eq!(rom.chip_id(), 0x8000_00C2);
+    }
+
+    #[test]
+    fn read_blocks() {
+        let mut rom = new_normal(test_rom(0x1_0000, 0x4000));
+        // Blocks 0x47..0x4A span the 0x9000 page boundary
+        let mut blocks = [0; 0x600];
+        rom.read_blocks(0x47, 3, &mut blocks);
+        let mut expected = [0; 0x600];
+        rom.read(0x8E00, &mut expected);
+        assert_eq!(blocks, expected);
+
+        // Each block matches a single KEY2 data read
+        rom.set_stage(Stage::Key2);
+        let mut output = zero();
+        rom.handle_rom_command(
+            Bytes::new([0xB7, 0, 0, 0x90, 0, 0, 0, 0]),
+            &mut output,
+            RomOutputLen::new(0x200),
+        );
+        assert_eq!(output[..0x200], blocks[0x200..0x400]);
+
+        // Blocks below 0x8000 are redirected, unlike byte-level reads
+        rom.read_blocks(1, 1, &mut blocks);
+        rom.read(0x8000, &mut expected[..0x200]);
+        assert_eq!(blocks[..0x200], expected[..0x200]);
+        rom.read(0x200, &mut expected[..0x200]);
+        assert_ne!(blocks[..0x200], expected[..0x200]);
+    }
+
+    #[test]
+    fn disabled_secure_area_is_left_untouched() {
+        let bios = test_arm7_bios();
+        // The test ROM's game code is 0
+        let key_buf = key1::KeyBuffer::<false>::new_boxed::<2>(0, &bios);
+        let magic = key1::SECURE_AREA_DISABLE_MAGIC;
+        let encrypted_magic = key_buf.encrypt_64_bit([magic.read_le(0), magic.read_le(4)]);
+
+        for (arm7_bios, field) in [
+            (Some(&*bios), encrypted_magic),
+            // Dumps storing the magic in plain text don't need the KEY1 tables to be recognized
+            (Some(&*bios), [magic.read_le(0), magic.read_le(4)]),
+            (None, [magic.read_le(0), magic.read_le(4)]),
+        ] {
+            for direct_boot in [false, true] {
+                let mut contents = test_rom(0x1_0000, 0x4000);
+                if !direct_boot {
+                    // Would otherwise be encrypted, as it starts with the decrypted marker
+                    contents.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+                }
+                contents.write_le(0x78, field[0]);
+                contents.write_le(0x7C, field[1]);
+                let expected = contents[0x4000..0x4800].to_vec();
+                let mut rom = Normal::new(
+                    Box::new(contents),
+                    arm7_bios,
+                    Model::Ds,
+                    #[cfg(feature = "log")]
+                    slog::Logger::root(slog::Discard, slog::o!()),
+                )
+                .unwrap();
+                assert!(rom.is_secure_area_disabled());
+                assert_eq!(rom.setup(direct_boot), Ok(()));
+                assert_eq!(&*read_secure_area(&mut rom), &expected[..]);
+                assert!(!rom.secure_area_decryption_failed());
+            }
+        }
+
+        // Without the magic, the same secure area does get encrypted
+        let mut contents = test_rom(0x1_0000, 0x4000);
+        contents.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+        let expected = contents[0x4000..0x4800].to_vec();
+        let mut rom = Normal::new(
+            Box::new(contents),
+            Some(&bios),
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+        assert!(!rom.is_secure_area_disabled());
+        assert_eq!(rom.setup(false), Ok(()));
+        assert_ne!(&*read_secure_area(&mut rom), &expected[..]);
+    }
+
+    #[test]
+    fn secure_area_decryption_marker() {
+        let bios = test_arm7_bios();
+        // The test ROM's game code is 0, so encrypting for any other one uses the wrong key
+        for (game_code, should_fail) in [(0, false), (1, true)] {
+            let mut contents = test_rom(0x1_0000, 0x4000);
+            contents.write_le(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+            let decrypted = contents[0x4000..0x4800].to_vec();
+            key1::KeyBuffer::<false>::new_boxed::<2>(game_code, &bios)
+                .encrypt_secure_area(&mut contents[0x4000..0x4800]);
+            let mut rom = Normal::new(
+                Box::new(contents),
+                Some(&bios),
+                Model::Ds,
+                #[cfg(feature = "log")]
+                slog::Logger::root(slog::Discard, slog::o!()),
+            )
+            .unwrap();
+
+            assert_eq!(rom.setup(true), Ok(()));
+            assert_eq!(rom.secure_area_decryption_failed(), should_fail);
+            let secure_area = read_secure_area(&mut rom);
+            assert_eq!(
+                key1::is_secure_area_decrypted(&secure_area),
+                !should_fail,
+                "game code {game_code}"
+            );
+            assert_eq!(*secure_area == decrypted[..], !should_fail);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use super::{header::Header, Contents, Overlays};
+use crate::utils::{zero, Bytes};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch doesn't start with either the IPS or the UPS magic.
+    UnknownFormat,
+    /// The patch ended in the middle of a record (or before its footer, for UPS patches).
+    Truncated,
+}
+
+enum RecordKind {
+    Replace(Vec<u8>),
+    Fill(usize, u8),
+    Xor(Vec<u8>),
+}
+
+struct Record {
+    start: usize,
+    kind: RecordKind,
+}
+
+impl Record {
+    fn len(&self) -> usize {
+        match &self.kind {
+            RecordKind::Replace(data) | RecordKind::Xor(data) => data.len(),
+            RecordKind::Fill(len, _) => *len,
+        }
+    }
+}
+
+struct PatchReader<'a>(&'a [u8]);
+
+impl PatchReader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], PatchError> {
+        if self.0.len() < len {
+            return Err(PatchError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn be(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    // UPS variable-length integers, with the high bit marking the last byte
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0;
+        let mut shift = 1_usize;
+        loop {
+            let byte = self.bytes(1)?[0];
+            value += (byte & 0x7F) as usize * shift;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift <<= 7;
+            value += shift;
+        }
+    }
+}
+
+fn parse_ips(mut reader: PatchReader) -> Result<(Vec<Record>, Option<usize>), PatchError> {
+    let mut records = Vec::new();
+    loop {
+        if reader.0.starts_with(b"EOF") {
+            reader.bytes(3)?;
+            // Some patches append the length to truncate the ROM to
+            let truncated_len = (reader.0.len() >= 3).then(|| reader.be(3)).transpose()?;
+            return Ok((records, truncated_len));
+        }
+        let start = reader.be(3)?;
+        let len = reader.be(2)?;
+        let kind = if len == 0 {
+            let len = reader.be(2)?;
+            RecordKind::Fill(len, reader.bytes(1)?[0])
+        } else {
+            RecordKind::Replace(reader.bytes(len)?.to_vec())
+        };
+        records.push(Record { start, kind });
+    }
+}
+
+fn parse_ups(mut reader: PatchReader) -> Result<(Vec<Record>, usize), PatchError> {
+    let _source_len = reader.varint()?;
+    let target_len = reader.varint()?;
+    let body_len = reader
+        .0
+        .len()
+        .checked_sub(12)
+        .ok_or(PatchError::Truncated)?;
+    let mut body = PatchReader(&reader.0[..body_len]);
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while !body.0.is_empty() {
+        pos += body.varint()?;
+        let len = body
+            .0
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(PatchError::Truncated)?;
+        let data = body.bytes(len + 1)?[..len].to_vec();
+        records.push(Record {
+            start: pos,
+            kind: RecordKind::Xor(data),
+        });
+        // The terminating zero byte also covers one (unchanged) byte of the target
+        pos += len + 1;
+    }
+    Ok((records, target_len))
+}
+
+/// A [`Contents`] decorator applying an IPS or UPS patch over the inner contents as they're read,
+/// without modifying them; patches can extend (or, for UPS, shrink) the ROM.
+///
+/// As with file-backed contents, the (patched) secure area and DLDI overlays are kept in memory so
+/// that they can be modified in place.
+pub struct PatchedContents<C: Contents> {
+    inner: C,
+    records: Vec<Record>,
+    actual_len: usize,
+    game_code: u32,
+    overlays: Overlays,
+}
+
+impl<C: Contents> PatchedContents<C> {
+    /// # Errors
+    /// - [`PatchError::UnknownFormat`]: `patch` is neither an IPS nor a UPS patch.
+    /// - [`PatchError::Truncated`]: `patch` ends prematurely.
+    pub fn new(inner: C, patch: &[u8]) -> Result<Self, PatchError> {
+        let (records, actual_len) = if let Some(patch) = patch.strip_prefix(b"PATCH") {
+            let (records, truncated_len) = parse_ips(PatchReader(patch))?;
+            let patched_len = records
+                .iter()
+                .map(|record| record.start + record.len())
+                .fold(inner.actual_len(), usize::max);
+            (records, truncated_len.unwrap_or(patched_len))
+        } else if let Some(patch) = patch.strip_prefix(b"UPS1") {
+            parse_ups(PatchReader(patch))?
+        } else {
+            return Err(PatchError::UnknownFormat);
+        };
+
+        let mut result = PatchedContents {
+            inner,
+            records,
+            actual_len,
+            game_code: 0,
+            overlays: Overlays::default(),
+        };
+        let mut header_bytes = zero();
+        result.read_header(&mut header_bytes);
+        if let Some(header) = Header::parse(&header_bytes) {
+            result.game_code = header.game_code().0;
+            result.overlays = Overlays::new(header.arm9_rom_offset() as usize);
+        }
+        Ok(result)
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+fn read_patched(
+    inner: &mut impl Contents,
+    records: &[Record],
+    actual_len: usize,
+    addr: usize,
+    output: &mut [u8],
+) {
+    // Past the end of the patched data (i.e. for UPS patches shrinking the ROM), only padding
+    // is returned
+    let inner_len = output
+        .len()
+        .min(inner.len().min(actual_len).saturating_sub(addr));
+    if inner_len != 0 {
+        inner.read_slice(addr, &mut output[..inner_len]);
+    }
+    output[inner_len..].fill(0);
+
+    let end_addr = addr + output.len();
+    for record in records {
+        let record_end = (record.start + record.len()).min(actual_len);
+        if addr >= record_end || end_addr <= record.start {
+            continue;
+        }
+        let copy_start = addr.max(record.start);
+        let copy_end = end_addr.min(record_end);
+        let output = &mut output[copy_start - addr..copy_end - addr];
+        let record_range = copy_start - record.start..copy_end - record.start;
+        match &record.kind {
+            RecordKind::Replace(data) => output.copy_from_slice(&data[record_range]),
+            RecordKind::Fill(_, value) => output.fill(*value),
+            RecordKind::Xor(data) => {
+                for (byte, xor) in output.iter_mut().zip(&data[record_range]) {
+                    *byte ^= xor;
+                }
+            }
+        }
+    }
+}
+
+impl<C: Contents> Contents for PatchedContents<C> {
+    fn len(&self) -> usize {
+        self.inner.len().max(self.actual_len.next_power_of_two())
+    }
+
+    fn actual_len(&self) -> usize {
+        self.actual_len
+    }
+
+    fn game_code(&self) -> u32 {
+        self.game_code
+    }
+
+    fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        let PatchedContents {
+            inner,
+            records,
+            actual_len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.secure_area_mut(|addr, output| {
+            read_patched(inner, records, *actual_len, addr, output);
+        }))
+    }
+
+    fn secure_area(&mut self) -> Option<&[u8]> {
+        let PatchedContents {
+            inner,
+            records,
+            actual_len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.secure_area(|addr, output| {
+            read_patched(inner, records, *actual_len, addr, output);
+        }))
+    }
+
+    fn prefetch_secure_area(&mut self) {
+        self.secure_area();
+    }
+
+    fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+        let PatchedContents {
+            inner,
+            records,
+            actual_len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.dldi_area_mut(addr, len, |addr, output| {
+            read_patched(inner, records, *actual_len, addr, output);
+        }))
+    }
+
+    fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        let start = self.twl_region()?.secure_area_start;
+        let PatchedContents {
+            inner,
+            records,
+            actual_len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.twl_secure_area_mut(start, |addr, output| {
+            read_patched(inner, records, *actual_len, addr, output);
+        }))
+    }
+
+    fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+        self.read_slice(0, &mut **buf);
+    }
+
+    fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+        read_patched(
+            &mut self.inner,
+            &self.records,
+            self.actual_len,
+            addr,
+            output,
+        );
+        self.overlays.apply(addr, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{mem_prelude::*, BoxedByteSlice};
+
+    fn test_rom() -> BoxedByteSlice {
+        let mut rom = BoxedByteSlice::new_zeroed(0x8000);
+        for (i, byte) in rom.iter_mut().enumerate().skip(0x200) {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        rom[0xC..0x10].copy_from_slice(b"ABCE");
+        rom.write_le(0x20, 0x4000_u32);
+        rom
+    }
+
+    #[test]
+    fn ips_patch() {
+        let rom = test_rom().to_vec();
+        let mut patch = b"PATCH".to_vec();
+        // Replace 0x210..0x214
+        patch.extend_from_slice(&[0x00, 0x02, 0x10, 0x00, 0x04, 1, 2, 3, 4]);
+        // Fill 0x300..0x310 with 0xAA
+        patch.extend_from_slice(&[0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x10, 0xAA]);
+        patch.extend_from_slice(b"EOF");
+        let mut contents = PatchedContents::new(test_rom(), &patch).unwrap();
+        assert_eq!(contents.actual_len(), rom.len());
+        assert_eq!(contents.game_code(), u32::from_le_bytes(*b"ABCE"));
+
+        let mut expected = rom[0x200..0x400].to_vec();
+        expected[0x10..0x14].copy_from_slice(&[1, 2, 3, 4]);
+        expected[0x100..0x110].fill(0xAA);
+        let mut output = vec![0; 0x200];
+        contents.read_slice(0x200, &mut output);
+        assert_eq!(output, expected);
+
+        // The bytes around each record are read from the inner contents untouched
+        let mut output = [0; 4];
+        contents.read_slice(0x20E, &mut output);
+        assert_eq!(output, [rom[0x20E], rom[0x20F], 1, 2]);
+        contents.read_slice(0x30E, &mut output);
+        assert_eq!(output, [0xAA, 0xAA, rom[0x310], rom[0x311]]);
+        assert_eq!(&contents.inner()[0x210..0x214], &rom[0x210..0x214]);
+    }
+
+    #[test]
+    fn invalid_patches() {
+        assert_eq!(
+            PatchedContents::new(test_rom(), b"NOTAPATCH").err(),
+            Some(PatchError::UnknownFormat)
+        );
+        assert_eq!(
+            PatchedContents::new(test_rom(), b"PATCH\x00\x02\x10\x00\x04\x01").err(),
+            Some(PatchError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use super::{super::RomOutputLen, normal::Stage};
+use crate::utils::Bytes;
+
+/// A per-game override for ROM command handling, used to emulate protection chips and other
+/// cart-specific behavior without special-casing it in the main command handler.
+pub trait CommandQuirk {
+    /// Called before the default handler for every command (already decrypted, in KEY1 mode);
+    /// returns whether it was handled, in which case `output[..output_len]` must have been filled.
+    fn handle(
+        &mut self,
+        stage: Stage,
+        cmd: &Bytes<8>,
+        output: &mut Bytes<0x4000>,
+        output_len: RomOutputLen,
+    ) -> bool;
+}
+
+/// Supplies the chip ID returned by the chip ID commands (raw 0x90, KEY1 0x1 and KEY2 0xB8) and
+/// written to RAM on direct boot, i.e. to emulate flashcarts reporting distinctive ones.
+pub trait ChipIdProvider {
+    /// Returns the chip ID to report in the given stage; `default` is the one [`Normal`] would
+    /// report otherwise (derived from the ROM size, unless overridden).
+    ///
+    /// [`Normal`]: super::normal::Normal
+    fn chip_id(&self, stage: Stage, default: u32) -> u32;
+}
+
+/// The KEY2 commands of carts storing their saves in NAND memory next to the ROM data instead of
+/// using an SPI backup chip ("Made in Ore" and "Jam with the Band"), whose boot code waits for
+/// the NAND to report being ready and would otherwise spin on the zeros returned for unknown
+/// commands.
+///
+/// Only the initialization and status commands are handled, with the NAND always idle.
+struct NandSave;
+
+impl NandSave {
+    // The status bit signaling that the NAND is ready for a new command
+    const STATUS_READY: u8 = 0x20;
+
+    // The game codes without their last (region) byte
+    const GAME_CODES: [&'static [u8; 3]; 2] = [b"UOR", b"UXB"];
+}
+
+impl CommandQuirk for NandSave {
+    fn handle(
+        &mut self,
+        stage: Stage,
+        cmd: &Bytes<8>,
+        output: &mut Bytes<0x4000>,
+        output_len: RomOutputLen,
+    ) -> bool {
+        if stage != Stage::Key2 {
+            return false;
+        }
+        let output = &mut output[..output_len.get() as usize];
+        match cmd[0] {
+            // NAND init
+            0x94 => output.fill(0),
+            // NAND status, repeated over the whole output
+            0xD6 => output.fill(Self::STATUS_READY),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Returns the command quirks needed by the game with the specified game code, if any.
+pub fn for_game_code(game_code: u32) -> Option<Box<dyn CommandQuirk>> {
+    let game_code = game_code.to_le_bytes();
+    if NandSave::GAME_CODES.contains(&&[game_code[0], game_code[1], game_code[2]]) {
+        return Some(Box::new(NandSave));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            normal::{Normal, NormalBuilder},
+            RomDevice,
+        },
+        *,
+    };
+    use crate::{
+        utils::{mem_prelude::*, zero, BoxedByteSlice},
+        Model,
+    };
+
+    struct TestQuirk;
+
+    impl CommandQuirk for TestQuirk {
+        fn handle(
+            &mut self,
+            stage: Stage,
+            cmd: &Bytes<8>,
+            output: &mut Bytes<0x4000>,
+            output_len: RomOutputLen,
+        ) -> bool {
+            if stage != Stage::Initial || cmd[0] != 0x42 {
+                return false;
+            }
+            output[..output_len.get() as usize].fill(0x5A);
+            true
+        }
+    }
+
+    fn test_quirk_table(game_code: u32) -> Option<Box<dyn CommandQuirk>> {
+        (game_code == u32::from_le_bytes(*b"ABCE")).then(|| Box::new(TestQuirk) as _)
+    }
+
+    #[test]
+    fn quirk_intercepts_custom_command() {
+        for (game_code, intercepted) in [(*b"ABCE", true), (*b"ABCJ", false)] {
+            let mut contents = BoxedByteSlice::new_zeroed(0x8000);
+            contents[0xC..0x10].copy_from_slice(&game_code);
+            let mut builder = NormalBuilder::new(
+                Box::new(contents),
+                None,
+                Model::Ds,
+                #[cfg(feature = "log")]
+                slog::Logger::root(slog::Discard, slog::o!()),
+            );
+            builder.quirk_table = test_quirk_table;
+            let mut rom = builder.build().unwrap();
+
+            let mut output = zero();
+            rom.handle_rom_command(
+                Bytes::new([0x42, 0, 0, 0, 0, 0, 0, 0]),
+                &mut output,
+                RomOutputLen::new(0x10),
+            );
+            assert_eq!(output[..0x10] == [0x5A; 0x10], intercepted);
+
+            // Other commands still go through the default handler
+            rom.handle_rom_command(
+                Bytes::new([0x9F, 0, 0, 0, 0, 0, 0, 0]),
+                &mut output,
+                RomOutputLen::new(0x10),
+            );
+            assert_eq!(output[..0x10], [0xFF; 0x10]);
+        }
+    }
+
+    #[test]
+    fn nand_save_status() {
+        for (game_code, has_quirk) in [(*b"UORE", true), (*b"UXBP", true), (*b"ABCE", false)] {
+            let mut contents = BoxedByteSlice::new_zeroed(0x1_0000);
+            contents[0xC..0x10].copy_from_slice(&game_code);
+            contents.write_le(0x20, 0x4000_u32);
+            let mut rom = Normal::new(
+                Box::new(contents),
+                None,
+                Model::Ds,
+                #[cfg(feature = "log")]
+                slog::Logger::root(slog::Discard, slog::o!()),
+            )
+            .unwrap();
+            rom.set_stage(Stage::Key2);
+            rom.set_strict(true);
+
+            let mut output = zero();
+            rom.handle_rom_command(
+                Bytes::new([0xD6, 0, 0, 0, 0, 0, 0, 0]),
+                &mut output,
+                RomOutputLen::new(4),
+            );
+            let expected = if has_quirk { [0x20; 4] } else { [0; 4] };
+            assert_eq!(output[..4], expected);
+
+            output[..4].fill(0xFF);
+            rom.handle_rom_command(
+                Bytes::new([0x94, 0, 0, 0, 0, 0, 0, 0]),
+                &mut output,
+                RomOutputLen::new(4),
+            );
+            assert_eq!(output[..4], [0; 4]);
+            // Both are only known commands for NAND carts
+            let expected = if has_quirk { 0 } else { 2 };
+            assert_eq!(rom.uncertain_responses(), expected);
+        }
+    }
+}
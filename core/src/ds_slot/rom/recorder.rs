@@ -0,0 +1,151 @@
+use super::{
+    super::RomOutputLen,
+    normal::{Normal, Stage},
+    RomDevice,
+};
+use crate::utils::Bytes;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedResponse {
+    pub stage: Stage,
+    pub cmd: [u8; 8],
+    pub output: Vec<u8>,
+}
+
+/// The first mismatch found by [`RomResponseRecorder::verify_against`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The index of the mismatching response in the command sequence.
+    pub index: usize,
+    pub stage: Stage,
+    pub cmd: [u8; 8],
+    /// The offset of the first mismatching byte within the response.
+    pub offset: usize,
+    /// The mismatching bytes, or `None` if either response ended before `offset`.
+    pub expected: Option<u8>,
+    pub got: Option<u8>,
+}
+
+/// Records the responses of a [`Normal`] cart to a sequence of commands, so they can be compared
+/// against ones captured from hardware.
+#[derive(Clone, Debug, Default)]
+pub struct RomResponseRecorder {
+    responses: Vec<RecordedResponse>,
+}
+
+impl RomResponseRecorder {
+    /// Sends each command to `rom` after switching it to the associated stage (or in the one the
+    /// previous command left it in, if `None`), recording the first `output_len` bytes of every
+    /// response.
+    pub fn record(
+        rom: &mut Normal,
+        commands: &[(Option<Stage>, [u8; 8])],
+        output_len: RomOutputLen,
+    ) -> Self {
+        let mut output = unsafe { Box::<Bytes<0x4000>>::new_zeroed().assume_init() };
+        let responses = commands
+            .iter()
+            .map(|&(stage, cmd)| {
+                if let Some(stage) = stage {
+                    rom.set_stage(stage);
+                }
+                let stage = rom.stage();
+                rom.handle_rom_command(Bytes::new(cmd), &mut output, output_len);
+                RecordedResponse {
+                    stage,
+                    cmd,
+                    output: output[..output_len.get() as usize].to_vec(),
+                }
+            })
+            .collect();
+        RomResponseRecorder { responses }
+    }
+
+    #[inline]
+    pub fn responses(&self) -> &[RecordedResponse] {
+        &self.responses
+    }
+
+    /// Compares the recorded responses against reference ones (in the same order), returning the
+    /// first divergence, if any; extra reference responses are ignored.
+    pub fn verify_against(&self, reference: &[impl AsRef<[u8]>]) -> Result<(), Divergence> {
+        for (index, (response, expected)) in self.responses.iter().zip(reference).enumerate() {
+            let expected = expected.as_ref();
+            let len = response.output.len().max(expected.len());
+            if let Some(offset) = (0..len).find(|&i| response.output.get(i) != expected.get(i)) {
+                return Err(Divergence {
+                    index,
+                    stage: response.stage,
+                    cmd: response.cmd,
+                    offset,
+                    expected: expected.get(offset).copied(),
+                    got: response.output.get(offset).copied(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        utils::{mem_prelude::*, BoxedByteSlice},
+        Model,
+    };
+
+    #[test]
+    fn replay_against_reference() {
+        let mut contents = BoxedByteSlice::new_zeroed(0x1_0000);
+        for (i, byte) in contents.iter_mut().enumerate().skip(0x200) {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        contents.write_le(0x20, 0x4000_u32);
+        let reference = [
+            contents[..0x200].to_vec(),
+            contents[0x8000..0x8200].to_vec(),
+        ];
+        let mut rom = Normal::new(
+            Box::new(contents),
+            None,
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+
+        let recorder = RomResponseRecorder::record(
+            &mut rom,
+            &[
+                (None, [0; 8]),
+                (Some(Stage::Key2), [0xB7, 0, 0, 0x80, 0, 0, 0, 0]),
+            ],
+            RomOutputLen::new(0x200),
+        );
+        assert_eq!(recorder.responses().len(), 2);
+        assert_eq!(recorder.responses()[0].stage, Stage::Initial);
+        assert_eq!(recorder.verify_against(&reference), Ok(()));
+
+        let mut bad_reference = reference.clone();
+        bad_reference[1][5] ^= 0xFF;
+        assert_eq!(
+            recorder.verify_against(&bad_reference),
+            Err(Divergence {
+                index: 1,
+                stage: Stage::Key2,
+                cmd: [0xB7, 0, 0, 0x80, 0, 0, 0, 0],
+                offset: 5,
+                expected: Some(reference[1][5] ^ 0xFF),
+                got: Some(reference[1][5]),
+            })
+        );
+
+        // A truncated reference diverges where it ends
+        let divergence = recorder
+            .verify_against(&[&reference[0][..0x100]])
+            .unwrap_err();
+        assert_eq!((divergence.index, divergence.offset), (0, 0x100));
+        assert_eq!(divergence.expected, None);
+    }
+}
@@ -0,0 +1,216 @@
+use super::{header::Header, Contents, Overlays};
+use crate::utils::{zero, Bytes};
+
+/// The order of the bytes in each 16-bit word of a ROM dump; some dumping setups produce
+/// word-swapped dumps, which have a valid size but fail to boot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    Normal,
+    Swapped,
+}
+
+fn swap_words(bytes: &mut [u8]) {
+    for word in bytes.chunks_exact_mut(2) {
+        word.swap(0, 1);
+    }
+}
+
+fn is_valid_header(bytes: &Bytes<0x170>) -> bool {
+    Header::parse(bytes).is_some_and(|header| header.logo_valid() && header.header_crc_valid())
+}
+
+/// Detects the byte order of a dump by checking whether its header's logo and CRC are valid as-is
+/// or once word-swapped, returning `None` if neither interpretation is (i.e. for homebrew that
+/// doesn't fill them in).
+pub fn detect_byte_order(contents: &mut (impl Contents + ?Sized)) -> Option<ByteOrder> {
+    let mut header_bytes = zero();
+    contents.read_header(&mut header_bytes);
+    if is_valid_header(&header_bytes) {
+        return Some(ByteOrder::Normal);
+    }
+    swap_words(&mut *header_bytes);
+    is_valid_header(&header_bytes).then_some(ByteOrder::Swapped)
+}
+
+/// A [`Contents`] decorator transparently undoing the word swapping of a dump (see
+/// [`ByteOrder::Swapped`]) as it's read.
+///
+/// As with file-backed contents, the (unswapped) secure area and DLDI overlays are kept in memory
+/// so that they can be modified in place.
+pub struct UnswappedContents<C: Contents> {
+    inner: C,
+    buf: Vec<u8>,
+    game_code: u32,
+    overlays: Overlays,
+}
+
+impl<C: Contents> UnswappedContents<C> {
+    pub fn new(inner: C) -> Self {
+        let mut result = UnswappedContents {
+            inner,
+            buf: Vec::new(),
+            game_code: 0,
+            overlays: Overlays::default(),
+        };
+        let mut header_bytes = zero();
+        result.read_header(&mut header_bytes);
+        if let Some(header) = Header::parse(&header_bytes) {
+            result.game_code = header.game_code().0;
+            result.overlays = Overlays::new(header.arm9_rom_offset() as usize);
+        }
+        result
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+fn read_unswapped(inner: &mut impl Contents, buf: &mut Vec<u8>, addr: usize, output: &mut [u8]) {
+    // Reads have to cover whole words to be swapped back, so they're done through a word-aligned
+    // buffer
+    let start = addr & !1;
+    let end = (addr + output.len() + 1) & !1;
+    buf.resize(end - start, 0);
+    inner.read_slice(start, buf);
+    swap_words(buf);
+    output.copy_from_slice(&buf[addr - start..addr - start + output.len()]);
+}
+
+impl<C: Contents> Contents for UnswappedContents<C> {
+    forward_contents_to_inner!(len, actual_len);
+
+    fn game_code(&self) -> u32 {
+        self.game_code
+    }
+
+    fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        let UnswappedContents {
+            inner,
+            buf,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.secure_area_mut(|addr, output| read_unswapped(inner, buf, addr, output)))
+    }
+
+    fn secure_area(&mut self) -> Option<&[u8]> {
+        let UnswappedContents {
+            inner,
+            buf,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.secure_area(|addr, output| read_unswapped(inner, buf, addr, output)))
+    }
+
+    fn prefetch_secure_area(&mut self) {
+        self.secure_area();
+    }
+
+    fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+        let UnswappedContents {
+            inner,
+            buf,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.dldi_area_mut(addr, len, |addr, output| {
+            read_unswapped(inner, buf, addr, output);
+        }))
+    }
+
+    fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        let start = self.twl_region()?.secure_area_start;
+        let UnswappedContents {
+            inner,
+            buf,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.twl_secure_area_mut(start, |addr, output| {
+            read_unswapped(inner, buf, addr, output);
+        }))
+    }
+
+    fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+        self.inner.read_header(buf);
+        swap_words(&mut **buf);
+    }
+
+    fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+        read_unswapped(&mut self.inner, &mut self.buf, addr, output);
+        self.overlays.apply(addr, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::header::{tests::test_logo, CodeBlockInfo, HeaderBuilder},
+        *,
+    };
+    use crate::utils::BoxedByteSlice;
+
+    // Returns a ROM whose header has a valid logo and CRC, followed by a pattern
+    fn test_rom() -> BoxedByteSlice {
+        let logo = test_logo();
+        let code_block = |rom_offset| CodeBlockInfo {
+            rom_offset,
+            entry_addr: 0x0200_0000,
+            ram_addr: 0x0200_0000,
+            size: 0x1000,
+        };
+        let mut builder = HeaderBuilder::new(
+            "GAME",
+            *b"ABCE",
+            *b"01",
+            code_block(0x4000),
+            code_block(0x6000),
+        );
+        builder.logo = Some(&logo);
+        let mut rom = BoxedByteSlice::new_zeroed(0x8000);
+        rom[..0x170].copy_from_slice(&*builder.build());
+        for (i, byte) in rom.iter_mut().enumerate().skip(0x200) {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn detect_and_unswap() {
+        let mut rom = test_rom();
+        let expected = rom.to_vec();
+        assert_eq!(detect_byte_order(&mut rom), Some(ByteOrder::Normal));
+
+        swap_words(&mut rom);
+        assert_eq!(detect_byte_order(&mut rom), Some(ByteOrder::Swapped));
+        let mut contents = UnswappedContents::new(rom);
+        assert_eq!(detect_byte_order(&mut contents), Some(ByteOrder::Normal));
+        assert_eq!(contents.game_code(), u32::from_le_bytes(*b"ABCE"));
+
+        let mut header_bytes = zero();
+        contents.read_header(&mut header_bytes);
+        assert_eq!(&header_bytes[..], &expected[..0x170]);
+        // Reads starting or ending in the middle of a word are unswapped too
+        for (addr, len) in [(0x201, 0x10), (0x300, 0x11), (0x7FFF, 1)] {
+            let mut output = vec![0; len];
+            contents.read_slice(addr, &mut output);
+            assert_eq!(output, expected[addr..addr + len], "{addr:#X}+{len:#X}");
+        }
+        assert_eq!(
+            contents.secure_area_mut().unwrap(),
+            &expected[0x4000..0x4800]
+        );
+
+        // Homebrew headers without a logo are valid in neither byte order
+        let mut homebrew = BoxedByteSlice::new_zeroed(0x8000);
+        assert_eq!(detect_byte_order(&mut homebrew), None);
+    }
+}
@@ -0,0 +1,145 @@
+use super::Contents;
+use crate::utils::Bytes;
+
+/// A [`Contents`] decorator recording the address ranges of all reads, to find out which parts of
+/// the ROM a game accesses.
+pub struct TracingContents<C: Contents> {
+    inner: C,
+    sink: Option<Box<dyn FnMut(usize, usize)>>,
+    granularity_shift: u32,
+    histogram: Vec<u64>,
+}
+
+impl<C: Contents> TracingContents<C> {
+    /// Creates a tracing wrapper around `inner`, counting accesses in buckets of
+    /// `1 << granularity_shift` bytes.
+    pub fn new(inner: C, granularity_shift: u32) -> Self {
+        let buckets = ((inner.len() - 1) >> granularity_shift) + 1;
+        TracingContents {
+            inner,
+            sink: None,
+            granularity_shift,
+            histogram: vec![0; buckets],
+        }
+    }
+
+    /// Sets a callback that will be invoked with the address and length of every read.
+    pub fn set_sink(&mut self, sink: Option<Box<dyn FnMut(usize, usize)>>) {
+        self.sink = sink;
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    #[inline]
+    pub fn granularity_shift(&self) -> u32 {
+        self.granularity_shift
+    }
+
+    /// Returns the amount of reads that touched each `1 << granularity_shift`-byte bucket.
+    #[inline]
+    pub fn histogram(&self) -> &[u64] {
+        &self.histogram
+    }
+
+    /// Returns the start address and read count of all buckets that were accessed at least once.
+    pub fn accessed_buckets(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.histogram
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count != 0)
+            .map(|(i, count)| (i << self.granularity_shift, *count))
+    }
+
+    pub fn clear_histogram(&mut self) {
+        self.histogram.fill(0);
+    }
+
+    fn record(&mut self, addr: usize, len: usize) {
+        if let Some(sink) = &mut self.sink {
+            sink(addr, len);
+        }
+        if len == 0 {
+            return;
+        }
+        let start_bucket = addr >> self.granularity_shift;
+        let end_bucket = ((addr + len - 1) >> self.granularity_shift).min(self.histogram.len() - 1);
+        for count in self
+            .histogram
+            .iter_mut()
+            .take(end_bucket + 1)
+            .skip(start_bucket)
+        {
+            *count += 1;
+        }
+    }
+}
+
+impl<C: Contents> Contents for TracingContents<C> {
+    forward_contents_to_inner!(
+        len,
+        actual_len,
+        game_code,
+        secure_area_mut,
+        secure_area,
+        prefetch_secure_area,
+        dldi_area_mut,
+        twl_secure_area_mut
+    );
+
+    fn read_header(&mut self, buf: &mut Bytes<0x170>) {
+        self.record(0, 0x170);
+        self.inner.read_header(buf);
+    }
+
+    fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
+        self.record(addr, output.len());
+        self.inner.read_slice(addr, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::BoxedByteSlice;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn reads_are_forwarded_and_traced() {
+        let mut inner = BoxedByteSlice::new_zeroed(0x8000);
+        for (i, byte) in inner.iter_mut().enumerate() {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        let expected = inner.to_vec();
+        let mut contents = TracingContents::new(inner, 12);
+        let reads = Rc::new(RefCell::new(Vec::new()));
+        contents.set_sink(Some(Box::new({
+            let reads = Rc::clone(&reads);
+            move |addr, len| reads.borrow_mut().push((addr, len))
+        })));
+
+        let mut output = [0; 0x20];
+        contents.read_slice(0x1FF0, &mut output);
+        assert_eq!(output[..], expected[0x1FF0..0x2010]);
+        contents.read_slice(0x7000, &mut output[..4]);
+        assert_eq!(output[..4], expected[0x7000..0x7004]);
+        let mut header = Bytes::new([0; 0x170]);
+        contents.read_header(&mut header);
+        assert_eq!(header[..], expected[..0x170]);
+
+        assert_eq!(*reads.borrow(), [(0x1FF0, 0x20), (0x7000, 4), (0, 0x170)]);
+        assert_eq!(
+            contents.accessed_buckets().collect::<Vec<_>>(),
+            [(0, 1), (0x1000, 1), (0x2000, 1), (0x7000, 1)]
+        );
+        contents.clear_histogram();
+        assert_eq!(contents.accessed_buckets().count(), 0);
+    }
+}
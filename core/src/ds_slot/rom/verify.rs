@@ -0,0 +1,124 @@
+use super::{crc32::Crc32, for_each_block_until, sha1::Sha1, Contents, HASH_BLOCK_LEN};
+
+/// The size and hashes of a known-good dump, as listed in a No-Intro DAT file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DatEntry {
+    pub size: u64,
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+/// Which part of the ROM was hashed to compare it against a [`DatEntry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum DumpKind {
+    /// The ROM data up to [`Contents::actual_len`].
+    Trimmed,
+    /// The whole addressable range up to [`Contents::len`], including padding.
+    Padded,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Verification {
+    /// The range that matched the entry's size and was hashed, or `None` if neither did (in
+    /// which case no hashes were calculated).
+    pub kind: Option<DumpKind>,
+    pub crc32_matches: bool,
+    pub sha1_matches: bool,
+}
+
+impl Verification {
+    /// Returns whether the ROM is the dump described by the entry, i.e. whether all of its size
+    /// and hashes match.
+    #[inline]
+    pub fn is_match(&self) -> bool {
+        self.kind.is_some() && self.crc32_matches && self.sha1_matches
+    }
+}
+
+/// Compares `contents` against a DAT entry, hashing either the trimmed or the padded ROM
+/// depending on which one has the entry's size; both hashes are calculated in a single pass.
+pub fn verify(contents: &mut (impl Contents + ?Sized), entry: &DatEntry) -> Verification {
+    let kind = if entry.size == contents.actual_len() as u64 {
+        DumpKind::Trimmed
+    } else if entry.size == contents.len() as u64 {
+        DumpKind::Padded
+    } else {
+        return Verification {
+            kind: None,
+            crc32_matches: false,
+            sha1_matches: false,
+        };
+    };
+    let len = entry.size as usize;
+    let mut crc32 = Crc32::new();
+    let mut sha1 = Sha1::new();
+    for_each_block_until(contents, len, HASH_BLOCK_LEN, &mut |_, block| {
+        crc32.update(block);
+        sha1.update(block);
+    });
+    Verification {
+        kind: Some(kind),
+        crc32_matches: crc32.finish() == entry.crc32,
+        sha1_matches: sha1.finish() == entry.sha1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::tests::TrimmedContents, *};
+    use crate::utils::BoxedByteSlice;
+
+    #[test]
+    fn crafted_rom_matches_own_hashes() {
+        let mut rom = BoxedByteSlice::new_zeroed(0x5000);
+        for (i, byte) in rom.iter_mut().enumerate() {
+            *byte = (i ^ i >> 8) as u8;
+        }
+        let mut padded = rom.to_vec();
+        padded.resize(0x8000, 0);
+        let mut contents = TrimmedContents(rom);
+
+        let trimmed = DatEntry {
+            size: 0x5000,
+            crc32: contents.crc32(),
+            sha1: contents.hash_sha1(),
+        };
+        let mut crc32 = Crc32::new();
+        crc32.update(&padded);
+        let padded = DatEntry {
+            size: 0x8000,
+            crc32: crc32.finish(),
+            sha1: contents.hash_padded_sha1(),
+        };
+        for (entry, kind) in [(trimmed, DumpKind::Trimmed), (padded, DumpKind::Padded)] {
+            let verification = verify(&mut contents, &entry);
+            assert_eq!(
+                verification,
+                Verification {
+                    kind: Some(kind),
+                    crc32_matches: true,
+                    sha1_matches: true,
+                }
+            );
+            assert!(verification.is_match());
+        }
+
+        let mut bad_sha1 = trimmed;
+        bad_sha1.sha1[0] ^= 1;
+        let verification = verify(&mut contents, &bad_sha1);
+        assert!(verification.crc32_matches && !verification.sha1_matches);
+        assert!(!verification.is_match());
+
+        // Sizes matching neither the trimmed nor the padded ROM aren't hashed at all
+        let wrong_size = DatEntry {
+            size: 0x6000,
+            ..trimmed
+        };
+        let verification = verify(&mut contents, &wrong_size);
+        assert_eq!(verification.kind, None);
+        assert!(!verification.is_match());
+    }
+}
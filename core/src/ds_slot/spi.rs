@@ -3,6 +3,8 @@ pub use empty::Empty;
 pub mod eeprom_4k;
 pub mod eeprom_fram;
 pub mod flash;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
 
 use crate::{utils::Savestate, SaveReloadContents};
 
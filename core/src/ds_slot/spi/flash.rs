@@ -1,4 +1,5 @@
 use crate::{flash, utils::Savestate, SaveContents, SaveReloadContents};
+use std::collections::VecDeque;
 
 pub type Status = flash::Status;
 
@@ -13,6 +14,13 @@ pub struct Flash {
     has_ir: bool,
     ir_cmd: u8,
     first_ir_data_byte: bool,
+    // The IR queues hold traffic exchanged with a peer outside the emulated system (i.e. a
+    // Pokéwalker driven by the frontend), which can't be restored along with a savestate; they're
+    // left as-is when loading one, and it's up to the frontend to resynchronize with the peer
+    #[savestate(skip)]
+    ir_rx: VecDeque<u8>,
+    #[savestate(skip)]
+    ir_tx: Vec<u8>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -44,6 +52,8 @@ impl Flash {
             has_ir,
             ir_cmd: 0,
             first_ir_data_byte: false,
+            ir_rx: VecDeque::new(),
+            ir_tx: Vec::new(),
             #[cfg(feature = "log")]
             logger,
         })
@@ -55,6 +65,8 @@ impl Flash {
             contents: self.contents.reset(),
             ir_cmd: 0,
             first_ir_data_byte: false,
+            ir_rx: VecDeque::new(),
+            ir_tx: Vec::new(),
             ..self
         }
     }
@@ -64,6 +76,16 @@ impl Flash {
         self.has_ir
     }
 
+    /// Queues bytes received by the IR transceiver, to be returned by subsequent receive commands.
+    pub fn receive_ir_data(&mut self, data: &[u8]) {
+        self.ir_rx.extend(data);
+    }
+
+    /// Returns the bytes sent through the IR transceiver since the last call.
+    pub fn take_sent_ir_data(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.ir_tx)
+    }
+
     #[inline]
     pub fn id(&self) -> &[u8; 20] {
         self.contents.id()
@@ -142,6 +164,22 @@ impl super::SpiDevice for Flash {
                         self.contents.handle_byte(value, first, last)
                     }
 
+                    0x01 => {
+                        // Receive: the first byte is the amount of received data, followed by the
+                        // data itself
+                        if first {
+                            self.ir_rx.len().min(0xFF) as u8
+                        } else {
+                            self.ir_rx.pop_front().unwrap_or(0)
+                        }
+                    }
+
+                    0x02 => {
+                        // Send
+                        self.ir_tx.push(value);
+                        0
+                    }
+
                     0x08 => {
                         // Read ID
                         0xAA
@@ -170,3 +208,44 @@ impl super::SpiDevice for Flash {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{super::SpiDevice, *};
+
+    fn new_flash() -> Flash {
+        Flash::new(
+            SaveContents::New(0x4_0000),
+            [0; 20],
+            true,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .expect("test FLASH should have a valid size")
+    }
+
+    fn transfer(flash: &mut Flash, bytes: &[u8]) -> Vec<u8> {
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| flash.write_data(byte, i == 0, i == bytes.len() - 1))
+            .collect()
+    }
+
+    #[test]
+    fn ir_handshake() {
+        let mut flash = new_flash();
+        assert_eq!(transfer(&mut flash, &[0x08, 0]), [0, 0xAA]);
+
+        flash.receive_ir_data(&[0x12, 0x34, 0x56]);
+        assert_eq!(
+            transfer(&mut flash, &[0x01, 0, 0, 0, 0]),
+            [0, 3, 0x12, 0x34, 0x56]
+        );
+        assert_eq!(transfer(&mut flash, &[0x01, 0, 0]), [0, 0, 0]);
+
+        assert_eq!(transfer(&mut flash, &[0x02, 0xAB, 0xCD]), [0, 0, 0]);
+        assert_eq!(flash.take_sent_ir_data(), [0xAB, 0xCD]);
+        assert!(flash.take_sent_ir_data().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::SaveReloadContents;
+
+/// A scriptable SPI backup device, replying to each command in a table with a canned response and
+/// recording all transfers, i.e. to exercise save type detection without emulating real chips.
+/// Only available in tests and with the `test-utils` feature; it isn't an [`Spi`](super::Spi)
+/// variant, so it's never part of savestates.
+///
+/// Bytes clocked out while the command is still being sent, after the response runs out, or for
+/// unknown commands read as 0xFF (like an undriven bus).
+#[derive(Clone)]
+pub struct Mock {
+    responses: Vec<(Vec<u8>, Vec<u8>)>,
+    transfers: Vec<Vec<u8>>,
+    cur_transfer: Vec<u8>,
+    // The index of the response to the current transfer's command, and the position inside it
+    cur_response: Option<(usize, usize)>,
+}
+
+#[allow(clippy::new_without_default)]
+impl Mock {
+    #[inline]
+    pub fn new() -> Self {
+        Mock {
+            responses: Vec::new(),
+            transfers: Vec::new(),
+            cur_transfer: Vec::new(),
+            cur_response: None,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn reset(mut self) -> Self {
+        self.cur_transfer.clear();
+        self.cur_response = None;
+        self
+    }
+
+    /// Makes the device reply with `response` once a transfer's first bytes match `command`
+    /// (i.e. `[0x9F]` for a JEDEC ID read), replacing any previous response for it.
+    pub fn set_response(&mut self, command: &[u8], response: &[u8]) {
+        match self.responses.iter_mut().find(|(cmd, _)| cmd == command) {
+            Some((_, prev_response)) => *prev_response = response.to_vec(),
+            None => self.responses.push((command.to_vec(), response.to_vec())),
+        }
+    }
+
+    /// Returns all the bytes written in each completed transfer so far, in order.
+    #[inline]
+    pub fn transfers(&self) -> &[Vec<u8>] {
+        &self.transfers
+    }
+
+    #[inline]
+    pub fn clear_transfers(&mut self) {
+        self.transfers.clear();
+    }
+
+    /// Clocks a whole transfer of `bytes` through the device, returning the bytes it replied with.
+    pub fn transfer(&mut self, bytes: &[u8]) -> Vec<u8> {
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| {
+                super::SpiDevice::write_data(self, byte, i == 0, i == bytes.len() - 1)
+            })
+            .collect()
+    }
+}
+
+impl super::SpiDevice for Mock {
+    fn contents(&self) -> &[u8] {
+        &[]
+    }
+
+    fn contents_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    fn reload_contents(&mut self, _contents: SaveReloadContents) {}
+
+    fn contents_dirty(&self) -> bool {
+        false
+    }
+
+    fn mark_contents_dirty(&mut self) {}
+
+    fn mark_contents_flushed(&mut self) {}
+
+    fn write_data(&mut self, data: u8, first: bool, last: bool) -> u8 {
+        if first {
+            self.cur_transfer.clear();
+            self.cur_response = None;
+        }
+        self.cur_transfer.push(data);
+
+        let result = if let Some((i, pos)) = &mut self.cur_response {
+            let byte = self.responses[*i].1.get(*pos).copied().unwrap_or(0xFF);
+            *pos += 1;
+            byte
+        } else {
+            self.cur_response = self
+                .responses
+                .iter()
+                .position(|(cmd, _)| *cmd == self.cur_transfer)
+                .map(|i| (i, 0));
+            0xFF
+        };
+
+        if last {
+            self.transfers.push(self.cur_transfer.clone());
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jedec_id() {
+        let mut mock = Mock::new();
+        mock.set_response(&[0x9F], &[0x20, 0x40, 0x12]);
+        assert_eq!(
+            mock.transfer(&[0x9F, 0, 0, 0, 0]),
+            [0xFF, 0x20, 0x40, 0x12, 0xFF]
+        );
+        assert_eq!(mock.transfer(&[0x05, 0]), [0xFF, 0xFF]);
+        assert_eq!(mock.transfers(), [vec![0x9F, 0, 0, 0, 0], vec![0x05, 0]]);
+    }
+}
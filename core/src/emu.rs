@@ -188,7 +188,7 @@ pub struct Builder {
 pub enum BuildError {
     MissingSysFiles,
     RomCreation(ds_slot::rom::normal::CreationError),
-    RomNeedsDecryptionButNoBiosProvided,
+    RomSetup(ds_slot::rom::SetupError),
 }
 
 impl Builder {
@@ -265,7 +265,7 @@ impl Builder {
 
         ds_rom
             .setup(self.direct_boot)
-            .map_err(|()| BuildError::RomNeedsDecryptionButNoBiosProvided)?;
+            .map_err(BuildError::RomSetup)?;
 
         let (global_engine_data, arm7_engine_data, arm9_engine_data) = engine.into_data();
         let mut arm7 = Arm7::new(
@@ -380,7 +380,7 @@ impl<E: cpu::Engine> Emu<E> {
 
         let mut header_bytes = Bytes::new([0; 0x170]);
         self.ds_slot.rom.read_header(&mut header_bytes);
-        let header = ds_slot::rom::header::Header::new(&*header_bytes)
+        let header = ds_slot::rom::header::Header::parse(&header_bytes)
             // NOTE: The ROM file's size is ensured beforehand, this should never occur.
             .expect("couldn't read DS slot ROM header");
         let chip_id = self.ds_slot.rom.chip_id();
@@ -508,23 +508,25 @@ impl<E: cpu::Engine> Emu<E> {
 
         // ––––––––––––––––    Game boot code     ––––––––––––––––
 
-        let mut arm7_loaded_data = BoxedByteSlice::new_zeroed(header.arm7_size() as usize);
+        let arm7_code = header.arm7();
+        let mut arm7_loaded_data = BoxedByteSlice::new_zeroed(arm7_code.size as usize);
         self.ds_slot
             .rom
-            .read(header.arm7_rom_offset(), &mut arm7_loaded_data);
-        for (&byte, addr) in arm7_loaded_data.iter().zip(header.arm7_ram_addr()..) {
+            .read(arm7_code.rom_offset, &mut arm7_loaded_data);
+        for (&byte, addr) in arm7_loaded_data.iter().zip(arm7_code.ram_addr..) {
             arm7::bus::write_8::<CpuAccess, _>(self, addr, byte);
         }
-        E::Arm7Data::setup_direct_boot(self, header.arm7_entry_addr());
+        E::Arm7Data::setup_direct_boot(self, arm7_code.entry_addr);
 
-        let mut arm9_loaded_data = BoxedByteSlice::new_zeroed(header.arm9_size() as usize);
+        let arm9_code = header.arm9();
+        let mut arm9_loaded_data = BoxedByteSlice::new_zeroed(arm9_code.size as usize);
         self.ds_slot
             .rom
-            .read(header.arm9_rom_offset(), &mut arm9_loaded_data);
-        for (&byte, addr) in arm9_loaded_data.iter().zip(header.arm9_ram_addr()..) {
+            .read(arm9_code.rom_offset, &mut arm9_loaded_data);
+        for (&byte, addr) in arm9_loaded_data.iter().zip(arm9_code.ram_addr..) {
             arm9::bus::write_8::<CpuAccess, _>(self, addr, byte);
         }
-        E::Arm9Data::setup_direct_boot(self, header.arm9_entry_addr());
+        E::Arm9Data::setup_direct_boot(self, arm9_code.entry_addr);
     }
 
     #[inline]
@@ -9,19 +9,43 @@ static CRC16_VALUES: [u16; 8] = [
     0xC0C1, 0xC181, 0xC301, 0xC601, 0xCC01, 0xD801, 0xF001, 0xA001,
 ];
 
-fn crc16(init: u16, bytes: &[u8]) -> u16 {
-    let mut result = init as u32;
-    for &byte in bytes {
-        result ^= byte as u32;
-        for (i, crc) in CRC16_VALUES.iter().enumerate() {
-            let carry = result & 1 != 0;
-            result >>= 1;
-            if carry {
-                result ^= (*crc as u32) << (i ^ 7);
+/// An incremental version of [`crc16`], for data that's read in chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc16(u16);
+
+impl Crc16 {
+    #[inline]
+    pub fn new(init: u16) -> Self {
+        Crc16(init)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut result = self.0 as u32;
+        for &byte in bytes {
+            result ^= byte as u32;
+            for (i, crc) in CRC16_VALUES.iter().enumerate() {
+                let carry = result & 1 != 0;
+                result >>= 1;
+                if carry {
+                    result ^= (*crc as u32) << (i ^ 7);
+                }
             }
         }
+        self.0 = result as u16;
     }
-    result as u16
+
+    #[inline]
+    pub fn finish(self) -> u16 {
+        self.0
+    }
+}
+
+/// Calculates the CRC16 used throughout the DS (by the firmware, and by the cartridge header and
+/// banner), starting from `init`.
+pub fn crc16(init: u16, bytes: &[u8]) -> u16 {
+    let mut crc = Crc16::new(init);
+    crc.update(bytes);
+    crc.finish()
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -203,3 +227,30 @@ pub fn newest_user_settings(firmware: &[u8]) -> &[u8] {
         &firmware[user_settings_offset..user_settings_offset + 0x100]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_known_values() {
+        // The standard check values for CRC-16/MODBUS and CRC-16/ARC, which only differ in the
+        // initial value
+        assert_eq!(crc16(0xFFFF, b"123456789"), 0x4B37);
+        assert_eq!(crc16(0, b"123456789"), 0xBB3D);
+        assert_eq!(crc16(0xFFFF, b"A"), 0x707F);
+        assert_eq!(crc16(0xFFFF, &[0]), 0x40BF);
+        assert_eq!(crc16(0xFFFF, &[]), 0xFFFF);
+    }
+
+    #[test]
+    fn incremental_crc16_matches() {
+        let data = b"123456789";
+        for split in 0..=data.len() {
+            let mut crc = Crc16::new(0xFFFF);
+            crc.update(&data[..split]);
+            crc.update(&data[split..]);
+            assert_eq!(crc.finish(), 0x4B37, "split at {split}");
+        }
+    }
+}
@@ -702,6 +702,36 @@ impl fmt::Display for LaunchError {
     }
 }
 
+pub enum BiosError {
+    Io(io::Error),
+    InvalidSize { expected: usize, got: u64 },
+}
+
+impl From<io::Error> for BiosError {
+    fn from(value: io::Error) -> Self {
+        BiosError::Io(value)
+    }
+}
+
+/// Reads a BIOS image from `file`, which has to be exactly `SIZE` bytes long.
+pub fn read_bios<const SIZE: usize>(file: &mut fs::File) -> Result<Box<Bytes<SIZE>>, BiosError> {
+    let got = file.metadata()?.len();
+    if got != SIZE as u64 {
+        return Err(BiosError::InvalidSize {
+            expected: SIZE,
+            got,
+        });
+    }
+    let mut buf = unsafe { Box::<Bytes<SIZE>>::new_zeroed().assume_init() };
+    file.read_exact(&mut **buf)?;
+    Ok(buf)
+}
+
+/// Reads the ARM7 BIOS image at `path`, see [`read_bios`].
+pub fn load_arm7_bios(path: &Path) -> Result<Box<Bytes<{ arm7::BIOS_SIZE }>>, BiosError> {
+    read_bios(&mut fs::File::open(path)?)
+}
+
 pub struct Launch {
     pub sys_files: SysFiles,
     pub skip_firmware: bool,
@@ -745,20 +775,17 @@ impl Launch {
         let (arm7_bios, arm9_bios, firmware) = (
             if !prefer_hle_bios {
                 open_file!(&config.sys_paths.get().arm7_bios, Arm7Bios, |file| {
-                    let len = file.metadata()?.len();
-                    if len == arm7::BIOS_SIZE as u64 {
-                        let mut buf = unsafe {
-                            Box::<Bytes<{ arm7::BIOS_SIZE }>>::new_zeroed().assume_init()
-                        };
-                        file.read_exact(&mut **buf)?;
-                        Some(buf)
-                    } else {
-                        errors.push(LaunchError::InvalidSysFileLength {
-                            file: SystemFile::Arm7Bios,
-                            expected: arm7::BIOS_SIZE,
-                            got: len,
-                        });
-                        None
+                    match read_bios::<{ arm7::BIOS_SIZE }>(&mut file) {
+                        Ok(bios) => Some(bios),
+                        Err(BiosError::Io(err)) => return Err(err),
+                        Err(BiosError::InvalidSize { expected, got }) => {
+                            errors.push(LaunchError::InvalidSysFileLength {
+                                file: SystemFile::Arm7Bios,
+                                expected,
+                                got,
+                            });
+                            None
+                        }
                     }
                 })
             } else {
@@ -766,20 +793,17 @@ impl Launch {
             },
             if !prefer_hle_bios {
                 open_file!(&config.sys_paths.get().arm9_bios, Arm9Bios, |file| {
-                    let len = file.metadata()?.len();
-                    if len == arm9::BIOS_SIZE as u64 {
-                        let mut buf = unsafe {
-                            Box::<Bytes<{ arm9::BIOS_SIZE }>>::new_zeroed().assume_init()
-                        };
-                        file.read_exact(&mut **buf)?;
-                        Some(buf)
-                    } else {
-                        errors.push(LaunchError::InvalidSysFileLength {
-                            file: SystemFile::Arm9Bios,
-                            expected: arm9::BIOS_SIZE,
-                            got: len,
-                        });
-                        None
+                    match read_bios::<{ arm9::BIOS_SIZE }>(&mut file) {
+                        Ok(bios) => Some(bios),
+                        Err(BiosError::Io(err)) => return Err(err),
+                        Err(BiosError::InvalidSize { expected, got }) => {
+                            errors.push(LaunchError::InvalidSysFileLength {
+                                file: SystemFile::Arm9Bios,
+                                expected,
+                                got,
+                            });
+                            None
+                        }
                     }
                 })
             } else {
@@ -841,3 +865,28 @@ impl Launch {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrong_sized_bios() {
+        let path =
+            std::env::temp_dir().join(format!("dust-config-{}-bios.bin", std::process::id()));
+        fs::write(&path, vec![0; arm7::BIOS_SIZE + 4]).unwrap();
+        let result = load_arm7_bios(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            result,
+            Err(BiosError::InvalidSize {
+                expected: arm7::BIOS_SIZE,
+                got,
+            }) if got == arm7::BIOS_SIZE as u64 + 4
+        ));
+        assert!(matches!(
+            load_arm7_bios(&path),
+            Err(BiosError::Io(err)) if err.kind() == io::ErrorKind::NotFound
+        ));
+    }
+}
@@ -1,3 +1,4 @@
+pub mod command_log;
 #[cfg(feature = "dldi")]
 mod dldi;
 pub mod ds_slot_rom;
@@ -8,7 +9,14 @@ pub mod soft_renderer_3d;
 
 #[cfg(feature = "debug-views")]
 use super::debug_views;
-use crate::{audio, config::SysFiles, game_db::SaveType, input, FrameData};
+use crate::{
+    audio,
+    config::SysFiles,
+    game_db::{self, SaveType},
+    input,
+    utils::boxed_byte_slice_from_file,
+    FrameData,
+};
 use ds_slot_rom::DsSlotRom;
 #[cfg(feature = "xq-audio")]
 use dust_core::audio::{Audio, ChannelInterpMethod as AudioChannelInterpMethod};
@@ -33,9 +41,8 @@ use std::net::SocketAddr;
 use std::num::NonZeroU32;
 use std::{
     fs::{self, File},
-    hint,
-    io::{self, Read},
-    path::PathBuf,
+    hint, io,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -81,6 +88,11 @@ pub enum Message {
     UpdateSavePath(SavePathUpdate),
     UpdateSaveIntervalMs(f32),
 
+    ReplayRomCommandLog {
+        log_path: PathBuf,
+        output_path: PathBuf,
+    },
+
     UpdateRtcTimeOffsetSeconds(i64),
 
     UpdateRenderers {
@@ -121,6 +133,74 @@ pub struct DsSlot {
     pub has_ir: bool,
 }
 
+/// A ROM bundled with its save file, see [`load_cart`].
+pub struct Cart {
+    pub ds_slot: DsSlot,
+    pub save_path: Option<PathBuf>,
+    detected_save: Option<SaveType>,
+}
+
+impl Cart {
+    /// Returns the save type found for the ROM's game code in the database, if any; unlike
+    /// `ds_slot.save_type`, this isn't inferred from the save file's size.
+    #[inline]
+    pub fn detected_save(&self) -> Option<SaveType> {
+        self.detected_save
+    }
+}
+
+pub enum LoadCartError {
+    Rom(ds_slot_rom::CreationError),
+    Save(io::Error),
+}
+
+/// Loads the ROM at `rom_path` and determines its save type, from `game_db` or, failing that, from
+/// the size of the existing save file; a missing save file is created at the detected size,
+/// filled with 0xFF like fresh save memory.
+pub fn load_cart(
+    rom_path: &Path,
+    save_path: Option<&Path>,
+    model: Model,
+    in_memory_max_size: u32,
+    game_db: Option<&game_db::Database>,
+) -> Result<Cart, LoadCartError> {
+    let rom = DsSlotRom::new(rom_path, in_memory_max_size, model).map_err(LoadCartError::Rom)?;
+    let game_code = rom.game_code();
+    let detected_save = game_db
+        .and_then(|db| db.lookup(game_code))
+        .map(|entry| entry.save_type);
+    let mut save_type = detected_save;
+
+    if let Some(save_path) = save_path {
+        match fs::metadata(save_path) {
+            Ok(metadata) => {
+                if save_type.is_none() {
+                    save_type = SaveType::from_save_len(metadata.len() as usize);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                if let Some(len) = save_type.and_then(SaveType::expected_len) {
+                    if let Some(parent) = save_path.parent() {
+                        fs::create_dir_all(parent).map_err(LoadCartError::Save)?;
+                    }
+                    fs::write(save_path, vec![0xFF; len]).map_err(LoadCartError::Save)?;
+                }
+            }
+            Err(err) => return Err(LoadCartError::Save(err)),
+        }
+    }
+
+    Ok(Cart {
+        ds_slot: DsSlot {
+            rom,
+            save_type,
+            has_ir: game_code as u8 == b'I',
+        },
+        save_path: save_path.map(Path::to_path_buf),
+        detected_save,
+    })
+}
+
 #[cfg(feature = "dldi")]
 pub struct Dldi {
     pub root_path: PathBuf,
@@ -136,9 +216,7 @@ fn read_save_file_contents(save_path: &PathBuf) -> io::Result<Option<BoxedByteSl
         },
     };
     let save_len = save_file.metadata()?.len() as usize;
-    let mut save_contents = BoxedByteSlice::new_zeroed(save_len.next_power_of_two());
-    save_file.read_exact(&mut save_contents[..save_len])?;
-    Ok(Some(save_contents))
+    boxed_byte_slice_from_file(&mut save_file, save_len.next_power_of_two()).map(Some)
 }
 
 fn setup_ds_slot(
@@ -289,9 +367,24 @@ fn setup_ds_slot(
     }
 }
 
+/// Returns the frontend's ROM contents if a cart is inserted, see
+/// [`Contents::as_any_mut`](ds_slot::rom::Contents::as_any_mut).
+fn ds_slot_rom<E: cpu::Engine>(emu: &mut emu::Emu<E>) -> Option<&mut DsSlotRom> {
+    emu.ds_slot.rom.contents()?.as_any_mut()?.downcast_mut()
+}
+
 fn build_emu<E: cpu::Engine>(emu_builder: emu::Builder, engine: E) -> Option<emu::Emu<E>> {
     match emu_builder.build(engine) {
-        Ok(emu) => Some(emu),
+        Ok(emu) => {
+            if emu.ds_slot.rom.secure_area_decryption_failed() {
+                warning!(
+                    "Secure area decryption failed",
+                    "The ROM's secure area couldn't be decrypted with the provided ARM7 BIOS, the \
+                     game will most likely crash."
+                );
+            }
+            Some(emu)
+        }
         Err(err) => match err {
             emu::BuildError::MissingSysFiles => unreachable!("Missing emulator system files"),
             emu::BuildError::RomCreation(err) => match err {
@@ -299,11 +392,16 @@ fn build_emu<E: cpu::Engine>(emu_builder: emu::Builder, engine: E) -> Option<emu
                     unreachable!("Invalid DS slot ROM file size")
                 }
             },
-            emu::BuildError::RomNeedsDecryptionButNoBiosProvided => {
-                error!(
-                    "Emulator error",
-                    "Couldn't start emulator: ROM needs decryption but no BIOS provided."
-                );
+            emu::BuildError::RomSetup(err) => {
+                let reason = match err {
+                    ds_slot::rom::SetupError::MissingArm7Bios => {
+                        "ROM needs decryption or encryption but no BIOS provided"
+                    }
+                    ds_slot::rom::SetupError::InvalidSecureArea => {
+                        "the ROM's secure area is truncated"
+                    }
+                };
+                error!("Emulator error", "Couldn't start emulator: {reason}.");
                 None
             }
         },
@@ -527,10 +625,14 @@ pub(super) fn run(
 
                 Message::CreateSavestate { name, include_save } => {
                     let mut contents = Vec::new();
-                    if PersistentWriteSavestate::new(&mut contents)
-                        .store(&mut emu)
-                        .is_ok()
-                    {
+                    let stored = {
+                        let mut save = PersistentWriteSavestate::new(&mut contents);
+                        save.store(&mut emu).and_then(|()| {
+                            ds_slot_rom(&mut emu)
+                                .map_or(Ok(()), |rom| rom.store_savestate(&mut save))
+                        })
+                    };
+                    if stored.is_ok() {
                         notif!(Notification::SavestateCreated(
                             name,
                             Savestate {
@@ -565,7 +667,12 @@ pub(super) fn run(
 
                 Message::ApplySavestate(savestate) => {
                     if PersistentReadSavestate::new(&savestate.contents)
-                        .and_then(|mut savestate| savestate.load_into(&mut emu).map_err(drop))
+                        .and_then(|mut save| {
+                            save.load_into(&mut emu).map_err(drop)?;
+                            ds_slot_rom(&mut emu)
+                                .map_or(Ok(()), |rom| rom.load_savestate(&mut save))
+                                .map_err(drop)
+                        })
                         .is_ok()
                     {
                         if let Some(save) = savestate.save {
@@ -621,6 +728,31 @@ pub(super) fn run(
                     save_interval = Duration::from_secs_f32(value);
                 }
 
+                Message::ReplayRomCommandLog {
+                    log_path,
+                    output_path,
+                } => {
+                    if let ds_slot::rom::Rom::Normal(rom) = &mut emu.ds_slot.rom {
+                        let result = File::open(&log_path)
+                            .and_then(|log| Ok((log, File::create(&output_path)?)))
+                            .map_err(command_log::ParseError::Io)
+                            .and_then(|(log, output)| {
+                                command_log::replay(
+                                    rom,
+                                    io::BufReader::new(log),
+                                    io::BufWriter::new(output),
+                                    ds_slot::RomOutputLen::new(0x200),
+                                )
+                            });
+                        if let Err(err) = result {
+                            error!(
+                                "Command log error",
+                                "Couldn't replay ROM command log: {err}"
+                            );
+                        }
+                    }
+                }
+
                 Message::UpdateRtcTimeOffsetSeconds(value) => {
                     rtc_time_offset_seconds = value;
                     emu.rtc
@@ -868,3 +1000,93 @@ pub(super) fn run(
 
     frame_tx
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dust-emu-{}-{name}", std::process::id()))
+    }
+
+    fn test_rom(game_code: &[u8; 4]) -> Vec<u8> {
+        let mut rom = vec![0; 0x4000];
+        rom[0xC..0x10].copy_from_slice(game_code);
+        rom
+    }
+
+    // Builds a database the way it's read from disk, mapping each game code to its save type
+    fn test_db(entries: &[(&[u8; 4], &str)]) -> game_db::Database {
+        let entries = entries
+            .iter()
+            .map(|(game_code, save_type)| {
+                format!(
+                    r#"{{"code":{},"rom-size":16384,"save-type":"{save_type}"}}"#,
+                    u32::from_le_bytes(**game_code)
+                )
+            })
+            .collect::<Vec<_>>();
+        serde_json::from_str(&format!("[{}]", entries.join(","))).unwrap()
+    }
+
+    #[test]
+    fn missing_save_is_created() {
+        let dir = temp_dir("missing-save");
+        let rom_path = dir.join("game.nds");
+        // The save directory doesn't exist yet either
+        let save_path = dir.join("saves").join("game.sav");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&rom_path, test_rom(b"ABCE")).unwrap();
+
+        let db = test_db(&[(b"ABCE", "flash-2m")]);
+        let cart = load_cart(
+            &rom_path,
+            Some(save_path.as_path()),
+            Model::Ds,
+            0,
+            Some(&db),
+        )
+        .ok()
+        .unwrap();
+        assert!(cart.ds_slot.save_type == Some(SaveType::Flash2m));
+        let save = fs::read(&save_path);
+        drop(cart);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let save = save.unwrap();
+        assert_eq!(save.len(), 0x4_0000);
+        assert!(save.iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn detected_save_comes_from_db() {
+        let dir = temp_dir("detected-save");
+        let save_path = dir.join("game.sav");
+        fs::create_dir_all(&dir).unwrap();
+        // An existing 0x2000-byte (64 Kib EEPROM/FRAM) save doesn't override the database's type
+        fs::write(&save_path, vec![0xFF; 0x2000]).unwrap();
+        let db = test_db(&[(b"ABCE", "eeprom-4k"), (b"ABCJ", "nand-64m")]);
+
+        let mut results = Vec::new();
+        for game_code in [b"ABCE", b"ABCJ", b"ABCP"] {
+            let rom_path = dir.join("game.nds");
+            fs::write(&rom_path, test_rom(game_code)).unwrap();
+            let cart = load_cart(
+                &rom_path,
+                Some(save_path.as_path()),
+                Model::Ds,
+                0,
+                Some(&db),
+            )
+            .ok()
+            .unwrap();
+            results.push((cart.detected_save(), cart.ds_slot.save_type));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(results[0] == (Some(SaveType::Eeprom4k), Some(SaveType::Eeprom4k)));
+        assert!(results[1] == (Some(SaveType::Nand64m), Some(SaveType::Nand64m)));
+        // Unknown game codes fall back to the save file's size, which isn't reported as detected
+        assert!(results[2] == (None, Some(SaveType::EepromFram64k)));
+    }
+}
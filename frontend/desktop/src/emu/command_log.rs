@@ -0,0 +1,261 @@
+// Plain-text logs of raw DS slot ROM commands, i.e. to turn hardware captures into reproducible
+// inputs for `Rom::handle_rom_command`.
+//
+// Each non-empty line holds one command as 16 hex digits (spaces between bytes are allowed),
+// optionally preceded by the stage it was sent in (`initial`, `key1` or `key2`) and a colon;
+// anything after a `#` is a comment.
+
+use dust_core::{
+    ds_slot::{
+        rom::{
+            normal::{Normal, Stage},
+            recorder::{RecordedResponse, RomResponseRecorder},
+        },
+        RomOutputLen,
+    },
+    utils::Bytes,
+};
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    // The 1-based number of the line that couldn't be parsed
+    InvalidLine(usize),
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> Self {
+        ParseError::Io(err)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(err) => write!(f, "I/O error: {err}"),
+            ParseError::InvalidLine(line) => write!(f, "invalid command on line {line}"),
+        }
+    }
+}
+
+fn parse_stage(stage: &str) -> Option<Stage> {
+    match stage.trim() {
+        "initial" => Some(Stage::Initial),
+        "key1" => Some(Stage::Key1),
+        "key2" => Some(Stage::Key2),
+        _ => None,
+    }
+}
+
+fn stage_name(stage: Stage) -> &'static str {
+    match stage {
+        Stage::Initial => "initial",
+        Stage::Key1 => "key1",
+        Stage::Key2 => "key2",
+    }
+}
+
+fn parse_line(line: &str) -> Option<Option<(Option<Stage>, Bytes<8>)>> {
+    let line = line.split('#').next().unwrap_or_default().trim();
+    if line.is_empty() {
+        return Some(None);
+    }
+    let (stage, cmd) = match line.split_once(':') {
+        Some((stage, cmd)) => (Some(parse_stage(stage)?), cmd),
+        None => (None, line),
+    };
+    let digits = cmd
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<String>();
+    if digits.len() != 16 {
+        return None;
+    }
+    let mut bytes = Bytes::new([0; 8]);
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(digits.get(i << 1..(i + 1) << 1)?, 16).ok()?;
+    }
+    Some(Some((stage, bytes)))
+}
+
+/// Iterates over the commands in a log, yielding each one along with its stage, if specified.
+pub struct CommandLog<R: BufRead> {
+    lines: io::Lines<R>,
+    line_number: usize,
+}
+
+impl<R: BufRead> CommandLog<R> {
+    pub fn new(reader: R) -> Self {
+        CommandLog {
+            lines: reader.lines(),
+            line_number: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CommandLog<R> {
+    type Item = Result<(Option<Stage>, Bytes<8>), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            self.line_number += 1;
+            match parse_line(&line) {
+                Some(Some(command)) => return Some(Ok(command)),
+                Some(None) => {}
+                None => return Some(Err(ParseError::InvalidLine(self.line_number))),
+            }
+        }
+    }
+}
+
+/// Writes commands in the format read by [`CommandLog`].
+pub struct CommandLogWriter<W: Write>(pub W);
+
+impl<W: Write> CommandLogWriter<W> {
+    pub fn write_command(&mut self, stage: Option<Stage>, cmd: &Bytes<8>) -> io::Result<()> {
+        if let Some(stage) = stage {
+            write!(self.0, "{}: ", stage_name(stage))?;
+        }
+        for byte in cmd.iter() {
+            write!(self.0, "{byte:02X}")?;
+        }
+        writeln!(self.0)
+    }
+
+    /// Writes a recorded command followed by its response, as comment lines of up to 32 bytes
+    /// each (so the output can itself be replayed).
+    pub fn write_response(&mut self, response: &RecordedResponse) -> io::Result<()> {
+        self.write_command(Some(response.stage), &Bytes::new(response.cmd))?;
+        for line in response.output.chunks(0x20) {
+            write!(self.0, "#")?;
+            for byte in line {
+                write!(self.0, " {byte:02X}")?;
+            }
+            writeln!(self.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends the commands in `log` to `rom` through a [`RomResponseRecorder`], writing the responses
+/// to `output` with [`CommandLogWriter::write_response`]; the cart's stage is restored afterwards.
+pub fn replay(
+    rom: &mut Normal,
+    log: impl BufRead,
+    output: impl Write,
+    output_len: RomOutputLen,
+) -> Result<(), ParseError> {
+    let commands = CommandLog::new(log)
+        .map(|command| command.map(|(stage, cmd)| (stage, *cmd)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let prev_stage = rom.stage();
+    let recorder = RomResponseRecorder::record(rom, &commands, output_len);
+    rom.set_stage(prev_stage);
+
+    let mut writer = CommandLogWriter(output);
+    for response in recorder.responses() {
+        writer.write_response(response)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dust_core::{
+        utils::{mem_prelude::*, BoxedByteSlice},
+        Model,
+    };
+
+    const LOG: &str = "\
+# Captured at boot
+initial: 9F 00 00 00 00 00 00 00
+
+key1: 3C00000000000000 # switch to KEY1
+B700008000000000
+";
+
+    #[test]
+    fn round_trip() {
+        let commands = CommandLog::new(LOG.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0].0, Some(Stage::Initial));
+        assert_eq!(commands[1].0, Some(Stage::Key1));
+        assert_eq!(commands[2].0, None);
+        assert_eq!(*commands[2].1, [0xB7, 0, 0, 0x80, 0, 0, 0, 0]);
+
+        let mut output = Vec::new();
+        let mut writer = CommandLogWriter(&mut output);
+        for (stage, cmd) in &commands {
+            writer.write_command(*stage, cmd).unwrap();
+        }
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "initial: 9F00000000000000\nkey1: 3C00000000000000\nB700008000000000\n"
+        );
+
+        let reparsed = CommandLog::new(output.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(reparsed.len(), commands.len());
+        for ((stage, cmd), (orig_stage, orig_cmd)) in reparsed.iter().zip(&commands) {
+            assert_eq!(stage, orig_stage);
+            assert_eq!(**cmd, **orig_cmd);
+        }
+
+        assert!(matches!(
+            CommandLog::new("9F00\n".as_bytes()).next(),
+            Some(Err(ParseError::InvalidLine(1)))
+        ));
+        assert!(matches!(
+            CommandLog::new("\nkey3: 9F00000000000000\n".as_bytes()).next(),
+            Some(Err(ParseError::InvalidLine(2)))
+        ));
+    }
+
+    #[test]
+    fn replay_writes_responses() {
+        let mut contents = BoxedByteSlice::new_zeroed(0x1_0000);
+        contents.write_le(0x20, 0x4000_u32);
+        contents[0x8000..0x8004].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        let mut rom = Normal::new(
+            Box::new(contents),
+            None,
+            Model::Ds,
+            #[cfg(feature = "log")]
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+        rom.set_stage(Stage::Key1);
+
+        let mut output = Vec::new();
+        replay(
+            &mut rom,
+            "key2: B7 00 00 80 00 00 00 00\n".as_bytes(),
+            &mut output,
+            RomOutputLen::new(4),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output.clone()).unwrap(),
+            "key2: B700008000000000\n# DE AD BE EF\n"
+        );
+        assert_eq!(rom.stage(), Stage::Key1);
+
+        // The output is a valid log itself, replaying to the same responses
+        let mut replayed = Vec::new();
+        replay(&mut rom, &output[..], &mut replayed, RomOutputLen::new(4)).unwrap();
+        assert_eq!(replayed, output);
+    }
+}
@@ -1,115 +1,311 @@
+use crate::{
+    config::{load_arm7_bios, BiosError},
+    utils::boxed_byte_slice_from_reader,
+};
 use dust_core::{
-    ds_slot::rom::{self, Contents},
-    utils::{mem_prelude::*, BoxedByteSlice, Bytes},
+    ds_slot::rom::{
+        self,
+        header::{Header, UnitCode},
+        key1,
+        normal::{self, Normal},
+        Contents,
+    },
+    utils::{BoxedByteSlice, Bytes, ReadSavestate, WriteSavestate},
     Model,
 };
 use std::{
+    any::Any,
     fs,
     io::{self, Read, Seek, SeekFrom},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-pub struct File {
-    file: fs::File,
+struct Reader<R: Read + Seek = fs::File> {
+    file: io::BufReader<R>,
+    // The current position inside the file, if known
+    pos: Option<u64>,
+}
+
+impl<R: Read + Seek> Reader<R> {
+    fn new(file: R) -> Self {
+        Reader {
+            file: io::BufReader::new(file),
+            pos: None,
+        }
+    }
+
+    // Avoids seeking when reads are sequential, and keeps the buffered data around when seeking
+    // to nearby positions
+    fn read_at(&mut self, addr: u64, buf: &mut [u8]) -> io::Result<()> {
+        let result = match self.pos {
+            Some(pos) if pos == addr => Ok(()),
+            Some(pos) => self.file.seek_relative(addr.wrapping_sub(pos) as i64),
+            None => self.file.seek(SeekFrom::Start(addr)).map(drop),
+        }
+        .and_then(|()| self.file.read_exact(buf));
+        self.pos = result.is_ok().then_some(addr + buf.len() as u64);
+        result
+    }
+}
+
+struct SplitPart {
+    reader: Reader,
+    start: u64,
+    len: u64,
+}
+
+// Reads from a ROM image split across one or more consecutive files (see `split_part_paths`),
+// translating image addresses to per-file ones; reads straddling a split point are serviced from
+// both files. Also implements `Read`, reading sequentially from the start of the image.
+struct SplitReader {
+    parts: Vec<SplitPart>,
+    pos: u64,
+}
+
+impl SplitReader {
+    fn new(files: Vec<(fs::File, u64)>) -> Self {
+        let mut start = 0;
+        SplitReader {
+            parts: files
+                .into_iter()
+                .map(|(file, len)| {
+                    let part = SplitPart {
+                        reader: Reader::new(file),
+                        start,
+                        len,
+                    };
+                    start += len;
+                    part
+                })
+                .collect(),
+            pos: 0,
+        }
+    }
+
+    fn read_at(&mut self, mut addr: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        let mut i = self
+            .parts
+            .partition_point(|part| part.start <= addr)
+            .saturating_sub(1);
+        while !buf.is_empty() {
+            let Some(part) = self.parts.get_mut(i) else {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            };
+            let len = (part.start + part.len)
+                .saturating_sub(addr)
+                .min(buf.len() as u64) as usize;
+            let (part_buf, rest) = buf.split_at_mut(len);
+            part.reader.read_at(addr - part.start, part_buf)?;
+            addr += len as u64;
+            buf = rest;
+            i += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Read for SplitReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos;
+        let Some(part) = self
+            .parts
+            .iter_mut()
+            .find(|part| pos < part.start + part.len)
+        else {
+            return Ok(0);
+        };
+        let len = (part.start + part.len - pos).min(buf.len() as u64) as usize;
+        part.reader.read_at(pos - part.start, &mut buf[..len])?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+/// Returns the paths of all the parts of the ROM dump whose first part is at `path`; any further
+/// parts are named after the first one (`<name>.nds.1`, `<name>.nds.2`, ...), stopping at the
+/// first missing part.
+fn split_part_paths(path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![path.to_path_buf()];
+    loop {
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(format!(".{}", paths.len()));
+        let part_path = PathBuf::from(part_path);
+        if !part_path.is_file() {
+            break;
+        }
+        paths.push(part_path);
+    }
+    paths
+}
+
+/// Opens all the parts of the ROM at `path` (see [`split_part_paths`]), returning them along with
+/// their sizes and the total size.
+fn open_parts(path: &Path) -> io::Result<(Vec<(fs::File, u64)>, u64)> {
+    let paths = split_part_paths(path);
+    let mut files = Vec::with_capacity(paths.len());
+    let mut len = 0;
+    for path in &paths {
+        let file = fs::File::open(path)?;
+        let part_len = file.metadata()?.len();
+        files.push((file, part_len));
+        len += part_len;
+    }
+    Ok((files, len))
+}
+
+// Reads `output.len()` bytes at `addr` from a ROM image of `len` bytes, zero-filling the part past
+// the end of a trimmed ROM's data
+fn read_padded(file: &mut SplitReader, len: usize, addr: usize, output: &mut [u8]) {
+    let read_len = output.len().min(len.saturating_sub(addr));
+    // Only reads past the end of a trimmed ROM's data need padding
+    if read_len < output.len() {
+        output[read_len..].fill(0);
+    }
+    file.read_at(addr as u64, &mut output[..read_len])
+        .expect("couldn't read DS slot ROM data");
+}
+
+/// File-backed [`Contents`] for a ROM image split across one or more consecutive files, which
+/// are read on demand.
+///
+/// The secure area and DLDI overlays are kept in memory so that they can be modified in place.
+pub struct SplitContents {
+    file: SplitReader,
     len: usize,
     game_code: u32,
-    secure_area_start: usize,
-    secure_area_end: usize,
-    secure_area: Option<Option<Box<Bytes<0x800>>>>,
-    dldi_area_start: usize,
-    dldi_area_end: usize,
-    dldi_area: Option<Option<BoxedByteSlice>>,
+    overlays: rom::Overlays,
+}
+
+impl SplitContents {
+    /// Creates contents reading from `files`, the ordered parts of the ROM image along with their
+    /// lengths.
+    pub fn new(files: Vec<(fs::File, u64)>) -> io::Result<Self> {
+        let len = files.iter().map(|(_, len)| len).sum::<u64>();
+        let len = usize::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut file = SplitReader::new(files);
+
+        let mut header_bytes = Bytes::new([0; 0x170]);
+        file.read_at(0, &mut *header_bytes)?;
+        let header = Header::parse(&header_bytes)
+            // NOTE: The header was just read in full, this should never occur.
+            .expect("couldn't parse DS slot ROM header");
+
+        Ok(SplitContents {
+            file,
+            len,
+            game_code: header.game_code().0,
+            overlays: rom::Overlays::new(header.arm9_rom_offset() as usize),
+        })
+    }
 }
 
-impl Contents for File {
+impl Contents for SplitContents {
     fn len(&self) -> usize {
         self.len.next_power_of_two()
     }
 
+    fn actual_len(&self) -> usize {
+        self.len
+    }
+
     fn game_code(&self) -> u32 {
         self.game_code
     }
 
     fn secure_area_mut(&mut self) -> Option<&mut [u8]> {
-        self.secure_area
-            .get_or_insert_with(|| {
-                let mut buf = unsafe { Box::<Bytes<0x800>>::new_zeroed().assume_init() };
-                self.file
-                    .seek(SeekFrom::Start(self.secure_area_start as u64))
-                    .and_then(|_| self.file.read_exact(&mut **buf))
-                    .ok()
-                    .map(|_| buf)
-            })
-            .as_mut()
-            .map(|bytes| bytes.as_mut_slice())
+        let SplitContents {
+            file,
+            len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.secure_area_mut(|addr, output| read_padded(file, *len, addr, output)))
+    }
+
+    fn secure_area(&mut self) -> Option<&[u8]> {
+        let SplitContents {
+            file,
+            len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.secure_area(|addr, output| read_padded(file, *len, addr, output)))
+    }
+
+    fn prefetch_secure_area(&mut self) {
+        self.secure_area();
     }
 
     fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
-        self.dldi_area
-            .get_or_insert_with(|| {
-                self.dldi_area_start = addr;
-                self.dldi_area_end = addr + len;
-                let mut buf = BoxedByteSlice::new_zeroed(len);
-                self.file
-                    .seek(SeekFrom::Start(self.dldi_area_start as u64))
-                    .and_then(|_| self.file.read_exact(&mut buf))
-                    .ok()
-                    .map(|_| buf)
-            })
-            .as_mut()
-            .map(|dldi_area| &mut **dldi_area)
+        let SplitContents {
+            file,
+            len: rom_len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.dldi_area_mut(addr, len, |addr, output| {
+            read_padded(file, *rom_len, addr, output);
+        }))
+    }
+
+    fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        let start = self.twl_region()?.secure_area_start;
+        let SplitContents {
+            file,
+            len,
+            overlays,
+            ..
+        } = self;
+        Some(overlays.twl_secure_area_mut(start, |addr, output| {
+            read_padded(file, *len, addr, output);
+        }))
     }
 
     fn read_header(&mut self, buf: &mut Bytes<0x170>) {
         self.file
-            .seek(SeekFrom::Start(0))
-            .and_then(|_| self.file.read_exact(&mut **buf))
+            .read_at(0, &mut **buf)
             // NOTE: The ROM file's size is ensured beforehand, this should never occur.
             .expect("couldn't read DS slot ROM header");
     }
 
     fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
-        self.file
-            .seek(SeekFrom::Start(addr as u64))
-            .and_then(|_| {
-                let read_len = output.len().min(self.len - addr);
-                output[read_len..].fill(0);
-                self.file.read_exact(&mut output[..read_len])
-            })
-            .expect("couldn't read DS slot ROM data");
-        macro_rules! apply_overlay {
-            ($bytes: expr, $start: expr, $end: expr) => {
-                if let Some(Some(bytes)) = $bytes {
-                    if addr < $end && addr + output.len() > $start {
-                        let (start_src_i, start_dst_i) = if addr < $start {
-                            (0, $start - addr)
-                        } else {
-                            (addr - $start, 0)
-                        };
-                        let len = output.len().min(0x800 - start_src_i);
-                        output[start_dst_i..start_dst_i + len]
-                            .copy_from_slice(&bytes[start_src_i..start_src_i + len]);
-                    }
-                }
-            };
-        }
-        apply_overlay!(
-            &self.secure_area,
-            self.secure_area_start,
-            self.secure_area_end
-        );
-        apply_overlay!(&self.dldi_area, self.dldi_area_start, self.dldi_area_end);
+        read_padded(&mut self.file, self.len, addr, output);
+        self.overlays.apply(addr, output);
     }
 }
 
 pub enum DsSlotRom {
-    File(File),
+    File(SplitContents),
     Memory(BoxedByteSlice),
 }
 
 pub enum CreationError {
     InvalidFileSize(u64),
     Io(io::Error),
+    /// The ARM7 BIOS file wasn't exactly `arm7::BIOS_SIZE` bytes long.
+    InvalidBiosSize(u64),
+    BiosIo(io::Error),
+}
+
+/// The category of a [`CreationError`], for callers that only need to branch on the cause of
+/// the failure without matching on (and having to update for) every variant's details.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CreationErrorKind {
+    InvalidFileSize,
+    Io,
+    InvalidBiosSize,
+    BiosIo,
+}
+
+impl CreationError {
+    pub fn kind(&self) -> CreationErrorKind {
+        match self {
+            CreationError::InvalidFileSize(_) => CreationErrorKind::InvalidFileSize,
+            CreationError::Io(_) => CreationErrorKind::Io,
+            CreationError::InvalidBiosSize(_) => CreationErrorKind::InvalidBiosSize,
+            CreationError::BiosIo(_) => CreationErrorKind::BiosIo,
+        }
+    }
 }
 
 impl From<io::Error> for CreationError {
@@ -120,8 +316,7 @@ impl From<io::Error> for CreationError {
 
 impl DsSlotRom {
     pub fn new(path: &Path, in_memory_max_size: u32, model: Model) -> Result<Self, CreationError> {
-        let mut file = fs::File::open(path)?;
-        let len = file.metadata()?.len();
+        let (files, len) = open_parts(path)?;
         if len > usize::MAX as u64 || !rom::is_valid_size((len as usize).next_power_of_two(), model)
         {
             return Err(CreationError::InvalidFileSize(len));
@@ -131,29 +326,193 @@ impl DsSlotRom {
         let read_to_memory = len <= in_memory_max_size as usize;
 
         Ok(if read_to_memory {
-            let mut bytes = BoxedByteSlice::new_zeroed(len.next_power_of_two());
-            file.read_exact(&mut bytes[..len])?;
-            DsSlotRom::Memory(bytes)
+            DsSlotRom::Memory(boxed_byte_slice_from_reader(
+                &mut SplitReader::new(files),
+                len.next_power_of_two(),
+            )?)
         } else {
-            let mut header_bytes = Bytes::new([0; 0x170]);
-            file.read_exact(&mut *header_bytes)?;
-
-            let game_code = header_bytes.read_le::<u32>(0x0C);
-            let secure_area_start = header_bytes.read_le::<u32>(0x20) as usize;
-
-            DsSlotRom::File(File {
-                file,
-                len,
-                game_code,
-                secure_area_start,
-                secure_area_end: secure_area_start + 0x800,
-                secure_area: None,
-                dldi_area_start: 0,
-                dldi_area_end: 0,
-                dldi_area: None,
-            })
+            DsSlotRom::File(SplitContents::new(files)?)
         })
     }
+
+    /// Builds a [`Normal`] ROM from these contents, with the KEY1 tables from the ARM7 BIOS at
+    /// `arm7_bios_path` (if any, see [`load_arm7_bios`]); a BIOS of the wrong size is rejected
+    /// with [`CreationError::InvalidBiosSize`] instead of being truncated or padded.
+    pub fn into_normal(
+        self,
+        arm7_bios_path: Option<&Path>,
+        model: Model,
+        #[cfg(feature = "log")] logger: slog::Logger,
+    ) -> Result<Normal, CreationError> {
+        let arm7_bios =
+            arm7_bios_path
+                .map(load_arm7_bios)
+                .transpose()
+                .map_err(|err| match err {
+                    BiosError::Io(err) => CreationError::BiosIo(err),
+                    BiosError::InvalidSize { got, .. } => CreationError::InvalidBiosSize(got),
+                })?;
+        let len = self.actual_len() as u64;
+        Normal::new(
+            self.into(),
+            arm7_bios.as_deref(),
+            model,
+            #[cfg(feature = "log")]
+            logger,
+        )
+        .map_err(|err| match err {
+            normal::CreationError::InvalidSize => CreationError::InvalidFileSize(len),
+        })
+    }
+}
+
+/// Basic information about a ROM file, read from its header without loading it.
+#[derive(Clone, Copy, Debug)]
+pub struct RomProbe {
+    pub len: u64,
+    pub game_code: u32,
+    /// Whether the file's size is one [`DsSlotRom::new`] would accept.
+    pub valid_size: bool,
+    pub unit_code: Result<UnitCode, u8>,
+    /// Whether the secure area is still encrypted, as in a retail dump; always `false` for
+    /// homebrew, which has no secure area.
+    pub secure_area_encrypted: bool,
+}
+
+/// Reads just the header (and the start of the secure area) of the ROM at `path`, i.e. to quickly
+/// filter a directory of files before loading any of them with [`DsSlotRom::new`].
+pub fn probe(path: &Path, model: Model) -> Result<RomProbe, CreationError> {
+    let (files, len) = open_parts(path)?;
+    if len < 0x170 {
+        return Err(CreationError::InvalidFileSize(len));
+    }
+    let valid_size =
+        len <= usize::MAX as u64 && rom::is_valid_size((len as usize).next_power_of_two(), model);
+    let mut file = SplitReader::new(files);
+
+    let mut header_bytes = Bytes::new([0; 0x170]);
+    file.read_at(0, &mut *header_bytes)?;
+    let header = Header::parse(&header_bytes)
+        // NOTE: The header was just read in full, this should never occur.
+        .expect("couldn't parse DS slot ROM header");
+
+    let secure_area_start = header.arm9_rom_offset() as u64;
+    let secure_area_encrypted =
+        if (0x4000..0x8000).contains(&secure_area_start) && secure_area_start + 8 <= len {
+            let mut secure_area_id = [0; 8];
+            file.read_at(secure_area_start, &mut secure_area_id)?;
+            !key1::is_secure_area_decrypted(&secure_area_id)
+        } else {
+            false
+        };
+
+    Ok(RomProbe {
+        len,
+        game_code: header.game_code().0,
+        valid_size,
+        unit_code: header.unit_code(),
+        secure_area_encrypted,
+    })
+}
+
+// Inherent shorthands for the UI, so that it doesn't need to import `Contents`
+impl DsSlotRom {
+    /// Returns the power-of-two size the ROM is addressed as, see [`Contents::len`].
+    pub fn addressable_len(&self) -> usize {
+        Contents::len(self)
+    }
+
+    /// Returns the size of the ROM data actually present, which is smaller than
+    /// [`addressable_len`](Self::addressable_len) for trimmed ROMs.
+    pub fn actual_len(&self) -> usize {
+        Contents::actual_len(self)
+    }
+
+    pub fn game_code(&self) -> u32 {
+        Contents::game_code(self)
+    }
+
+    /// Returns the ROM version (revision) from the header, see [`Header::version`].
+    pub fn rom_version(&mut self) -> u8 {
+        let mut header_bytes = Bytes::new([0; 0x170]);
+        self.read_header(&mut header_bytes);
+        Header::parse(&header_bytes)
+            // NOTE: The header was just read in full, this should never occur.
+            .expect("couldn't parse DS slot ROM header")
+            .version()
+    }
+
+    /// Returns the game title from the header, see [`Header::game_title`].
+    pub fn game_title(&mut self) -> Option<String> {
+        let mut header_bytes = Bytes::new([0; 0x170]);
+        self.read_header(&mut header_bytes);
+        Header::parse(&header_bytes)
+            // NOTE: The header was just read in full, this should never occur.
+            .expect("couldn't parse DS slot ROM header")
+            .game_title()
+            .map(str::to_owned)
+    }
+
+    /// Returns the number of bytes of ROM data held in memory: the whole (padded) ROM for
+    /// in-memory ones, and only the loaded overlays for file-backed ones.
+    pub fn memory_footprint(&self) -> usize {
+        match self {
+            DsSlotRom::File(contents) => contents.overlays.loaded_len(),
+            DsSlotRom::Memory(bytes) => bytes.len(),
+        }
+    }
+}
+
+// Stores an area that setup may have modified in place, prefixed by its length (0 if there's none)
+fn store_area<S: WriteSavestate>(save: &mut S, area: Option<&mut [u8]>) {
+    let area = area.map_or(&[][..], |area| &*area);
+    save.store_raw(area.len() as u32);
+    for &byte in area {
+        save.store_raw(byte);
+    }
+}
+
+fn load_area<S: ReadSavestate>(save: &mut S, area: Option<&mut [u8]>) -> Result<(), S::Error> {
+    let len = save.load_raw::<u32>()? as usize;
+    match area {
+        Some(area) if area.len() == len => {
+            for byte in area {
+                *byte = save.load_raw()?;
+            }
+            Ok(())
+        }
+        None if len == 0 => Ok(()),
+        _ => Err(S::invalid_enum()),
+    }
+}
+
+// The ROM data itself is never written to, so only the areas that setup modifies in place (the
+// secure areas, which are decrypted or encrypted depending on the boot mode) are saved; the DLDI
+// area is patched the same way on every setup. Both variants save the same data, so savestates
+// stay compatible when the in-memory size limit changes.
+impl DsSlotRom {
+    pub fn store_savestate<S: WriteSavestate>(&mut self, save: &mut S) -> Result<(), S::Error> {
+        save.start_field(b"game_code")?;
+        save.store_raw(self.game_code());
+        save.start_field(b"secure_area")?;
+        store_area(save, self.secure_area_mut());
+        save.start_field(b"twl_secure_area")?;
+        store_area(save, self.twl_secure_area_mut());
+        Ok(())
+    }
+
+    /// Restores the secure areas saved by [`store_savestate`](Self::store_savestate); fails if
+    /// the savestate was created for a different game.
+    pub fn load_savestate<S: ReadSavestate>(&mut self, save: &mut S) -> Result<(), S::Error> {
+        save.start_field(b"game_code")?;
+        if save.load_raw::<u32>()? != self.game_code() {
+            return Err(S::invalid_enum());
+        }
+        save.start_field(b"secure_area")?;
+        load_area(save, self.secure_area_mut())?;
+        save.start_field(b"twl_secure_area")?;
+        load_area(save, self.twl_secure_area_mut())
+    }
 }
 
 macro_rules! forward_to_variants {
@@ -171,6 +530,10 @@ impl Contents for DsSlotRom {
         forward_to_variants!(DsSlotRom; File, Memory; self, len())
     }
 
+    fn actual_len(&self) -> usize {
+        forward_to_variants!(DsSlotRom; File, Memory; self, actual_len())
+    }
+
     fn game_code(&self) -> u32 {
         forward_to_variants!(DsSlotRom; File, Memory; self, game_code())
     }
@@ -179,10 +542,18 @@ impl Contents for DsSlotRom {
         forward_to_variants!(DsSlotRom; File, Memory; self, secure_area_mut())
     }
 
+    fn prefetch_secure_area(&mut self) {
+        forward_to_variants!(DsSlotRom; File, Memory; self, prefetch_secure_area());
+    }
+
     fn dldi_area_mut(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
         forward_to_variants!(DsSlotRom; File, Memory; self, dldi_area_mut(addr, len))
     }
 
+    fn twl_secure_area_mut(&mut self) -> Option<&mut [u8]> {
+        forward_to_variants!(DsSlotRom; File, Memory; self, twl_secure_area_mut())
+    }
+
     fn read_header(&mut self, buf: &mut Bytes<0x170>) {
         forward_to_variants!(DsSlotRom; File, Memory; self, read_header(buf));
     }
@@ -190,13 +561,306 @@ impl Contents for DsSlotRom {
     fn read_slice(&mut self, addr: usize, output: &mut [u8]) {
         forward_to_variants!(DsSlotRom; File, Memory; self, read_slice(addr, output));
     }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
 }
 
+// The ROM is boxed as-is (instead of unwrapping the variants) so that the emulator thread can get
+// it back through `Contents::as_any_mut` to save it
 impl From<DsSlotRom> for Box<dyn Contents> {
     fn from(rom: DsSlotRom) -> Self {
-        match rom {
-            DsSlotRom::File(file) => Box::new(file),
-            DsSlotRom::Memory(bytes) => Box::new(bytes),
+        Box::new(rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dust_core::{
+        cpu::arm7,
+        utils::{mem_prelude::*, PersistentReadSavestate, PersistentWriteSavestate},
+    };
+
+    struct CountingSeeks {
+        inner: io::Cursor<Vec<u8>>,
+        seeks: usize,
+    }
+
+    impl Read for CountingSeeks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
         }
     }
+
+    impl Seek for CountingSeeks {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.seeks += 1;
+            self.inner.seek(pos)
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dust-ds-slot-rom-{}-{name}", std::process::id()))
+    }
+
+    fn test_rom(len: usize) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..len).map(|i| (i ^ i >> 8) as u8).collect();
+        rom[..0x170].fill(0);
+        rom[0xC..0x10].copy_from_slice(b"ABCE");
+        rom
+    }
+
+    #[test]
+    fn trimmed_file_sizes() {
+        let path = temp_path("trimmed.nds");
+        fs::write(&path, test_rom(0x3000)).unwrap();
+        let rom = DsSlotRom::new(&path, 0, Model::Ds).ok().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(rom, DsSlotRom::File(_)));
+        assert_eq!(rom.addressable_len(), 0x4000);
+        assert_eq!(rom.actual_len(), 0x3000);
+        assert_eq!(rom.game_code(), u32::from_le_bytes(*b"ABCE"));
+    }
+
+    #[test]
+    fn memory_footprint() {
+        let path = temp_path("footprint.nds");
+        fs::write(&path, test_rom(0x3000)).unwrap();
+        let mut in_memory = DsSlotRom::new(&path, u32::MAX, Model::Ds).ok().unwrap();
+        let mut file = DsSlotRom::new(&path, 0, Model::Ds).ok().unwrap();
+
+        // In-memory ROMs are padded to their addressable size, while file-backed ones only hold
+        // the overlays that were loaded
+        assert!(matches!(in_memory, DsSlotRom::Memory(_)));
+        assert_eq!(in_memory.memory_footprint(), 0x4000);
+        assert_eq!(file.memory_footprint(), 0);
+        in_memory.prefetch_secure_area();
+        file.prefetch_secure_area();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(in_memory.memory_footprint(), 0x4000);
+        assert_eq!(file.memory_footprint(), 0x800);
+    }
+
+    #[test]
+    fn in_memory_padding() {
+        let path = temp_path("padding.nds");
+        let data = test_rom(0x3000);
+        fs::write(&path, &data).unwrap();
+        let mut rom = DsSlotRom::new(&path, u32::MAX, Model::Ds).ok().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut output = vec![0xFF; 0x4000];
+        rom.read_slice(0, &mut output);
+        assert_eq!(&output[..0x3000], &data[..]);
+        assert!(output[0x3000..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn split_files() {
+        let path = temp_path("split.nds");
+        let data = test_rom(0x4000);
+        // The second part is small enough for a read to straddle both of its split points
+        let split_points = [0x2100, 0x2108];
+        fs::write(&path, &data[..split_points[0]]).unwrap();
+        fs::write(
+            temp_path("split.nds.1"),
+            &data[split_points[0]..split_points[1]],
+        )
+        .unwrap();
+        fs::write(temp_path("split.nds.2"), &data[split_points[1]..]).unwrap();
+        let mut in_memory = DsSlotRom::new(&path, u32::MAX, Model::Ds).ok().unwrap();
+        let mut file = DsSlotRom::new(&path, 0, Model::Ds).ok().unwrap();
+        for part_path in [path, temp_path("split.nds.1"), temp_path("split.nds.2")] {
+            fs::remove_file(part_path).unwrap();
+        }
+
+        assert!(matches!(file, DsSlotRom::File(_)));
+        assert_eq!(file.actual_len(), 0x4000);
+        let mut output = [0; 0x20];
+        file.read_slice(0x20F0, &mut output);
+        assert_eq!(output[..], data[0x20F0..0x2110]);
+        file.read_slice(0x2104, &mut output[..2]);
+        assert_eq!(output[..2], data[0x2104..0x2106]);
+
+        let mut output = vec![0; 0x4000];
+        in_memory.read_slice(0, &mut output);
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn creation_error_kinds() {
+        assert_eq!(
+            CreationError::InvalidFileSize(0x100).kind(),
+            CreationErrorKind::InvalidFileSize
+        );
+        assert_eq!(
+            CreationError::from(io::Error::from(io::ErrorKind::NotFound)).kind(),
+            CreationErrorKind::Io
+        );
+        let Err(err) = DsSlotRom::new(&temp_path("missing.nds"), 0, Model::Ds) else {
+            panic!("opened a missing ROM file");
+        };
+        assert_eq!(err.kind(), CreationErrorKind::Io);
+    }
+
+    #[test]
+    fn short_bios() {
+        let path = temp_path("short-bios.nds");
+        let bios_path = temp_path("short-bios.bin");
+        fs::write(&path, test_rom(0x4000)).unwrap();
+        fs::write(&bios_path, vec![0; arm7::BIOS_SIZE - 0x10]).unwrap();
+        let result = DsSlotRom::new(&path, 0, Model::Ds)
+            .ok()
+            .unwrap()
+            .into_normal(
+                Some(&bios_path),
+                Model::Ds,
+                #[cfg(feature = "log")]
+                slog::Logger::root(slog::Discard, slog::o!()),
+            );
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&bios_path).unwrap();
+        let Err(err) = result else {
+            panic!("accepted a short ARM7 BIOS");
+        };
+        assert_eq!(err.kind(), CreationErrorKind::InvalidBiosSize);
+        assert!(matches!(
+            err,
+            CreationError::InvalidBiosSize(len) if len == arm7::BIOS_SIZE as u64 - 0x10
+        ));
+    }
+
+    #[test]
+    fn probe_files() {
+        let path = temp_path("probe.nds");
+        let mut rom = test_rom(0x8000);
+        rom[0x12] = 3;
+        rom.write_le::<u32>(0x20, 0x4000);
+        fs::write(&path, &rom).unwrap();
+        let valid = probe(&path, Model::Ds).ok().unwrap();
+        assert_eq!(valid.len, 0x8000);
+        assert_eq!(valid.game_code, u32::from_le_bytes(*b"ABCE"));
+        assert!(valid.valid_size);
+        assert_eq!(valid.unit_code, Ok(UnitCode::Dsi));
+        assert!(valid.secure_area_encrypted);
+
+        rom.write_le::<u64>(0x4000, key1::DECRYPTED_SECURE_AREA_ID);
+        fs::write(&path, &rom).unwrap();
+        assert!(!probe(&path, Model::Ds).ok().unwrap().secure_area_encrypted);
+
+        // Too small to be a valid DSi ROM, but still large enough to contain a header
+        fs::write(&path, &rom[..0x800]).unwrap();
+        assert!(probe(&path, Model::Ds).ok().unwrap().valid_size);
+        assert!(!probe(&path, Model::Dsi).ok().unwrap().valid_size);
+
+        fs::write(&path, &rom[..0x100]).unwrap();
+        let result = probe(&path, Model::Ds);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(CreationError::InvalidFileSize(0x100))));
+    }
+
+    #[test]
+    fn sequential_reads_skip_seeking() {
+        let data = (0..0x1_0000)
+            .map(|i: u32| (i ^ i >> 8) as u8)
+            .collect::<Vec<_>>();
+        let mut reader = Reader::new(CountingSeeks {
+            inner: io::Cursor::new(data.clone()),
+            seeks: 0,
+        });
+        let mut buf = [0; 0x100];
+        for addr in (0x4000..0x8000).step_by(buf.len()) {
+            reader.read_at(addr as u64, &mut buf).unwrap();
+            assert_eq!(buf[..], data[addr..addr + buf.len()]);
+        }
+        assert_eq!(reader.file.get_ref().seeks, 1);
+
+        // Random accesses still read the right data
+        for addr in [0x200, 0xF000, 0x7F80] {
+            reader.read_at(addr as u64, &mut buf).unwrap();
+            assert_eq!(buf[..], data[addr..addr + buf.len()]);
+        }
+    }
+
+    fn store_rom(rom: &mut DsSlotRom) -> Vec<u8> {
+        let mut contents = Vec::new();
+        rom.store_savestate(&mut PersistentWriteSavestate::new(&mut contents))
+            .unwrap();
+        contents
+    }
+
+    fn load_rom(rom: &mut DsSlotRom, contents: &[u8]) -> Result<(), ()> {
+        PersistentReadSavestate::new(contents)
+            .and_then(|mut save| rom.load_savestate(&mut save).map_err(drop))
+    }
+
+    #[test]
+    fn savestate_round_trip() {
+        let path = temp_path("savestate.nds");
+        let mut data = test_rom(0x8000);
+        data.write_le::<u32>(0x20, 0x4000);
+        fs::write(&path, &data).unwrap();
+        let new_rom = |in_memory_max_size| {
+            DsSlotRom::new(&path, in_memory_max_size, Model::Ds)
+                .ok()
+                .unwrap()
+        };
+
+        for (in_memory_max_size, is_file) in [(0, true), (u32::MAX, false)] {
+            let mut rom = new_rom(in_memory_max_size);
+            assert_eq!(matches!(rom, DsSlotRom::File(_)), is_file);
+            rom.secure_area_mut()
+                .unwrap()
+                .write_le::<u64>(0, key1::DECRYPTED_SECURE_AREA_ID);
+            let contents = store_rom(&mut rom);
+
+            // Savestates can be loaded regardless of the variant they were created with
+            for mut loaded in [new_rom(0), new_rom(u32::MAX)] {
+                load_rom(&mut loaded, &contents).unwrap();
+                let mut output = [0; 0x10];
+                loaded.read_slice(0x3FF8, &mut output);
+                assert_eq!(output[..8], data[0x3FF8..0x4000]);
+                assert_eq!(output.read_le::<u64>(8), key1::DECRYPTED_SECURE_AREA_ID);
+                loaded.read_slice(0x4800, &mut output);
+                assert_eq!(output[..], data[0x4800..0x4810]);
+            }
+        }
+
+        // Savestates for other games are rejected
+        let contents = store_rom(&mut new_rom(0));
+        data[0xC..0x10].copy_from_slice(b"ABCJ");
+        fs::write(&path, &data).unwrap();
+        let mut other = new_rom(0);
+        fs::remove_file(&path).unwrap();
+        assert!(load_rom(&mut other, &contents).is_err());
+    }
+
+    #[test]
+    fn padded_reads() {
+        let path = temp_path("padded.nds");
+        let data = test_rom(0x3000);
+        fs::write(&path, &data).unwrap();
+        let (files, len) = open_parts(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut file = SplitReader::new(files);
+
+        // Fully in-bounds reads return the data as-is, including one ending right at the end
+        for addr in [0, 0x1FF8, 0x2FF0] {
+            let mut output = [0xAA; 0x10];
+            read_padded(&mut file, len as usize, addr, &mut output);
+            assert_eq!(output[..], data[addr..addr + 0x10]);
+        }
+
+        // Reads past the end of the data are padded with zeros, whether they straddle it or not
+        let mut output = [0xAA; 0x10];
+        read_padded(&mut file, len as usize, 0x2FF8, &mut output);
+        assert_eq!(output[..8], data[0x2FF8..]);
+        assert_eq!(output[8..], [0; 8]);
+        let mut output = [0xAA; 0x10];
+        read_padded(&mut file, len as usize, 0x3800, &mut output);
+        assert_eq!(output, [0; 0x10]);
+    }
 }
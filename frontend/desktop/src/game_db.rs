@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::{fs, io, path::Path};
+use std::{fmt, fs, io, path::Path};
 
 #[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -61,6 +61,24 @@ impl SaveType {
     }
 }
 
+impl fmt::Display for SaveType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SaveType::None => "none",
+            SaveType::Eeprom4k => "4 Kib EEPROM",
+            SaveType::EepromFram64k => "64 Kib EEPROM/FRAM",
+            SaveType::EepromFram512k => "512 Kib EEPROM/FRAM",
+            SaveType::EepromFram1m => "1 Mib EEPROM/FRAM",
+            SaveType::Flash2m => "2 Mib flash",
+            SaveType::Flash4m => "4 Mib flash",
+            SaveType::Flash8m => "8 Mib flash",
+            SaveType::Nand64m => "64 Mib NAND",
+            SaveType::Nand128m => "128 Mib NAND",
+            SaveType::Nand256m => "256 Mib NAND",
+        })
+    }
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Entry {
@@ -19,17 +19,16 @@ use crate::debug_views;
 use crate::{
     audio,
     config::{self, Launch, Renderer2dKind, Renderer3dKind},
-    emu::{
-        self,
-        ds_slot_rom::{self, DsSlotRom},
-    },
+    emu::{self, ds_slot_rom},
     game_db, input,
     utils::{base_dirs, Lazy},
     FrameData,
 };
 use dust_core::{
-    ds_slot::rom::Contents,
+    cpu::arm7,
+    ds_slot::rom::{self, header::UnitCode, Contents},
     gpu::{engine_2d, engine_3d, Framebuffer, SCREEN_HEIGHT, SCREEN_WIDTH},
+    Model,
 };
 use emu_utils::triple_buffer;
 #[cfg(feature = "logging")]
@@ -71,10 +70,104 @@ enum Renderer3dData {
     Wgpu(dust_wgpu_3d::threaded::FrontendChannels),
 }
 
+/// Details about the loaded game shown in the "Game info" menu, gathered before the ROM is handed
+/// over to the emulation thread.
+struct GameInfo {
+    title: Option<String>,
+    game_code: u32,
+    version: u8,
+    addressable_len: usize,
+    actual_len: usize,
+    memory_footprint: usize,
+    detected_save: Option<game_db::SaveType>,
+}
+
+impl GameInfo {
+    fn new(cart: &mut emu::Cart) -> Self {
+        let ds_slot_rom = &mut cart.ds_slot.rom;
+        GameInfo {
+            title: ds_slot_rom.game_title().filter(|title| !title.is_empty()),
+            game_code: ds_slot_rom.game_code(),
+            version: ds_slot_rom.rom_version(),
+            addressable_len: ds_slot_rom.addressable_len(),
+            actual_len: ds_slot_rom.actual_len(),
+            memory_footprint: ds_slot_rom.memory_footprint(),
+            detected_save: cart.detected_save(),
+        }
+    }
+
+    fn draw(&self, ui: &imgui::Ui) {
+        if let Some(title) = &self.title {
+            ui.text(format!("Title: {title}"));
+        }
+        ui.text(format!(
+            "Game code: {}",
+            String::from_utf8_lossy(&self.game_code.to_le_bytes())
+        ));
+        ui.text(format!("Version: {}", self.version));
+        if self.actual_len < self.addressable_len {
+            ui.text(format!(
+                "Size: {} (trimmed to {})",
+                format_size(self.addressable_len),
+                format_size(self.actual_len)
+            ));
+        } else {
+            ui.text(format!("Size: {}", format_size(self.addressable_len)));
+        }
+        ui.text(format!(
+            "ROM data in memory: {}",
+            format_size(self.memory_footprint)
+        ));
+        if let Some(save_type) = self.detected_save {
+            ui.text(format!("Save type (from the game database): {save_type}"));
+        } else {
+            ui.text("Not found in the game database");
+        }
+    }
+}
+
+fn show_rom_creation_error(err: ds_slot_rom::CreationError, model: Model) {
+    use ds_slot_rom::{CreationError, CreationErrorKind};
+    let title = match err.kind() {
+        CreationErrorKind::InvalidFileSize => "Invalid ROM file",
+        CreationErrorKind::Io => "Couldn't load ROM file",
+        CreationErrorKind::InvalidBiosSize => "Invalid ARM7 BIOS file",
+        CreationErrorKind::BiosIo => "Couldn't load ARM7 BIOS",
+    };
+    let description = match err {
+        CreationError::InvalidFileSize(got) => {
+            let bounds = rom::size_bounds(model);
+            format!(
+                "Invalid ROM file size: {got} B (expected between {} and {} B)",
+                bounds.start(),
+                bounds.end()
+            )
+        }
+        CreationError::Io(err) => format!("Couldn't load the specified ROM file: {err}"),
+        CreationError::InvalidBiosSize(got) => format!(
+            "Invalid ARM7 BIOS file size: {got} B (expected {} B)",
+            arm7::BIOS_SIZE
+        ),
+        CreationError::BiosIo(err) => {
+            format!("Couldn't load the specified ARM7 BIOS file: {err}")
+        }
+    };
+    error!(title, "{description}");
+}
+
+fn format_size(len: usize) -> String {
+    if len >= 1 << 20 {
+        format!("{:.1} MiB", len as f64 / (1 << 20) as f64)
+    } else {
+        format!("{:.1} KiB", len as f64 / (1 << 10) as f64)
+    }
+}
+
 struct EmuState {
     playing: bool,
     title: String,
     game_loaded: bool,
+    game_info: Option<GameInfo>,
     save_path_update: Option<emu::SavePathUpdate>,
     #[cfg(feature = "gdb-server")]
     gdb_server_addr: Option<SocketAddr>,
@@ -245,6 +338,41 @@ impl UiState {
 }
 
 impl UiState {
+    fn load_game_db(&mut self, config: &Config) -> Option<&game_db::Database> {
+        self.game_db
+            .get(|| {
+                config!(config.config, game_db_path)
+                    .as_ref()
+                    .and_then(|path| match game_db::Database::read_from_file(&path.0) {
+                        Ok(db) => Some(db),
+                        Err(err) => {
+                            let location_str = location_str!(&path.0);
+                            match err {
+                                game_db::Error::Io(err) => {
+                                    if err.kind() == io::ErrorKind::NotFound {
+                                        warning!(
+                                            "Missing game database",
+                                            "The game database was not found{location_str}.",
+                                        );
+                                    } else {
+                                        config_error!(
+                                            "Couldn't read game database{location_str}: {err}",
+                                        );
+                                    }
+                                }
+                                game_db::Error::Json(err) => {
+                                    config_error!(
+                                        "Couldn't load game database{location_str}: {err}",
+                                    );
+                                }
+                            }
+                            None
+                        }
+                    })
+            })
+            .as_ref()
+    }
+
     fn load_from_rom_path(&mut self, path: &Path, config: &mut Config, window: &window::Window) {
         let Some(game_title) = path.file_stem().and_then(|path| path.to_str()) else {
             error!("Invalid ROM path", "Invalid ROM path provided: {path:?}");
@@ -269,35 +397,140 @@ impl UiState {
                     config_warning!("{}", format_list!(warnings));
                 }
 
-                let ds_slot_rom = match DsSlotRom::new(
+                // Only the header is read at first, to catch ROMs that can't boot with the chosen
+                // configuration before loading them (and creating their save file)
+                let rom_probe = match ds_slot_rom::probe(path, launch_config.model) {
+                    Ok(rom_probe) => rom_probe,
+                    Err(err) => {
+                        config.config.unset_game();
+                        show_rom_creation_error(err, launch_config.model);
+                        return;
+                    }
+                };
+                if !rom_probe.valid_size {
+                    config.config.unset_game();
+                    show_rom_creation_error(
+                        ds_slot_rom::CreationError::InvalidFileSize(rom_probe.len),
+                        launch_config.model,
+                    );
+                    return;
+                }
+                if rom_probe.secure_area_encrypted
+                    && launch_config.skip_firmware
+                    && launch_config.sys_files.arm7_bios.is_none()
+                {
+                    config.config.unset_game();
+                    error!(
+                        "Missing ARM7 BIOS",
+                        "The ROM's secure area is encrypted, and direct boot needs an ARM7 BIOS \
+                         to decrypt it; please provide one."
+                    );
+                    return;
+                }
+                if rom_probe.secure_area_encrypted && launch_config.sys_files.arm7_bios.is_some() {
+                    // Only the header and secure area are read, from a separate file-backed copy
+                    // of the ROM
+                    let arm7_bios_path = config.config.sys_paths.get().arm7_bios.clone();
+                    let bios_matches_cart =
+                        ds_slot_rom::DsSlotRom::new(path, 0, launch_config.model)
+                            .and_then(|rom| {
+                                rom.into_normal(
+                                    arm7_bios_path.as_ref().map(|path| path.0.as_path()),
+                                    launch_config.model,
+                                    #[cfg(feature = "log")]
+                                    self.log.logger().new(slog::o!("ds_rom" => "normal")),
+                                )
+                            })
+                            .map(|mut rom| rom.arm7_bios_matches_cart());
+                    match bios_matches_cart {
+                        Ok(Some(false)) => {
+                            warning!(
+                                "Invalid ARM7 BIOS",
+                                "The game's secure area couldn't be decrypted using the provided \
+                                 ARM7 BIOS, which might be patched or not a DS one; the game will \
+                                 most likely fail to boot."
+                            );
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            config.config.unset_game();
+                            show_rom_creation_error(err, launch_config.model);
+                            return;
+                        }
+                    }
+                }
+                if rom_probe.unit_code == Ok(UnitCode::Dsi) && launch_config.model != Model::Dsi {
+                    warning!(
+                        "DSi-exclusive ROM",
+                        "The ROM ({}) is DSi-exclusive, and will most likely not boot on the \
+                         selected DS model.",
+                        String::from_utf8_lossy(&rom_probe.game_code.to_le_bytes())
+                    );
+                }
+
+                let save_path = config.config.save_path(game_title);
+                let in_memory_max_size = config!(config.config, ds_slot_rom_in_memory_max_size);
+                let mut cart = match emu::load_cart(
                     path,
-                    config!(config.config, ds_slot_rom_in_memory_max_size),
+                    save_path.as_deref(),
                     launch_config.model,
+                    in_memory_max_size,
+                    self.load_game_db(config),
                 ) {
-                    Ok(ds_slot_rom) => ds_slot_rom,
+                    Ok(cart) => cart,
                     Err(err) => {
                         config.config.unset_game();
                         match err {
-                            ds_slot_rom::CreationError::Io(err) => {
-                                error!(
-                                    "Couldn't load ROM file",
-                                    "Couldn't load the specified ROM file: {err}"
-                                );
+                            emu::LoadCartError::Rom(err) => {
+                                show_rom_creation_error(err, launch_config.model);
                             }
-                            ds_slot_rom::CreationError::InvalidFileSize(got) => {
-                                error!("Invalid ROM file", "Invalid ROM file size: {got} B");
+                            emu::LoadCartError::Save(err) => {
+                                error!("Save file error", "Couldn't create save file: {err}");
                             }
                         }
                         return;
                     }
                 };
+                let ds_slot_rom = &mut cart.ds_slot.rom;
+
+                let game_code = ds_slot_rom.game_code();
+                if let Some(entry) = self
+                    .load_game_db(config)
+                    .and_then(|db| db.lookup(game_code))
+                {
+                    if entry.rom_size as usize != ds_slot_rom.len() {
+                        warning!(
+                            "Unexpected ROM size",
+                            "Unexpected ROM size: expected {} B, got {} B",
+                            entry.rom_size,
+                            ds_slot_rom.len()
+                        );
+                    }
+                }
+
+                // Homebrew often leaves the capacity byte unset, only check retail carts
+                let capacity_mismatch = (!rom::is_homebrew(ds_slot_rom))
+                    .then(|| ds_slot_rom.capacity_mismatch())
+                    .flatten();
+                if let Some(reported_len) = capacity_mismatch {
+                    warning!(
+                        "ROM capacity mismatch",
+                        "The ROM header reports a {reported_len} B cart, but the ROM file is \
+                         addressed as {} B; the dump might be bad.",
+                        ds_slot_rom.addressable_len()
+                    );
+                }
 
+                // Load the secure area right away for file-backed ROMs instead of during boot, so
+                // that the memory footprint accounts for it
+                ds_slot_rom.prefetch_secure_area();
+                let game_info = GameInfo::new(&mut cart);
                 self.start(
                     config,
                     launch_config,
-                    config.config.save_path(game_title),
+                    cart.save_path,
                     game_title.to_string(),
-                    Some((ds_slot_rom, path)),
+                    Some((cart.ds_slot, game_info, path)),
                     window,
                 );
                 config.game_path = game_config.path;
@@ -460,7 +693,7 @@ impl UiState {
         launch_config: Launch,
         save_path: Option<PathBuf>,
         title: String,
-        ds_slot_rom: Option<(DsSlotRom, &Path)>,
+        ds_slot: Option<(emu::DsSlot, GameInfo, &Path)>,
         window: &window::Window,
     ) {
         #[cfg(feature = "discord-presence")]
@@ -469,7 +702,7 @@ impl UiState {
         }
 
         let playing = !config!(config.config, pause_on_launch);
-        let game_loaded = ds_slot_rom.is_some();
+        let game_loaded = ds_slot.is_some();
 
         self.savestate_editor.update_game(
             window,
@@ -480,71 +713,18 @@ impl UiState {
         #[cfg(feature = "logging")]
         let logger = self.log.logger().clone();
 
-        let (mut ds_slot_rom, ds_slot_rom_path) = ds_slot_rom.unzip();
+        let (mut ds_slot, game_info, ds_slot_rom_path) = match ds_slot {
+            Some((ds_slot, game_info, path)) => (Some(ds_slot), Some(game_info), Some(path)),
+            None => (None, None, None),
+        };
 
         self.title_menu_bar.start_game(
-            ds_slot_rom.as_mut(),
+            ds_slot.as_mut().map(|ds_slot| &mut ds_slot.rom),
             ds_slot_rom_path,
             &config.config,
             window,
         );
 
-        #[allow(unused_mut, clippy::bind_instead_of_map)]
-        let ds_slot = ds_slot_rom.and_then(|mut rom| {
-            let game_code = rom.game_code();
-
-            let save_type = self
-                .game_db
-                .get(|| {
-                    config!(config.config, game_db_path)
-                        .as_ref()
-                        .and_then(|path| match game_db::Database::read_from_file(&path.0) {
-                            Ok(db) => Some(db),
-                            Err(err) => {
-                                let location_str = location_str!(&path.0);
-                                match err {
-                                    game_db::Error::Io(err) => {
-                                        if err.kind() == io::ErrorKind::NotFound {
-                                            warning!(
-                                                "Missing game database",
-                                                "The game database was not found{location_str}.",
-                                            );
-                                        } else {
-                                            config_error!(
-                                                "Couldn't read game database{location_str}: {err}",
-                                            );
-                                        }
-                                    }
-                                    game_db::Error::Json(err) => {
-                                        config_error!(
-                                            "Couldn't load game database{location_str}: {err}",
-                                        );
-                                    }
-                                }
-                                None
-                            }
-                        })
-                })
-                .as_ref()
-                .and_then(|db| db.lookup(game_code))
-                .map(|entry| {
-                    if entry.rom_size as usize != rom.len() {
-                        warning!(
-                            "Unexpected ROM size",
-                            "Unexpected ROM size: expected {} B, got {} B",
-                            entry.rom_size,
-                            rom.len()
-                        );
-                    }
-                    entry.save_type
-                });
-            Some(emu::DsSlot {
-                rom,
-                save_type,
-                has_ir: game_code as u8 == b'I',
-            })
-        });
-
         let frame_tx = self
             .frame_tx
             .take()
@@ -642,6 +822,7 @@ impl UiState {
             playing,
             title,
             game_loaded,
+            game_info,
             save_path_update: None,
             #[cfg(feature = "gdb-server")]
             gdb_server_addr: None,
@@ -1311,6 +1492,36 @@ pub fn main() {
                             state.load_firmware(config, window);
                         }
 
+                        if let Some(game_info) =
+                            state.emu.as_ref().and_then(|emu| emu.game_info.as_ref())
+                        {
+                            ui.menu("\u{f05a} Game info", || game_info.draw(ui));
+                        }
+
+                        if ui
+                            .menu_item_config("Replay ROM command log...")
+                            .enabled(state.emu.is_some())
+                            .build()
+                        {
+                            if let Some(emu) = &state.emu {
+                                if let Some(log_path) = FileDialog::new()
+                                    .add_filter("ROM command log", &["txt", "log"])
+                                    .pick_file()
+                                {
+                                    if let Some(output_path) = FileDialog::new()
+                                        .add_filter("ROM command log", &["txt", "log"])
+                                        .set_file_name("responses.txt")
+                                        .save_file()
+                                    {
+                                        emu.send_message(emu::Message::ReplayRomCommandLog {
+                                            log_path,
+                                            output_path,
+                                        });
+                                    }
+                                }
+                            }
+                        }
+
                         ui.separator();
 
                         state
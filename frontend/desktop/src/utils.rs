@@ -1,6 +1,9 @@
+use dust_core::utils::BoxedByteSlice;
 use std::{
     borrow::Cow,
     fmt,
+    fs::File,
+    io::{self, Read, Seek},
     path::{Path, PathBuf},
     str,
     sync::LazyLock,
@@ -86,6 +89,31 @@ pub fn base_dirs<'a>() -> &'a BaseDirs {
     &BASE_DIRS
 }
 
+/// Reads up to `len` bytes from `reader` into a new [`BoxedByteSlice`] of length `len`, leaving
+/// the tail zeroed if the reader runs out of data early.
+pub fn boxed_byte_slice_from_reader(
+    reader: &mut impl Read,
+    len: usize,
+) -> io::Result<BoxedByteSlice> {
+    let mut buf = BoxedByteSlice::new_zeroed(len);
+    let mut read_len = 0;
+    while read_len < len {
+        match reader.read(&mut buf[read_len..]) {
+            Ok(0) => break,
+            Ok(chunk_len) => read_len += chunk_len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(buf)
+}
+
+/// Reads up to `len` bytes from the start of `file`, see [`boxed_byte_slice_from_reader`].
+pub fn boxed_byte_slice_from_file(file: &mut File, len: usize) -> io::Result<BoxedByteSlice> {
+    file.rewind()?;
+    boxed_byte_slice_from_reader(file, len)
+}
+
 pub struct Lazy<T> {
     value: Option<T>,
 }
@@ -229,3 +257,31 @@ pub mod double_option {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Write};
+
+    #[test]
+    fn short_reads_are_zero_padded() {
+        let bytes = boxed_byte_slice_from_reader(&mut &[1, 2, 3][..], 8).unwrap();
+        assert_eq!(&bytes[..], &[1, 2, 3, 0, 0, 0, 0, 0]);
+
+        let path = std::env::temp_dir().join(format!("dust-utils-{}-short", std::process::id()));
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&[0xFF; 0x30]).unwrap();
+        // The file's cursor is at its end, so this also checks that it's rewound first
+        let bytes = boxed_byte_slice_from_file(&mut file, 0x40);
+        fs::remove_file(&path).unwrap();
+        let bytes = bytes.unwrap();
+        assert!(bytes[..0x30].iter().all(|&byte| byte == 0xFF));
+        assert!(bytes[0x30..].iter().all(|&byte| byte == 0));
+    }
+}
@@ -111,9 +111,17 @@ fn build_emu<E: cpu::Engine>(emu_builder: emu::Builder, engine: E) -> emu::Emu<E
                     unreachable!("Invalid DS slot ROM file size")
                 }
             },
-            emu::BuildError::RomNeedsDecryptionButNoBiosProvided => {
-                panic!("Couldn't start emulator: ROM needs decryption but no BIOS provided.");
-            }
+            emu::BuildError::RomSetup(err) => match err {
+                ds_slot::rom::SetupError::MissingArm7Bios => {
+                    panic!(
+                        "Couldn't start emulator: ROM needs decryption or encryption but no BIOS \
+                         provided."
+                    );
+                }
+                ds_slot::rom::SetupError::InvalidSecureArea => {
+                    panic!("Couldn't start emulator: the ROM's secure area is truncated.");
+                }
+            },
         },
     }
 }
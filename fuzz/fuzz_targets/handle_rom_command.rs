@@ -0,0 +1,80 @@
+//! Feeds sequences of ROM commands to a [`Normal`] ROM, checking that no command panics, reads
+//! more than a single transfer's worth of data from the contents or writes past the requested
+//! output length.
+//!
+//! Input layout: the starting stage (modulo 3), the transfer length (as ROMCTRL's data block size
+//! field, modulo 8) and then any number of 8-byte commands.
+
+#![no_main]
+
+use dust_core::{
+    cpu::arm7,
+    ds_slot::{
+        rom::{
+            limited::{LimitAction, LimitedContents},
+            normal::{Normal, Stage},
+            Rom,
+        },
+        RomOutputLen,
+    },
+    utils::{mem_prelude::*, BoxedByteSlice, Bytes},
+    Model,
+};
+use libfuzzer_sys::fuzz_target;
+
+const ROM_LEN: usize = 0x2_0000;
+const SENTINEL: u8 = 0xA5;
+
+fn rom_contents() -> BoxedByteSlice {
+    let mut contents = BoxedByteSlice::new_zeroed(ROM_LEN);
+    for (i, byte) in contents.iter_mut().enumerate() {
+        *byte = (i ^ i >> 8 ^ i >> 16) as u8;
+    }
+    contents[..0xC].copy_from_slice(b"FUZZ TARGET\0");
+    contents[0xC..0x10].copy_from_slice(b"AFZE");
+    // A retail-like layout, with the ARM9 binary (and so the secure area) at 0x4000
+    contents.write_le(0x20, 0x4000_u32);
+    contents.write_le(0x80, ROM_LEN as u32);
+    contents
+}
+
+// The KEY1 tables are derived from a synthetic BIOS, as the commands are random anyway
+fn arm7_bios() -> Box<Bytes<{ arm7::BIOS_SIZE }>> {
+    let mut bios = Box::new(Bytes::new([0; arm7::BIOS_SIZE]));
+    for (i, byte) in bios.iter_mut().enumerate() {
+        *byte = (i as u32).wrapping_mul(0x9E37_79B9).to_le_bytes()[i & 3];
+    }
+    bios
+}
+
+fuzz_target!(|data: &[u8]| {
+    let [stage, block_size_shift, ref cmds @ ..] = *data else {
+        return;
+    };
+    let stage = match stage % 3 {
+        0 => Stage::Initial,
+        1 => Stage::Key1,
+        _ => Stage::Key2,
+    };
+    let output_len = RomOutputLen::from_block_size_shift(block_size_shift & 7);
+
+    let bios = arm7_bios();
+    let contents = LimitedContents::new(rom_contents(), 0x4000, LimitAction::Panic);
+    let mut normal = Normal::new(Box::new(contents), Some(&bios), Model::Ds)
+        .expect("ROM should have a valid size");
+    normal.set_stage(stage);
+    let mut rom = Rom::Normal(normal);
+
+    let mut output = Box::new(Bytes::new([0; 0x4000]));
+    for cmd in cmds.chunks_exact(8) {
+        output.fill(SENTINEL);
+        let cmd = Bytes::new(<[u8; 8]>::try_from(cmd).unwrap());
+        let raw_cmd = cmd.read_be::<u64>(0);
+        rom.handle_rom_command(cmd, &mut output, output_len);
+        let len = output_len.get() as usize;
+        assert!(
+            output[len..].iter().all(|&byte| byte == SENTINEL),
+            "ROM command {raw_cmd:016X} wrote past the output length ({len:#X})"
+        );
+    }
+});